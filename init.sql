@@ -1,16 +1,40 @@
+-- Trigram matching for the user directory
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
 -- Create users table
 CREATE TABLE IF NOT EXISTS users (
     id SERIAL PRIMARY KEY,
     email VARCHAR(255) UNIQUE NOT NULL,
     password_hash VARCHAR(255) NOT NULL,
     username VARCHAR(100) NOT NULL,
-    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    tokens_valid_after TIMESTAMP,
+    email_verified_at TIMESTAMP,
+    is_bot BOOLEAN NOT NULL DEFAULT FALSE,
+    bot_owner_id INTEGER REFERENCES users(id) ON DELETE CASCADE,
+    display_name VARCHAR(100),
+    bio TEXT,
+    status_text VARCHAR(140),
+    timezone VARCHAR(64),
+    avatar_updated_at TIMESTAMP,
+    hidden_from_directory BOOLEAN NOT NULL DEFAULT FALSE,
+    email_visible BOOLEAN NOT NULL DEFAULT FALSE,
+    deleted_at TIMESTAMP,
+    role VARCHAR(16) NOT NULL DEFAULT 'user' CHECK (role IN ('user', 'admin')),
+    disabled_at TIMESTAMP,
+    password_reset_required BOOLEAN NOT NULL DEFAULT FALSE
 );
 
 -- Create rooms table
 CREATE TABLE IF NOT EXISTS rooms (
     id SERIAL PRIMARY KEY,
     name VARCHAR(255) NOT NULL,
+    visibility VARCHAR(16) NOT NULL DEFAULT 'public' CHECK (visibility IN ('public', 'private')),
+    kind VARCHAR(16) NOT NULL DEFAULT 'channel' CHECK (kind IN ('channel', 'dm', 'group_dm')),
+    topic VARCHAR(250),
+    description TEXT,
+    icon_updated_at TIMESTAMP,
+    settings JSONB NOT NULL DEFAULT '{}',
     created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
 );
 
@@ -20,6 +44,8 @@ CREATE TABLE IF NOT EXISTS messages (
     sender_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
     room_id INTEGER NOT NULL REFERENCES rooms(id) ON DELETE CASCADE,
     content TEXT NOT NULL,
+    kind VARCHAR(16) NOT NULL DEFAULT 'user' CHECK (kind IN ('user', 'system')),
+    expires_at TIMESTAMP,
     created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
 );
 
@@ -28,16 +54,316 @@ CREATE TABLE IF NOT EXISTS room_members (
     id SERIAL PRIMARY KEY,
     room_id INTEGER NOT NULL REFERENCES rooms(id) ON DELETE CASCADE,
     user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    role VARCHAR(16) NOT NULL DEFAULT 'member'
+        CHECK (role IN ('owner', 'admin', 'moderator', 'member', 'read-only')),
     joined_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
     UNIQUE(room_id, user_id)
 );
 
+-- Create user_blocks table (per-user block and mute lists)
+CREATE TABLE IF NOT EXISTS user_blocks (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    target_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    kind VARCHAR(10) NOT NULL CHECK (kind IN ('block', 'mute')),
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE(user_id, target_id)
+);
+
+-- Create refresh_tokens table (only SHA-256 hashes of tokens are stored)
+CREATE TABLE IF NOT EXISTS refresh_tokens (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    family_id UUID NOT NULL,
+    token_hash VARCHAR(64) UNIQUE NOT NULL,
+    expires_at TIMESTAMP NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    revoked_at TIMESTAMP,
+    replaced_by_id INTEGER REFERENCES refresh_tokens(id) ON DELETE SET NULL
+);
+
+-- Create revoked_tokens table (access token denylist, Postgres fallback for Redis)
+CREATE TABLE IF NOT EXISTS revoked_tokens (
+    jti UUID PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    expires_at TIMESTAMP NOT NULL,
+    revoked_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Create user_tokens table (single-use tokens sent by email; only SHA-256 hashes are stored)
+CREATE TABLE IF NOT EXISTS user_tokens (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    purpose VARCHAR(32) NOT NULL,
+    token_hash VARCHAR(64) UNIQUE NOT NULL,
+    nonce_hash VARCHAR(64),
+    expires_at TIMESTAMP NOT NULL,
+    used_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Create user_totp table (TOTP second factor; enabled once confirmed)
+CREATE TABLE IF NOT EXISTS user_totp (
+    user_id INTEGER PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    secret VARCHAR(64) NOT NULL,
+    enabled_at TIMESTAMP,
+    last_used_step BIGINT,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Create recovery_codes table (single-use 2FA backup codes; only SHA-256 hashes are stored)
+CREATE TABLE IF NOT EXISTS recovery_codes (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    code_hash VARCHAR(64) NOT NULL,
+    used_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Create user_identities table (links to accounts at OpenID Connect providers)
+CREATE TABLE IF NOT EXISTS user_identities (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    provider VARCHAR(64) NOT NULL,
+    subject VARCHAR(255) NOT NULL,
+    email VARCHAR(255),
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE(provider, subject)
+);
+
+-- Create webauthn_credentials table (passkeys; public keys are COSE-encoded)
+CREATE TABLE IF NOT EXISTS webauthn_credentials (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    credential_id VARCHAR(1366) UNIQUE NOT NULL,
+    public_key BYTEA NOT NULL,
+    sign_count BIGINT NOT NULL DEFAULT 0,
+    aaguid UUID NOT NULL,
+    name VARCHAR(100) NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_used_at TIMESTAMP
+);
+
+-- Create api_keys table (scoped keys for scripts and bots; only SHA-256 hashes are stored)
+CREATE TABLE IF NOT EXISTS api_keys (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name VARCHAR(100) NOT NULL,
+    prefix VARCHAR(16) NOT NULL,
+    key_hash VARCHAR(64) UNIQUE NOT NULL,
+    scopes VARCHAR(255) NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_used_at TIMESTAMP
+);
+
+-- Create sessions table (one row per login; the id is the refresh token family and `sid` claim)
+CREATE TABLE IF NOT EXISTS sessions (
+    id UUID PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    device_label VARCHAR(100) NOT NULL,
+    user_agent VARCHAR(512),
+    ip_address VARCHAR(45),
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_used_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Create login_lockouts table (audit log of accounts and IPs locked out after failed logins)
+CREATE TABLE IF NOT EXISTS login_lockouts (
+    id SERIAL PRIMARY KEY,
+    scope VARCHAR(16) NOT NULL,
+    subject VARCHAR(255) NOT NULL,
+    user_id INTEGER REFERENCES users(id) ON DELETE CASCADE,
+    ip_address VARCHAR(45),
+    failures INTEGER NOT NULL,
+    locked_until TIMESTAMP NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Create user_avatars table (resized profile pictures, kept out of the users rows)
+CREATE TABLE IF NOT EXISTS user_avatars (
+    user_id INTEGER PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    content_type VARCHAR(32) NOT NULL,
+    data BYTEA NOT NULL,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Create account_jobs table (personal data exports and account deletions, run in the background)
+CREATE TABLE IF NOT EXISTS account_jobs (
+    id UUID PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    kind VARCHAR(16) NOT NULL CHECK (kind IN ('export', 'deletion')),
+    status VARCHAR(16) NOT NULL CHECK (status IN ('pending', 'running', 'completed', 'failed')),
+    error TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    started_at TIMESTAMP,
+    finished_at TIMESTAMP
+);
+
+-- Create data_exports table (archives of finished exports, deleted once they expire)
+CREATE TABLE IF NOT EXISTS data_exports (
+    job_id UUID PRIMARY KEY REFERENCES account_jobs(id) ON DELETE CASCADE,
+    data BYTEA NOT NULL,
+    expires_at TIMESTAMP NOT NULL
+);
+
+-- Per-room changes to the default permissions of a role
+CREATE TABLE IF NOT EXISTS room_role_permissions (
+    id SERIAL PRIMARY KEY,
+    room_id INTEGER NOT NULL REFERENCES rooms(id) ON DELETE CASCADE,
+    role VARCHAR(16) NOT NULL,
+    permission VARCHAR(32) NOT NULL,
+    allowed BOOLEAN NOT NULL,
+    UNIQUE(room_id, role, permission)
+);
+
+CREATE TABLE IF NOT EXISTS room_bans (
+    id SERIAL PRIMARY KEY,
+    room_id INTEGER NOT NULL REFERENCES rooms(id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    banned_by INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    reason TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE(room_id, user_id)
+);
+
+-- Invite links; only a SHA-256 hash of each code is stored
+CREATE TABLE IF NOT EXISTS room_invites (
+    id SERIAL PRIMARY KEY,
+    room_id INTEGER NOT NULL REFERENCES rooms(id) ON DELETE CASCADE,
+    created_by INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    prefix VARCHAR(16) NOT NULL,
+    code_hash VARCHAR(64) NOT NULL UNIQUE,
+    role VARCHAR(16) NOT NULL DEFAULT 'member',
+    max_uses INTEGER CHECK (max_uses > 0),
+    uses INTEGER NOT NULL DEFAULT 0,
+    expires_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Pending invitations of specific users; answered ones are deleted
+CREATE TABLE IF NOT EXISTS room_invitations (
+    id SERIAL PRIMARY KEY,
+    room_id INTEGER NOT NULL REFERENCES rooms(id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    invited_by INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE(room_id, user_id)
+);
+
+-- Pending requests to join private rooms; decided ones are deleted
+CREATE TABLE IF NOT EXISTS room_join_requests (
+    id SERIAL PRIMARY KEY,
+    room_id INTEGER NOT NULL REFERENCES rooms(id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    message TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE(room_id, user_id)
+);
+
+-- Create room_icons table (resized room pictures, kept out of the rooms rows)
+CREATE TABLE IF NOT EXISTS room_icons (
+    room_id INTEGER PRIMARY KEY REFERENCES rooms(id) ON DELETE CASCADE,
+    content_type VARCHAR(32) NOT NULL,
+    data BYTEA NOT NULL,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Create room_post_times table (last post of each user in slow mode rooms)
+CREATE TABLE IF NOT EXISTS room_post_times (
+    room_id INTEGER NOT NULL REFERENCES rooms(id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    posted_at TIMESTAMP NOT NULL,
+    PRIMARY KEY (room_id, user_id)
+);
+
+-- Bring databases created from an older version of this file up to date. CREATE TABLE IF NOT
+-- EXISTS leaves existing tables alone, so columns added since then are added here.
+ALTER TABLE users ADD COLUMN IF NOT EXISTS tokens_valid_after TIMESTAMP;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS email_verified_at TIMESTAMP;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS is_bot BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS bot_owner_id INTEGER REFERENCES users(id) ON DELETE CASCADE;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS display_name VARCHAR(100);
+ALTER TABLE users ADD COLUMN IF NOT EXISTS bio TEXT;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS status_text VARCHAR(140);
+ALTER TABLE users ADD COLUMN IF NOT EXISTS timezone VARCHAR(64);
+ALTER TABLE users ADD COLUMN IF NOT EXISTS avatar_updated_at TIMESTAMP;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS hidden_from_directory BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS email_visible BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMP;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS role VARCHAR(16) NOT NULL DEFAULT 'user'
+    CHECK (role IN ('user', 'admin'));
+ALTER TABLE users ADD COLUMN IF NOT EXISTS disabled_at TIMESTAMP;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS password_reset_required BOOLEAN NOT NULL DEFAULT FALSE;
+
+ALTER TABLE rooms ADD COLUMN IF NOT EXISTS visibility VARCHAR(16) NOT NULL DEFAULT 'public'
+    CHECK (visibility IN ('public', 'private'));
+ALTER TABLE rooms ADD COLUMN IF NOT EXISTS kind VARCHAR(16) NOT NULL DEFAULT 'channel'
+    CHECK (kind IN ('channel', 'dm', 'group_dm'));
+ALTER TABLE rooms ADD COLUMN IF NOT EXISTS topic VARCHAR(250);
+ALTER TABLE rooms ADD COLUMN IF NOT EXISTS description TEXT;
+ALTER TABLE rooms ADD COLUMN IF NOT EXISTS icon_updated_at TIMESTAMP;
+ALTER TABLE rooms ADD COLUMN IF NOT EXISTS settings JSONB NOT NULL DEFAULT '{}';
+
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS kind VARCHAR(16) NOT NULL DEFAULT 'user'
+    CHECK (kind IN ('user', 'system'));
+ALTER TABLE messages ADD COLUMN IF NOT EXISTS expires_at TIMESTAMP;
+
+ALTER TABLE room_members ADD COLUMN IF NOT EXISTS role VARCHAR(16) NOT NULL DEFAULT 'member'
+    CHECK (role IN ('owner', 'admin', 'moderator', 'member', 'read-only'));
+
+-- Usernames became unique regardless of case; older duplicates keep their id as a suffix
+UPDATE users u SET username = u.username || '-' || u.id
+WHERE EXISTS (
+    SELECT 1 FROM users o WHERE lower(o.username) = lower(u.username) AND o.id < u.id
+);
+
+-- Emails became trimmed and lowercase; addresses whose normalized form is already taken are
+-- left as they were, and keep the case-insensitive index below from being created until merged
+UPDATE users u SET email = lower(trim(u.email))
+WHERE u.email <> lower(trim(u.email))
+AND NOT EXISTS (
+    SELECT 1 FROM users o WHERE lower(trim(o.email)) = lower(trim(u.email)) AND o.id <> u.id
+);
+
 -- Create indexes for better query performance
+CREATE INDEX IF NOT EXISTS idx_rooms_visibility ON rooms(visibility);
 CREATE INDEX IF NOT EXISTS idx_messages_room_id ON messages(room_id);
 CREATE INDEX IF NOT EXISTS idx_messages_sender_id ON messages(sender_id);
 CREATE INDEX IF NOT EXISTS idx_messages_created_at ON messages(created_at);
+CREATE INDEX IF NOT EXISTS idx_messages_expires_at ON messages(room_id, expires_at) WHERE expires_at IS NOT NULL;
 CREATE INDEX IF NOT EXISTS idx_room_members_user_id ON room_members(user_id);
 CREATE INDEX IF NOT EXISTS idx_room_members_room_id ON room_members(room_id);
+-- A room has at most one owner
+CREATE UNIQUE INDEX IF NOT EXISTS idx_room_members_owner ON room_members(room_id) WHERE role = 'owner';
+CREATE INDEX IF NOT EXISTS idx_room_bans_user_id ON room_bans(user_id);
+CREATE INDEX IF NOT EXISTS idx_room_invites_room_id ON room_invites(room_id);
+CREATE INDEX IF NOT EXISTS idx_room_invites_created_by ON room_invites(created_by);
+CREATE INDEX IF NOT EXISTS idx_room_invitations_user_id ON room_invitations(user_id);
+CREATE INDEX IF NOT EXISTS idx_room_join_requests_user_id ON room_join_requests(user_id);
+CREATE INDEX IF NOT EXISTS idx_user_blocks_target_id ON user_blocks(target_id);
+CREATE INDEX IF NOT EXISTS idx_refresh_tokens_user_id ON refresh_tokens(user_id);
+CREATE INDEX IF NOT EXISTS idx_refresh_tokens_family_id ON refresh_tokens(family_id);
+CREATE INDEX IF NOT EXISTS idx_revoked_tokens_expires_at ON revoked_tokens(expires_at);
+CREATE INDEX IF NOT EXISTS idx_user_tokens_user_id ON user_tokens(user_id, purpose);
+CREATE INDEX IF NOT EXISTS idx_recovery_codes_user_id ON recovery_codes(user_id);
+CREATE INDEX IF NOT EXISTS idx_user_identities_user_id ON user_identities(user_id);
+CREATE INDEX IF NOT EXISTS idx_webauthn_credentials_user_id ON webauthn_credentials(user_id);
+CREATE INDEX IF NOT EXISTS idx_api_keys_user_id ON api_keys(user_id);
+CREATE INDEX IF NOT EXISTS idx_users_bot_owner_id ON users(bot_owner_id);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_users_username_lower ON users(lower(username));
+DO $$
+BEGIN
+    CREATE UNIQUE INDEX IF NOT EXISTS idx_users_email_lower ON users(lower(email));
+EXCEPTION WHEN unique_violation THEN
+    RAISE WARNING 'Accounts share an email in different case; merge them to enforce unique emails';
+END
+$$;
+CREATE INDEX IF NOT EXISTS idx_users_username_trgm ON users USING GIN (lower(username) gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS idx_users_display_name_trgm ON users USING GIN (lower(display_name) gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS idx_sessions_user_id ON sessions(user_id);
+CREATE INDEX IF NOT EXISTS idx_login_lockouts_user_id ON login_lockouts(user_id);
+CREATE INDEX IF NOT EXISTS idx_account_jobs_user_id ON account_jobs(user_id, kind);
+CREATE INDEX IF NOT EXISTS idx_data_exports_expires_at ON data_exports(expires_at);
 
 -- Insert sample rooms
 INSERT INTO rooms (name, created_at) VALUES
@@ -1,5 +1,6 @@
 use std::env;
 use std::fmt;
+use std::path::PathBuf;
 
 /// Application environment
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -31,6 +32,7 @@ impl Environment {
     }
 
     /// Check if environment is local/development
+    #[allow(dead_code)]
     pub fn is_development(&self) -> bool {
         matches!(self, Environment::Local | Environment::Development)
     }
@@ -41,6 +43,7 @@ impl Environment {
     }
 
     /// Get recommended log level for this environment
+    #[allow(dead_code)]
     pub fn default_log_level(&self) -> &str {
         match self {
             Environment::Local => "debug",
@@ -62,6 +65,146 @@ impl fmt::Display for Environment {
     }
 }
 
+/// How outgoing email is delivered
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MailBackend {
+    /// Log emails instead of sending them
+    Log,
+    /// Write emails to files in `MAIL_DIR`
+    File,
+    /// Deliver through an SMTP relay
+    Smtp,
+}
+
+impl MailBackend {
+    /// Parse mail backend from string
+    pub fn from_str(s: &str) -> Self {
+        match s.to_lowercase().as_str() {
+            "log" => MailBackend::Log,
+            "file" => MailBackend::File,
+            "smtp" => MailBackend::Smtp,
+            _ => {
+                tracing::warn!("Unknown mail backend '{}', defaulting to log", s);
+                MailBackend::Log
+            }
+        }
+    }
+}
+
+impl fmt::Display for MailBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MailBackend::Log => write!(f, "log"),
+            MailBackend::File => write!(f, "file"),
+            MailBackend::Smtp => write!(f, "smtp"),
+        }
+    }
+}
+
+/// Where the client address of a request is read from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClientIpSource {
+    /// The TCP peer address
+    Peer,
+    /// The last entry of `X-Forwarded-For`, as appended by a trusted reverse proxy
+    XForwardedFor,
+}
+
+impl ClientIpSource {
+    /// Parse client IP source from string
+    pub fn from_str(s: &str) -> Self {
+        match s.to_lowercase().as_str() {
+            "peer" => ClientIpSource::Peer,
+            "x-forwarded-for" => ClientIpSource::XForwardedFor,
+            _ => {
+                tracing::warn!("Unknown client IP source '{}', defaulting to peer", s);
+                ClientIpSource::Peer
+            }
+        }
+    }
+}
+
+impl fmt::Display for ClientIpSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientIpSource::Peer => write!(f, "peer"),
+            ClientIpSource::XForwardedFor => write!(f, "x-forwarded-for"),
+        }
+    }
+}
+
+/// An OpenID Connect identity provider used for single sign-on
+#[derive(Clone, Debug)]
+pub struct OidcProviderConfig {
+    /// Short name used in URLs, e.g. `corp` in `/auth/oidc/corp/start`
+    pub name: String,
+
+    /// Issuer URL; discovery is fetched from `{issuer}/.well-known/openid-configuration`
+    pub issuer: String,
+
+    pub client_id: String,
+
+    pub client_secret: Option<String>,
+
+    /// Callback URL registered with the provider
+    pub redirect_uri: String,
+
+    /// Space-separated scopes to request
+    pub scopes: String,
+}
+
+impl OidcProviderConfig {
+    /// Load provider `name` from `OIDC_<NAME>_*` variables
+    fn from_env(name: &str, port: u16) -> Result<Self, env::VarError> {
+        let prefix = format!("OIDC_{}", name.to_uppercase().replace('-', "_"));
+        let var = |key: &str| env::var(format!("{}_{}", prefix, key));
+
+        Ok(Self {
+            name: name.to_string(),
+            issuer: var("ISSUER")?.trim_end_matches('/').to_string(),
+            client_id: var("CLIENT_ID")?,
+            client_secret: var("CLIENT_SECRET").ok(),
+            redirect_uri: var("REDIRECT_URI").unwrap_or_else(|_| {
+                format!("http://localhost:{}/auth/oidc/{}/callback", port, name)
+            }),
+            scopes: var("SCOPES").unwrap_or_else(|_| "openid email profile".to_string()),
+        })
+    }
+}
+
+/// Requirements for new passwords
+#[derive(Clone, Debug)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub require_lowercase: bool,
+    pub require_uppercase: bool,
+    pub require_digit: bool,
+    pub require_symbol: bool,
+}
+
+impl PasswordPolicy {
+    /// Load the policy from `PASSWORD_*` variables
+    fn from_env() -> Self {
+        let flag = |key: &str| {
+            env::var(key)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(false)
+        };
+
+        Self {
+            min_length: env::var("PASSWORD_MIN_LENGTH")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(8),
+            require_lowercase: flag("PASSWORD_REQUIRE_LOWERCASE"),
+            require_uppercase: flag("PASSWORD_REQUIRE_UPPERCASE"),
+            require_digit: flag("PASSWORD_REQUIRE_DIGIT"),
+            require_symbol: flag("PASSWORD_REQUIRE_SYMBOL"),
+        }
+    }
+}
+
 /// Application configuration
 #[derive(Clone, Debug)]
 pub struct Config {
@@ -71,8 +214,17 @@ pub struct Config {
     /// Database connection URL
     pub database_url: String,
     
-    /// JWT secret for token signing
-    pub jwt_secret: String,
+    /// JWT secret for HS256 token signing
+    pub jwt_secret: Option<String>,
+    
+    /// PEM private key (RSA or Ed25519) for asymmetric token signing
+    pub jwt_private_key_file: Option<PathBuf>,
+    
+    /// PEM public key matching `jwt_private_key_file`
+    pub jwt_public_key_file: Option<PathBuf>,
+    
+    /// Further PEM public keys accepted for verification (key rotation)
+    pub jwt_verification_key_files: Vec<PathBuf>,
     
     /// Server port
     pub port: u16,
@@ -80,12 +232,24 @@ pub struct Config {
     /// Server host
     pub host: String,
     
-    /// JWT token expiration in hours
-    pub jwt_expiration_hours: i64,
+    /// Access token (JWT) lifetime in minutes
+    pub access_token_ttl_minutes: i64,
+    
+    /// Refresh token lifetime in days
+    pub refresh_token_ttl_days: i64,
     
     /// Maximum WebSocket connections per room
     pub max_ws_connections: usize,
     
+    /// Longest chat message accepted, in characters
+    pub max_message_length: usize,
+    
+    /// Most people in a group DM, its creator included
+    pub max_group_dm_participants: usize,
+    
+    /// Where client IP addresses are taken from
+    pub client_ip_source: ClientIpSource,
+    
     /// Allowed CORS origins (empty = allow all)
     pub cors_origins: Vec<String>,
     
@@ -100,6 +264,63 @@ pub struct Config {
     
     /// Enable Redis caching
     pub enable_redis: bool,
+    
+    /// Public URL of the web client, used to build links in emails
+    pub public_base_url: String,
+    
+    /// Issuer name shown in authenticator apps
+    pub totp_issuer: String,
+    
+    /// WebAuthn relying party ID (the domain passkeys are bound to)
+    pub webauthn_rp_id: String,
+    
+    /// Relying party name shown by authenticators
+    pub webauthn_rp_name: String,
+    
+    /// Origins allowed to perform WebAuthn ceremonies
+    pub webauthn_origins: Vec<String>,
+    
+    /// OpenID Connect providers enabled for single sign-on
+    pub oidc_providers: Vec<OidcProviderConfig>,
+    
+    /// Requirements for new passwords
+    pub password_policy: PasswordPolicy,
+    
+    /// Argon2 memory cost in KiB
+    pub argon2_memory_kib: u32,
+    
+    /// Argon2 iterations
+    pub argon2_iterations: u32,
+    
+    /// Argon2 lanes
+    pub argon2_parallelism: u32,
+    
+    /// Secret mixed into every password hash (optional)
+    pub password_pepper: Option<String>,
+    
+    /// Refuse logins until the email address has been verified
+    pub require_email_verification: bool,
+    
+    /// Email delivery backend
+    pub mail_backend: MailBackend,
+    
+    /// Sender address for outgoing email
+    pub mail_from: String,
+    
+    /// Directory for the file mail backend
+    pub mail_dir: PathBuf,
+    
+    /// SMTP relay host
+    pub smtp_host: Option<String>,
+    
+    /// SMTP relay port
+    pub smtp_port: u16,
+    
+    /// SMTP username (optional)
+    pub smtp_username: Option<String>,
+    
+    /// SMTP password (optional)
+    pub smtp_password: Option<String>,
 }
 
 impl Config {
@@ -114,11 +335,27 @@ impl Config {
         // Get database URL (required)
         let database_url = env::var("DATABASE_URL")?;
 
-        // Get JWT secret (required)
-        let jwt_secret = env::var("JWT_SECRET")?;
+        // Get JWT secret (required unless signing with a key pair)
+        let jwt_secret = env::var("JWT_SECRET").ok();
+        let jwt_private_key_file = env::var("JWT_PRIVATE_KEY_FILE").ok().map(PathBuf::from);
+        let jwt_public_key_file = env::var("JWT_PUBLIC_KEY_FILE").ok().map(PathBuf::from);
+        let jwt_verification_key_files = env::var("JWT_VERIFICATION_KEY_FILES")
+            .ok()
+            .map(|s| {
+                s.split(',')
+                    .map(str::trim)
+                    .filter(|path| !path.is_empty())
+                    .map(PathBuf::from)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        if jwt_secret.is_none() && jwt_private_key_file.is_none() {
+            return Err(env::VarError::NotPresent);
+        }
 
         // Validate JWT secret length in production
-        if environment.is_production() && jwt_secret.len() < 32 {
+        if environment.is_production() && jwt_secret.as_ref().is_some_and(|s| s.len() < 32) {
             panic!("JWT_SECRET must be at least 32 characters in production!");
         }
 
@@ -138,11 +375,17 @@ impl Config {
         let host = env::var("HOST")
             .unwrap_or_else(|_| "0.0.0.0".to_string());
 
-        // JWT expiration (default: 24 hours)
-        let jwt_expiration_hours = env::var("JWT_EXPIRATION_HOURS")
+        // Access token lifetime (default: 15 minutes)
+        let access_token_ttl_minutes = env::var("ACCESS_TOKEN_TTL_MINUTES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(15);
+
+        // Refresh token lifetime (default: 30 days)
+        let refresh_token_ttl_days = env::var("REFRESH_TOKEN_TTL_DAYS")
             .ok()
             .and_then(|v| v.parse().ok())
-            .unwrap_or(24);
+            .unwrap_or(30);
 
         // Max WebSocket connections per room
         let max_ws_connections = env::var("MAX_WS_CONNECTIONS")
@@ -150,6 +393,23 @@ impl Config {
             .and_then(|v| v.parse().ok())
             .unwrap_or(100);
 
+        // Longest chat message (default: 4000 characters)
+        let max_message_length = env::var("MAX_MESSAGE_LENGTH")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(4000);
+
+        // Largest group DM (default: 10 participants)
+        let max_group_dm_participants = env::var("MAX_GROUP_DM_PARTICIPANTS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(10);
+
+        // Client IP source (only trust X-Forwarded-For behind a reverse proxy)
+        let client_ip_source = env::var("CLIENT_IP_SOURCE")
+            .map(|v| ClientIpSource::from_str(&v))
+            .unwrap_or(ClientIpSource::Peer);
+
         // CORS origins
         let cors_origins = env::var("CORS_ORIGINS")
             .ok()
@@ -160,7 +420,7 @@ impl Config {
         let enable_logging = env::var("ENABLE_LOGGING")
             .ok()
             .and_then(|v| v.parse().ok())
-            .unwrap_or(!environment.is_production() || true);
+            .unwrap_or(true);
 
         // Redis configuration
         let redis_url = env::var("REDIS_URL").ok();
@@ -175,19 +435,121 @@ impl Config {
             .and_then(|v| v.parse().ok())
             .unwrap_or(300); // Default: 5 minutes
 
+        // Public URL of the web client (default: this server)
+        let public_base_url = env::var("APP_BASE_URL")
+            .unwrap_or_else(|_| format!("http://localhost:{}", port));
+
+        // Issuer name for TOTP two-factor authentication
+        let totp_issuer = env::var("TOTP_ISSUER")
+            .unwrap_or_else(|_| "Chat".to_string());
+
+        // WebAuthn relying party (defaults derived from the public URL)
+        let webauthn_rp_id = env::var("WEBAUTHN_RP_ID").unwrap_or_else(|_| {
+            reqwest::Url::parse(&public_base_url)
+                .ok()
+                .and_then(|url| url.host_str().map(str::to_string))
+                .unwrap_or_else(|| "localhost".to_string())
+        });
+        let webauthn_rp_name = env::var("WEBAUTHN_RP_NAME")
+            .unwrap_or_else(|_| totp_issuer.clone());
+        let webauthn_origins = env::var("WEBAUTHN_ORIGINS")
+            .ok()
+            .map(|s| s.split(',').map(|s| s.trim().trim_end_matches('/').to_string()).collect())
+            .unwrap_or_else(|| vec![public_base_url.trim_end_matches('/').to_string()]);
+
+        // OpenID Connect providers (comma-separated names, each configured via OIDC_<NAME>_*)
+        let oidc_providers = env::var("OIDC_PROVIDERS")
+            .ok()
+            .map(|s| {
+                s.split(',')
+                    .map(str::trim)
+                    .filter(|name| !name.is_empty())
+                    .map(|name| OidcProviderConfig::from_env(name, port))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .transpose()?
+            .unwrap_or_default();
+
+        // Password policy for registration and password changes
+        let password_policy = PasswordPolicy::from_env();
+
+        // Password hashing costs (defaults: OWASP's Argon2id recommendation)
+        let argon2_memory_kib = env::var("ARGON2_MEMORY_KIB")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(19 * 1024);
+        let argon2_iterations = env::var("ARGON2_ITERATIONS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(2);
+        let argon2_parallelism = env::var("ARGON2_PARALLELISM")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(1);
+        let password_pepper = env::var("PASSWORD_PEPPER").ok().filter(|p| !p.is_empty());
+
+        // Require verified email before login (default: only in production)
+        let require_email_verification = env::var("REQUIRE_EMAIL_VERIFICATION")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(environment.is_production());
+
+        // Mail configuration
+        let mail_backend = env::var("MAIL_BACKEND")
+            .map(|v| MailBackend::from_str(&v))
+            .unwrap_or(MailBackend::Log);
+        let mail_from = env::var("MAIL_FROM")
+            .unwrap_or_else(|_| "Chat <no-reply@localhost>".to_string());
+        let mail_dir = env::var("MAIL_DIR")
+            .unwrap_or_else(|_| "./mail".to_string())
+            .into();
+        let smtp_host = env::var("SMTP_HOST").ok();
+        let smtp_port = env::var("SMTP_PORT")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(587);
+        let smtp_username = env::var("SMTP_USERNAME").ok();
+        let smtp_password = env::var("SMTP_PASSWORD").ok();
+
         let config = Config {
             environment,
             database_url,
             jwt_secret,
+            jwt_private_key_file,
+            jwt_public_key_file,
+            jwt_verification_key_files,
             port,
             host,
-            jwt_expiration_hours,
+            access_token_ttl_minutes,
+            refresh_token_ttl_days,
             max_ws_connections,
+            max_message_length,
+            max_group_dm_participants,
+            client_ip_source,
             cors_origins,
             enable_logging,
             redis_url,
             redis_cache_ttl,
             enable_redis,
+            public_base_url,
+            totp_issuer,
+            webauthn_rp_id,
+            webauthn_rp_name,
+            webauthn_origins,
+            oidc_providers,
+            password_policy,
+            argon2_memory_kib,
+            argon2_iterations,
+            argon2_parallelism,
+            password_pepper,
+            require_email_verification,
+            mail_backend,
+            mail_from,
+            mail_dir,
+            smtp_host,
+            smtp_port,
+            smtp_username,
+            smtp_password,
         };
 
         // Log configuration (without secrets)
@@ -195,19 +557,30 @@ impl Config {
         tracing::info!("  Environment: {}", config.environment);
         tracing::info!("  Host: {}", config.host);
         tracing::info!("  Port: {}", config.port);
-        tracing::info!("  JWT Expiration: {} hours", config.jwt_expiration_hours);
+        tracing::info!("  Access Token TTL: {} minutes", config.access_token_ttl_minutes);
+        tracing::info!("  Refresh Token TTL: {} days", config.refresh_token_ttl_days);
         tracing::info!("  Max WS Connections: {}", config.max_ws_connections);
+        tracing::info!("  Client IP Source: {}", config.client_ip_source);
         tracing::info!("  CORS Origins: {:?}", config.cors_origins);
         tracing::info!("  Enable Logging: {}", config.enable_logging);
         tracing::info!("  Redis Enabled: {}", config.enable_redis);
         if config.enable_redis {
             tracing::info!("  Redis Cache TTL: {}s", config.redis_cache_ttl);
         }
+        tracing::info!("  Mail Backend: {}", config.mail_backend);
+        tracing::info!("  WebAuthn RP ID: {}", config.webauthn_rp_id);
+        tracing::info!("  WebAuthn Origins: {:?}", config.webauthn_origins);
+        tracing::info!(
+            "  OIDC Providers: {:?}",
+            config.oidc_providers.iter().map(|p| &p.name).collect::<Vec<_>>()
+        );
+        tracing::info!("  Require Email Verification: {}", config.require_email_verification);
 
         Ok(config)
     }
 
     /// Get full server address
+    #[allow(dead_code)]
     pub fn server_address(&self) -> String {
         format!("{}:{}", self.host, self.port)
     }
@@ -1,4 +1,4 @@
-use sea_orm::{Database, DatabaseConnection, DbErr};
+use sea_orm::{ConnectionTrait, Database, DatabaseConnection, DbBackend, DbErr, Statement};
 
 pub async fn establish_connection(database_url: &str) -> Result<DatabaseConnection, DbErr> {
     tracing::info!("Connecting to database...");
@@ -6,3 +6,34 @@ pub async fn establish_connection(database_url: &str) -> Result<DatabaseConnecti
     tracing::info!("Database connection established");
     Ok(db)
 }
+
+/// What an advisory lock protects. Postgres has one advisory lock space per database, so each
+/// use gets its own tag in the top two bits of the 64-bit key; the two ids fill the remaining
+/// 31 bits each. Locks of different uses therefore never block each other.
+#[derive(Clone, Copy, Debug)]
+pub enum LockSpace {
+    /// Starting a DM between two users, keyed by the lower and the higher user id
+    DirectMessage = 1,
+    /// Posting in a slow mode room, keyed by room id and user id
+    SlowMode = 2,
+}
+
+/// Take the advisory lock on `(a, b)` in `space`, held until the transaction `txn` ends.
+/// Both ids must be non-negative, as database ids are.
+pub async fn advisory_xact_lock<C: ConnectionTrait>(
+    txn: &C,
+    space: LockSpace,
+    a: i32,
+    b: i32,
+) -> Result<(), DbErr> {
+    debug_assert!(a >= 0 && b >= 0, "advisory lock ids must be non-negative");
+    let key = (space as u64) << 62 | u64::from(a as u32) << 31 | u64::from(b as u32);
+
+    txn.execute(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        "SELECT pg_advisory_xact_lock($1)",
+        [(key as i64).into()],
+    ))
+    .await?;
+    Ok(())
+}
@@ -1,5 +1,6 @@
+use crate::validation::ValidationErrors;
 use axum::{
-    http::StatusCode,
+    http::{header, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
@@ -20,30 +21,92 @@ pub enum AppError {
     #[error("User already exists")]
     UserAlreadyExists,
 
+    #[error("Username already taken")]
+    UsernameTaken,
+
     #[error("User not found")]
     UserNotFound,
 
     #[error("Room not found")]
     RoomNotFound,
 
+    #[error("Job not found")]
+    JobNotFound,
+
+    #[error("Invite not found")]
+    InviteNotFound,
+
+    #[error("Invite expired")]
+    InviteExpired,
+
+    #[error("Invitation not found")]
+    InvitationNotFound,
+
+    #[error("Join request not found")]
+    JoinRequestNotFound,
+
     #[error("Invalid token")]
     InvalidToken,
 
     #[error("Token expired")]
     TokenExpired,
 
+    #[error("Token revoked")]
+    TokenRevoked,
+
+    #[error("Invalid two-factor code")]
+    InvalidTwoFactorCode,
+
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+
+    #[error("Too many failed attempts; retry after {retry_after} seconds")]
+    TooManyAttempts { retry_after: u64 },
+
+    #[error("Slow mode; retry after {retry_after} seconds")]
+    SlowMode { retry_after: u64 },
+
+    #[error("Rate limited; retry after {retry_after} seconds")]
+    RateLimited { retry_after: u64 },
+
+    #[error("Email not verified")]
+    EmailNotVerified,
+
+    #[error("Account disabled")]
+    AccountDisabled,
+
+    #[error("Password reset required")]
+    PasswordResetRequired,
+
     #[error("Password hashing error")]
     PasswordHashError,
 
     #[error("Internal server error")]
     InternalServerError,
 
+    #[error("Upstream service error: {0}")]
+    UpstreamError(String),
+
+    #[error("Bad request: {0}")]
+    BadRequest(String),
+
     #[error("Validation error: {0}")]
-    ValidationError(String),
+    ValidationError(ValidationErrors),
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
+        let retry_after = match self {
+            AppError::TooManyAttempts { retry_after }
+            | AppError::SlowMode { retry_after }
+            | AppError::RateLimited { retry_after } => Some(retry_after),
+            _ => None,
+        };
+        let fields = match self {
+            AppError::ValidationError(ref errors) => Some(json!(errors)),
+            _ => None,
+        };
+
         let (status, error_message) = match self {
             AppError::DatabaseError(err) => {
                 tracing::error!("Database error: {:?}", err);
@@ -52,10 +115,38 @@ impl IntoResponse for AppError {
             AppError::AuthError(ref msg) => (StatusCode::UNAUTHORIZED, msg.as_str()),
             AppError::InvalidCredentials => (StatusCode::UNAUTHORIZED, "Invalid credentials"),
             AppError::UserAlreadyExists => (StatusCode::CONFLICT, "User already exists"),
+            AppError::UsernameTaken => (StatusCode::CONFLICT, "Username already taken"),
             AppError::UserNotFound => (StatusCode::NOT_FOUND, "User not found"),
             AppError::RoomNotFound => (StatusCode::NOT_FOUND, "Room not found"),
+            AppError::JobNotFound => (StatusCode::NOT_FOUND, "Job not found"),
+            AppError::InviteNotFound => (StatusCode::NOT_FOUND, "Invite not found"),
+            AppError::InviteExpired => (StatusCode::GONE, "Invite expired or used up"),
+            AppError::InvitationNotFound => (StatusCode::NOT_FOUND, "Invitation not found"),
+            AppError::JoinRequestNotFound => (StatusCode::NOT_FOUND, "Join request not found"),
             AppError::InvalidToken => (StatusCode::UNAUTHORIZED, "Invalid token"),
             AppError::TokenExpired => (StatusCode::UNAUTHORIZED, "Token expired"),
+            AppError::TokenRevoked => (StatusCode::UNAUTHORIZED, "Token revoked"),
+            AppError::InvalidTwoFactorCode => {
+                (StatusCode::UNAUTHORIZED, "Invalid two-factor code")
+            }
+            AppError::Forbidden(ref msg) => (StatusCode::FORBIDDEN, msg.as_str()),
+            AppError::TooManyAttempts { .. } => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "Too many failed attempts, try again later",
+            ),
+            AppError::SlowMode { .. } => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "Slow mode is on, wait before posting again",
+            ),
+            AppError::RateLimited { .. } => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "Too many requests, try again later",
+            ),
+            AppError::EmailNotVerified => (StatusCode::FORBIDDEN, "Email not verified"),
+            AppError::AccountDisabled => (StatusCode::FORBIDDEN, "Account disabled"),
+            AppError::PasswordResetRequired => {
+                (StatusCode::FORBIDDEN, "Password reset required")
+            }
             AppError::PasswordHashError => {
                 tracing::error!("Password hashing error");
                 (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
@@ -63,14 +154,29 @@ impl IntoResponse for AppError {
             AppError::InternalServerError => {
                 (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
             }
-            AppError::ValidationError(ref msg) => (StatusCode::BAD_REQUEST, msg.as_str()),
+            AppError::UpstreamError(ref msg) => {
+                tracing::error!("Upstream service error: {}", msg);
+                (StatusCode::BAD_GATEWAY, "Upstream service error")
+            }
+            AppError::BadRequest(ref msg) => (StatusCode::BAD_REQUEST, msg.as_str()),
+            AppError::ValidationError(_) => (StatusCode::BAD_REQUEST, "Validation failed"),
         };
 
-        let body = Json(json!({
+        let mut body = json!({
             "error": error_message,
-        }));
+        });
+        if let Some(fields) = fields {
+            body["fields"] = fields;
+        }
+        if let Some(seconds) = retry_after {
+            body["retry_after"] = json!(seconds);
+        }
+        let body = Json(body);
 
-        (status, body).into_response()
+        match retry_after {
+            Some(seconds) => (status, [(header::RETRY_AFTER, seconds.to_string())], body).into_response(),
+            None => (status, body).into_response(),
+        }
     }
 }
 
@@ -5,23 +5,47 @@ mod models;
 mod routes;
 mod services;
 mod utils;
+mod validation;
 
 use axum::{
-    extract::FromRef,
-    routing::{get, post},
-    Router,
+    extract::{DefaultBodyLimit, FromRef},
+    handler::Handler,
+    routing::{delete, get, patch, post, put},
+    Extension, Router,
 };
+use config::ClientIpSource;
+use models::api_key::Scope;
 use sea_orm::DatabaseConnection;
 use services::{
+    account_job_service::AccountJobService,
+    admin_service::AdminService,
+    api_key_service::ApiKeyService,
     auth_service::AuthService, 
+    block_service::BlockService,
+    bot_service::BotService,
+    connection_registry::ConnectionRegistry,
+    invitation_service::InvitationService,
+    invite_service::InviteService,
     jwt_service::JwtService, 
     message_service::MessageService,
+    oidc_service::OidcService,
+    profile_service::{ProfileService, MAX_IMAGE_UPLOAD_BYTES},
+    rate_limiter::RateLimiter,
     redis_service::RedisService,
+    refresh_token_service::RefreshTokenService,
+    revocation_service::RevocationService,
+    room_service::RoomService,
+    webauthn_service::WebAuthnService,
 };
-use std::{collections::HashMap, net::SocketAddr, sync::Arc};
-use tokio::sync::{broadcast, RwLock};
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    sync::Arc,
+};
+use tokio::sync::{broadcast, watch, RwLock};
 use tower_http::cors::{Any, CorsLayer};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use validation::ValidationRules;
 
 // Unified application state
 #[derive(Clone, FromRef)]
@@ -29,9 +53,30 @@ pub struct AppState {
     pub jwt_service: Arc<JwtService>,
     pub auth_service: Arc<AuthService>,
     pub message_service: Arc<MessageService>,
+    pub block_service: Arc<BlockService>,
+    pub api_key_service: Arc<ApiKeyService>,
+    pub bot_service: Arc<BotService>,
+    pub revocation_service: Arc<RevocationService>,
+    pub oidc_service: Arc<OidcService>,
+    pub profile_service: Arc<ProfileService>,
+    pub webauthn_service: Arc<WebAuthnService>,
+    pub account_job_service: Arc<AccountJobService>,
+    pub admin_service: Arc<AdminService>,
+    pub room_service: Arc<RoomService>,
+    pub invite_service: Arc<InviteService>,
+    pub invitation_service: Arc<InvitationService>,
     pub db: Arc<DatabaseConnection>,
     pub rooms: Arc<RwLock<HashMap<i32, broadcast::Sender<String>>>>,
+    /// Per-user event channels, keyed by user id
+    #[from_ref(skip)]
+    pub user_channels: Arc<RwLock<HashMap<i32, broadcast::Sender<String>>>>,
+    /// Senders each connected user has blocked or muted, shared by their room connections
+    #[from_ref(skip)]
+    pub hidden_senders: Arc<RwLock<HashMap<i32, watch::Sender<HashSet<i32>>>>>,
+    pub connections: Arc<ConnectionRegistry>,
     pub redis: Option<Arc<RedisService>>,
+    pub client_ip_source: ClientIpSource,
+    pub validation: Arc<ValidationRules>,
 }
 
 #[tokio::main]
@@ -58,11 +103,6 @@ async fn main() {
         .await
         .expect("Failed to connect to database");
 
-    // Initialize services with config
-    let jwt_service = Arc::new(JwtService::new(&config.jwt_secret, config.jwt_expiration_hours));
-    let auth_service = Arc::new(AuthService::new(db.clone(), jwt_service.as_ref().clone()));
-    let message_service = Arc::new(MessageService::new(db.clone()));
-
     // Initialize Redis service if enabled
     let redis = if config.enable_redis {
         match &config.redis_url {
@@ -88,14 +128,79 @@ async fn main() {
         None
     };
 
+    // Initialize services with config
+    let jwt_service = Arc::new(JwtService::from_config(&config).expect("Failed to load JWT keys"));
+    let refresh_token_service = RefreshTokenService::new(db.clone(), config.refresh_token_ttl_days);
+    let revocation_service = Arc::new(RevocationService::new(
+        db.clone(),
+        redis.clone(),
+        jwt_service.expires_in(),
+    ));
+    let mailer = services::mailer::from_config(&config).expect("Failed to configure mailer");
+    let auth_service = Arc::new(AuthService::new(
+        db.clone(),
+        jwt_service.as_ref().clone(),
+        refresh_token_service,
+        revocation_service.as_ref().clone(),
+        mailer,
+        redis.clone(),
+        &config,
+    ));
+    let message_service = Arc::new(MessageService::new(db.clone()));
+    let block_service = Arc::new(BlockService::new(db.clone()));
+    let api_key_service = Arc::new(ApiKeyService::new(db.clone()));
+    let bot_service = Arc::new(BotService::new(db.clone()));
+    let profile_service = Arc::new(ProfileService::new(db.clone()));
+    let oidc_service = Arc::new(OidcService::new(config.oidc_providers.clone(), redis.clone()));
+    let webauthn_service = Arc::new(WebAuthnService::new(db.clone(), redis.clone(), &config));
+    let connections = Arc::new(ConnectionRegistry::default());
+    let account_job_service = Arc::new(AccountJobService::new(
+        db.clone(),
+        revocation_service.clone(),
+        connections.clone(),
+    ));
+    let admin_service = Arc::new(AdminService::new(db.clone(), revocation_service.clone()));
+    let room_service = Arc::new(RoomService::new(
+        db.clone(),
+        config.max_group_dm_participants,
+    ));
+    let invite_service = Arc::new(InviteService::new(db.clone(), room_service.clone()));
+    let invitation_service = Arc::new(InvitationService::new(
+        db.clone(),
+        room_service.clone(),
+        Arc::new(RateLimiter::new(redis.clone())),
+    ));
+    account_job_service
+        .resume()
+        .await
+        .expect("Failed to resume account jobs");
+    message_service.spawn_expiry_sweeper();
+
     // Create unified application state
     let app_state = AppState {
         jwt_service,
         auth_service,
         message_service,
+        block_service,
+        api_key_service,
+        bot_service,
+        revocation_service,
+        oidc_service,
+        profile_service,
+        webauthn_service,
+        account_job_service,
+        admin_service,
+        room_service,
+        invite_service,
+        invitation_service,
         db: Arc::new(db),
         rooms: Arc::new(RwLock::new(HashMap::new())),
+        user_channels: Arc::new(RwLock::new(HashMap::new())),
+        hidden_senders: Arc::new(RwLock::new(HashMap::new())),
+        connections,
         redis,
+        client_ip_source: config.client_ip_source,
+        validation: Arc::new(ValidationRules::from_config(&config)),
     };
 
     // Configure CORS based on environment
@@ -118,17 +223,194 @@ async fn main() {
             .allow_headers(Any)
     };
 
+    // Admin routes; every handler is guarded by the `Admin` extractor
+    let admin = Router::new()
+        .route("/users", get(routes::admin::list_users))
+        .route("/users/:user_id", get(routes::admin::get_user))
+        .route("/users/:user_id/disable", post(routes::admin::disable_user))
+        .route("/users/:user_id/enable", post(routes::admin::enable_user))
+        .route(
+            "/users/:user_id/force-password-reset",
+            post(routes::admin::force_password_reset),
+        )
+        .route("/rooms/:room_id", delete(routes::admin::delete_room))
+        .route("/messages/:message_id", delete(routes::admin::delete_message))
+        .route("/stats", get(routes::admin::stats));
+
     // Build application with routes
     let app = Router::new()
         // Health check route
         .route("/health", get(routes::health::health_check))
+        .route("/.well-known/jwks.json", get(routes::jwks::jwks))
         // Auth routes
         .route("/auth/register", post(routes::auth::register))
         .route("/auth/login", post(routes::auth::login))
-        // Protected routes
-        .route("/rooms", get(routes::room::get_rooms))
-        .route("/rooms/:room_id/messages", post(routes::room::create_message))
+        .route("/auth/login/mfa", post(routes::auth::login_mfa))
+        .route("/auth/magic-link", post(routes::auth::request_magic_link))
+        .route("/auth/magic-link/verify", get(routes::auth::verify_magic_link))
+        .route("/auth/oidc/:provider/start", get(routes::oidc::start))
+        .route("/auth/oidc/:provider/callback", get(routes::oidc::callback))
+        .route("/auth/passkey/start", post(routes::passkey::start_login))
+        .route("/auth/passkey/finish", post(routes::passkey::finish_login))
+        .route("/auth/forgot-password", post(routes::auth::forgot_password))
+        .route("/auth/reset-password", post(routes::auth::reset_password))
+        .route("/auth/refresh", post(routes::auth::refresh))
+        .route("/auth/verify-email", post(routes::auth::verify_email))
+        .route("/auth/resend-verification", post(routes::auth::resend_verification))
+        .route("/auth/logout", post(routes::auth::logout))
+        .route("/auth/logout-all", post(routes::auth::logout_all))
+        // Protected routes (the Scope extension lets API keys with that scope in)
+        .route(
+            "/rooms",
+            get(routes::room::get_rooms.layer(Extension(Scope::RoomsRead)))
+                .post(routes::room::create_room),
+        )
+        .route(
+            "/rooms/:room_id",
+            patch(routes::room::update_room).delete(routes::room::delete_room),
+        )
+        .route(
+            "/rooms/:room_id/icon",
+            // Reading is unauthenticated, like avatars; leave room for the multipart framing
+            get(routes::room::get_icon)
+                .put(routes::room::upload_icon
+                    .layer(DefaultBodyLimit::max(MAX_IMAGE_UPLOAD_BYTES + 64 * 1024)))
+                .delete(routes::room::delete_icon),
+        )
+        .route("/dms", post(routes::room::start_dm))
+        .route(
+            "/rooms/:room_id/me",
+            get(routes::room::get_my_access.layer(Extension(Scope::RoomsRead))),
+        )
+        .route(
+            "/rooms/:room_id/members",
+            get(routes::room::list_members.layer(Extension(Scope::RoomsRead)))
+                .post(routes::room::add_member),
+        )
+        .route(
+            "/rooms/:room_id/members/:user_id",
+            patch(routes::room::update_member).delete(routes::room::remove_member),
+        )
+        .route("/rooms/:room_id/join", post(routes::room::join_room))
+        .route("/rooms/:room_id/leave", post(routes::room::leave_room))
+        .route("/rooms/:room_id/bans", get(routes::room::list_bans))
+        .route(
+            "/rooms/:room_id/bans/:user_id",
+            put(routes::room::ban_member).delete(routes::room::unban_member),
+        )
+        .route(
+            "/rooms/:room_id/permissions",
+            get(routes::room::get_permissions).put(routes::room::set_permission),
+        )
+        .route(
+            "/rooms/:room_id/permissions/:role/:permission",
+            delete(routes::room::clear_permission),
+        )
+        .route("/rooms/:room_id/transfer", post(routes::room::transfer_ownership))
+        .route(
+            "/rooms/:room_id/invites",
+            get(routes::invite::list_invites).post(routes::invite::create_invite),
+        )
+        .route(
+            "/rooms/:room_id/invites/:invite_id",
+            delete(routes::invite::revoke_invite),
+        )
+        .route("/invites/:code/accept", post(routes::invite::accept_invite))
+        .route(
+            "/rooms/:room_id/invitations",
+            get(routes::invitation::list_room_invitations).post(routes::invitation::invite_user),
+        )
+        .route(
+            "/rooms/:room_id/invitations/:user_id",
+            delete(routes::invitation::cancel_invitation),
+        )
+        .route(
+            "/rooms/:room_id/join-requests",
+            get(routes::invitation::list_join_requests)
+                .post(routes::invitation::request_to_join)
+                .delete(routes::invitation::withdraw_join_request),
+        )
+        .route(
+            "/rooms/:room_id/join-requests/:user_id/approve",
+            post(routes::invitation::approve_join_request),
+        )
+        .route(
+            "/rooms/:room_id/join-requests/:user_id/reject",
+            post(routes::invitation::reject_join_request),
+        )
+        .route(
+            "/rooms/:room_id/messages",
+            get(routes::room::list_messages.layer(Extension(Scope::MessagesRead)))
+                .post(routes::room::create_message.layer(Extension(Scope::MessagesWrite))),
+        )
+        .route(
+            "/rooms/:room_id/messages/:message_id",
+            delete(routes::room::delete_message.layer(Extension(Scope::MessagesWrite))),
+        )
+        .route(
+            "/me",
+            get(routes::profile::get_me)
+                .patch(routes::profile::update_me)
+                .delete(routes::account::delete_me),
+        )
+        .route(
+            "/me/export",
+            get(routes::account::download_export).post(routes::account::start_export),
+        )
+        .route("/jobs/:job_id", get(routes::account::get_job))
+        .route(
+            "/me/avatar",
+            // Leave room for the multipart framing around the image
+            put(routes::profile::upload_avatar
+                .layer(DefaultBodyLimit::max(MAX_IMAGE_UPLOAD_BYTES + 64 * 1024)))
+            .delete(routes::profile::delete_avatar),
+        )
+        .route("/me/password", post(routes::auth::change_password))
+        .route("/me/identities/:provider", post(routes::oidc::start_link))
+        .route("/me/2fa/enroll", post(routes::auth::enroll_totp))
+        .route("/me/2fa/confirm", post(routes::auth::confirm_totp))
+        .route("/me/2fa/disable", post(routes::auth::disable_totp))
+        .route("/me/passkeys", get(routes::passkey::list_passkeys))
+        .route("/me/passkeys/:id", delete(routes::passkey::delete_passkey))
+        .route("/me/passkeys/register/start", post(routes::passkey::start_registration))
+        .route("/me/passkeys/register/finish", post(routes::passkey::finish_registration))
+        .route(
+            "/me/api-keys",
+            get(routes::api_key::list_api_keys).post(routes::api_key::create_api_key),
+        )
+        .route("/me/api-keys/:id", delete(routes::api_key::delete_api_key))
+        .route("/me/bots", get(routes::bot::list_bots).post(routes::bot::create_bot))
+        .route("/me/bots/:bot_id", delete(routes::bot::delete_bot))
+        .route(
+            "/me/bots/:bot_id/api-keys",
+            get(routes::bot::list_bot_api_keys).post(routes::bot::create_bot_api_key),
+        )
+        .route(
+            "/me/bots/:bot_id/api-keys/:id",
+            delete(routes::bot::delete_bot_api_key),
+        )
+        .route("/me/sessions", get(routes::session::list_sessions))
+        .route("/me/sessions/:id", delete(routes::session::delete_session))
+        .route("/me/invitations", get(routes::invitation::list_my_invitations))
+        .route(
+            "/me/invitations/:invitation_id/accept",
+            post(routes::invitation::accept_invitation),
+        )
+        .route(
+            "/me/invitations/:invitation_id/decline",
+            post(routes::invitation::decline_invitation),
+        )
+        .route("/me/blocks", get(routes::block::list_blocks))
+        .route(
+            "/me/blocks/:user_id",
+            put(routes::block::block_user).delete(routes::block::unblock_user),
+        )
+        .route("/users", get(routes::profile::search_users))
+        .route("/users/:user_id", get(routes::profile::get_user))
+        .route("/users/:user_id/avatar", get(routes::profile::get_avatar))
+        .nest("/admin", admin)
         // WebSocket route
+        .route("/ws/me", get(routes::websocket::user_websocket_handler))
         .route("/ws/:room_id", get(routes::websocket::websocket_handler))
         .with_state(app_state)
         .layer(cors);
@@ -138,6 +420,8 @@ async fn main() {
     tracing::info!("Server listening on {}", addr);
 
     let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .await
+        .unwrap();
 }
 
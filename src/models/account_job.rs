@@ -0,0 +1,137 @@
+use crate::validation::{Validate, ValidationErrors, ValidationRules, Validator};
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// What an account job does
+#[derive(Clone, Copy, Debug, PartialEq, Eq, EnumIter, DeriveActiveEnum, Serialize, Deserialize)]
+#[sea_orm(rs_type = "String", db_type = "String(Some(16))")]
+#[serde(rename_all = "lowercase")]
+pub enum JobKind {
+    /// Collect the user's personal data into a downloadable archive
+    #[sea_orm(string_value = "export")]
+    Export,
+    /// Anonymize the account, keeping its messages under a "deleted user" attribution
+    #[sea_orm(string_value = "deletion")]
+    Deletion,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, EnumIter, DeriveActiveEnum, Serialize, Deserialize)]
+#[sea_orm(rs_type = "String", db_type = "String(Some(16))")]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    #[sea_orm(string_value = "pending")]
+    Pending,
+    #[sea_orm(string_value = "running")]
+    Running,
+    #[sea_orm(string_value = "completed")]
+    Completed,
+    #[sea_orm(string_value = "failed")]
+    Failed,
+}
+
+impl JobStatus {
+    /// Whether the job has yet to finish
+    pub fn is_active(&self) -> bool {
+        matches!(self, JobStatus::Pending | JobStatus::Running)
+    }
+}
+
+/// A personal data export or account deletion, run in the background
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "account_jobs")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: Uuid,
+
+    pub user_id: i32,
+
+    pub kind: JobKind,
+
+    pub status: JobStatus,
+
+    /// Why the job failed, in terms fit to show the user
+    pub error: Option<String>,
+
+    pub created_at: DateTime,
+
+    pub started_at: Option<DateTime>,
+
+    pub finished_at: Option<DateTime>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::UserId",
+        to = "super::user::Column::Id"
+    )]
+    User,
+    #[sea_orm(has_one = "super::data_export::Entity")]
+    DataExport,
+}
+
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl Related<super::data_export::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::DataExport.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JobResponse {
+    pub id: Uuid,
+    pub kind: JobKind,
+    pub status: JobStatus,
+    pub error: Option<String>,
+    pub created_at: DateTime,
+    pub started_at: Option<DateTime>,
+    pub finished_at: Option<DateTime>,
+    /// Where the archive of a completed export can be downloaded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub download_url: Option<String>,
+}
+
+impl From<Model> for JobResponse {
+    fn from(job: Model) -> Self {
+        JobResponse {
+            download_url: (job.kind == JobKind::Export && job.status == JobStatus::Completed)
+                .then(|| "/me/export".to_string()),
+            id: job.id,
+            kind: job.kind,
+            status: job.status,
+            error: job.error,
+            created_at: job.created_at,
+            started_at: job.started_at,
+            finished_at: job.finished_at,
+        }
+    }
+}
+
+/// Confirms that whoever deletes the account holds more than a token: the current password or
+/// a second-factor code. Neither is needed within a few minutes of signing in.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct DeleteAccountRequest {
+    #[serde(default)]
+    pub password: Option<String>,
+    /// A current TOTP code or an unused recovery code
+    #[serde(default)]
+    pub code: Option<String>,
+}
+
+impl Validate for DeleteAccountRequest {
+    fn validate(&self, _rules: &ValidationRules) -> Result<(), ValidationErrors> {
+        let mut validator = Validator::new();
+        if let Some(password) = &self.password {
+            validator = validator.password_input("password", password);
+        }
+        validator.max_length("code", self.code.as_deref(), 32).finish()
+    }
+}
@@ -0,0 +1,139 @@
+use crate::validation::{Validate, ValidationErrors, ValidationRules, Validator};
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// What an API key may be used for. Keys are rejected on endpoints that do not accept
+/// any scope (account management, logout, ...).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Scope {
+    #[serde(rename = "rooms:read")]
+    RoomsRead,
+    #[serde(rename = "messages:read")]
+    MessagesRead,
+    #[serde(rename = "messages:write")]
+    MessagesWrite,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::RoomsRead => "rooms:read",
+            Scope::MessagesRead => "messages:read",
+            Scope::MessagesWrite => "messages:write",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "rooms:read" => Some(Scope::RoomsRead),
+            "messages:read" => Some(Scope::MessagesRead),
+            "messages:write" => Some(Scope::MessagesWrite),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Long-lived credential for scripts and bots. Only a SHA-256 hash of the key is stored.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "api_keys")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = true)]
+    pub id: i32,
+
+    /// The user the key authenticates as
+    pub user_id: i32,
+
+    pub name: String,
+
+    /// First characters of the key, so users can tell keys apart
+    pub prefix: String,
+
+    #[sea_orm(unique)]
+    #[serde(skip_serializing)]
+    pub key_hash: String,
+
+    /// Space-separated scopes
+    pub scopes: String,
+
+    pub created_at: DateTime,
+
+    pub last_used_at: Option<DateTime>,
+}
+
+impl Model {
+    pub fn scope_list(&self) -> Vec<Scope> {
+        self.scopes.split_whitespace().filter_map(Scope::parse).collect()
+    }
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::UserId",
+        to = "super::user::Column::Id"
+    )]
+    User,
+}
+
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateApiKeyRequest {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+}
+
+impl Validate for CreateApiKeyRequest {
+    fn validate(&self, _rules: &ValidationRules) -> Result<(), ValidationErrors> {
+        Validator::new()
+            .length("name", &self.name, 100)
+            .check("scopes", !self.scopes.is_empty(), "must not be empty")
+            .finish()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApiKeyResponse {
+    pub id: i32,
+    pub user_id: i32,
+    pub name: String,
+    pub prefix: String,
+    pub scopes: Vec<Scope>,
+    pub created_at: DateTime,
+    pub last_used_at: Option<DateTime>,
+}
+
+impl From<Model> for ApiKeyResponse {
+    fn from(key: Model) -> Self {
+        Self {
+            scopes: key.scope_list(),
+            id: key.id,
+            user_id: key.user_id,
+            name: key.name,
+            prefix: key.prefix,
+            created_at: key.created_at,
+            last_used_at: key.last_used_at,
+        }
+    }
+}
+
+/// Returned once when a key is created; the full key cannot be retrieved again
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreatedApiKeyResponse {
+    pub key: String,
+    pub api_key: ApiKeyResponse,
+}
@@ -0,0 +1,65 @@
+use super::message::MessageResponse;
+use super::room_member::RoomRole;
+use super::user::UserResponse;
+use super::user_block::BlockResponse;
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// The archive produced by an export job, kept out of the `account_jobs` rows
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+#[sea_orm(table_name = "data_exports")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub job_id: Uuid,
+
+    /// The JSON document described by `DataExport`
+    pub data: Vec<u8>,
+
+    /// The archive is deleted after this
+    pub expires_at: DateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::account_job::Entity",
+        from = "Column::JobId",
+        to = "super::account_job::Column::Id"
+    )]
+    AccountJob,
+}
+
+impl Related<super::account_job::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::AccountJob.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+/// Everything stored about a user, as handed out by `GET /me/export`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DataExport {
+    pub exported_at: DateTime,
+    pub profile: UserResponse,
+    pub avatar: Option<ExportedAvatar>,
+    pub memberships: Vec<ExportedMembership>,
+    pub messages: Vec<MessageResponse>,
+    pub blocks: Vec<BlockResponse>,
+    pub bots: Vec<UserResponse>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportedAvatar {
+    pub content_type: String,
+    /// The image, base64-encoded
+    pub data: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportedMembership {
+    pub room_id: i32,
+    pub room_name: String,
+    pub role: RoomRole,
+    pub joined_at: DateTime,
+}
@@ -0,0 +1,48 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Audit record of a login lockout, written whenever an account or IP address is locked out
+/// after too many failed attempts
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "login_lockouts")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = true)]
+    pub id: i32,
+
+    /// What was locked: "account" or "ip"
+    pub scope: String,
+
+    /// The email address or IP address that was locked
+    pub subject: String,
+
+    /// Set when the email belongs to an existing account
+    pub user_id: Option<i32>,
+
+    /// Address the attempt that triggered the lockout came from
+    pub ip_address: Option<String>,
+
+    /// Failed attempts counted when the lockout was applied
+    pub failures: i32,
+
+    pub locked_until: DateTime,
+
+    pub created_at: DateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::UserId",
+        to = "super::user::Column::Id"
+    )]
+    User,
+}
+
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
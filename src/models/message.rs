@@ -1,6 +1,22 @@
+use crate::validation::{Validate, ValidationErrors, ValidationRules, Validator};
 use sea_orm::entity::prelude::*;
 use serde::{Deserialize, Serialize};
 
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, EnumIter, DeriveActiveEnum, Serialize, Deserialize,
+)]
+#[sea_orm(rs_type = "String", db_type = "String(Some(16))")]
+#[serde(rename_all = "lowercase")]
+pub enum MessageKind {
+    /// Posted by its sender
+    #[default]
+    #[sea_orm(string_value = "user")]
+    User,
+    /// Written by the server about something the sender did, e.g. changing the topic
+    #[sea_orm(string_value = "system")]
+    System,
+}
+
 #[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
 #[sea_orm(table_name = "messages")]
 pub struct Model {
@@ -13,6 +29,11 @@ pub struct Model {
     
     pub content: String,
     
+    pub kind: MessageKind,
+    
+    /// Set from the room's `message_expiry_seconds` when the message is posted
+    pub expires_at: Option<DateTime>,
+    
     pub created_at: DateTime,
 }
 
@@ -52,6 +73,8 @@ pub struct MessageResponse {
     pub sender_id: i32,
     pub room_id: i32,
     pub content: String,
+    pub kind: MessageKind,
+    pub expires_at: Option<DateTime>,
     pub created_at: DateTime,
 }
 
@@ -62,6 +85,8 @@ impl From<Model> for MessageResponse {
             sender_id: message.sender_id,
             room_id: message.room_id,
             content: message.content,
+            kind: message.kind,
+            expires_at: message.expires_at,
             created_at: message.created_at,
         }
     }
@@ -71,3 +96,18 @@ impl From<Model> for MessageResponse {
 pub struct CreateMessageRequest {
     pub content: String,
 }
+
+impl Validate for CreateMessageRequest {
+    fn validate(&self, rules: &ValidationRules) -> Result<(), ValidationErrors> {
+        Validator::new()
+            .length("content", &self.content, rules.max_message_length)
+            .finish()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MessageHistoryQuery {
+    /// Only return messages with an id lower than this (for paging backwards)
+    pub before: Option<i32>,
+    pub limit: Option<u64>,
+}
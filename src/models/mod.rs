@@ -2,3 +2,24 @@ pub mod user;
 pub mod room;
 pub mod message;
 pub mod room_member;
+pub mod user_block;
+pub mod refresh_token;
+pub mod revoked_token;
+pub mod user_token;
+pub mod user_totp;
+pub mod recovery_code;
+pub mod user_identity;
+pub mod webauthn_credential;
+pub mod api_key;
+pub mod session;
+pub mod login_lockout;
+pub mod user_avatar;
+pub mod account_job;
+pub mod data_export;
+pub mod room_permission;
+pub mod room_ban;
+pub mod room_invite;
+pub mod room_invitation;
+pub mod room_join_request;
+pub mod room_icon;
+pub mod room_post_time;
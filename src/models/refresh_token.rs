@@ -0,0 +1,55 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "refresh_tokens")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = true)]
+    pub id: i32,
+
+    pub user_id: i32,
+
+    /// Every token minted by rotating the same login shares a family
+    pub family_id: Uuid,
+
+    #[sea_orm(unique)]
+    pub token_hash: String,
+
+    pub expires_at: DateTime,
+
+    pub created_at: DateTime,
+
+    pub revoked_at: Option<DateTime>,
+
+    /// The token this one was rotated into, if any
+    pub replaced_by_id: Option<i32>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::UserId",
+        to = "super::user::Column::Id"
+    )]
+    User,
+}
+
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LogoutRequest {
+    /// Refresh token issued alongside the access token, so it can be revoked too
+    pub refresh_token: Option<String>,
+}
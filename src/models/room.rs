@@ -1,17 +1,111 @@
-use sea_orm::entity::prelude::*;
+use crate::validation::{Validate, ValidationErrors, ValidationRules, Validator};
+use sea_orm::{entity::prelude::*, FromJsonQueryResult};
 use serde::{Deserialize, Serialize};
 
+/// Longest slow mode delay
+pub const MAX_SLOW_MODE_SECONDS: u32 = 6 * 60 * 60;
+
+/// Longest message expiry
+pub const MAX_MESSAGE_EXPIRY_SECONDS: u32 = 365 * 24 * 60 * 60;
+
+/// Who can find and read a room
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, EnumIter, DeriveActiveEnum, Serialize, Deserialize,
+)]
+#[sea_orm(rs_type = "String", db_type = "String(Some(16))")]
+#[serde(rename_all = "lowercase")]
+pub enum RoomVisibility {
+    /// Listed for everyone, who may read and post without joining
+    #[default]
+    #[sea_orm(string_value = "public")]
+    Public,
+    /// Only listed for and readable by its members
+    #[sea_orm(string_value = "private")]
+    Private,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, EnumIter, DeriveActiveEnum, Serialize, Deserialize)]
+#[sea_orm(rs_type = "String", db_type = "String(Some(16))")]
+#[serde(rename_all = "snake_case")]
+pub enum RoomKind {
+    #[sea_orm(string_value = "channel")]
+    Channel,
+    /// A private conversation between two users
+    #[sea_orm(string_value = "dm")]
+    Dm,
+    /// A private conversation between a few users, started by its owner
+    #[sea_orm(string_value = "group_dm")]
+    GroupDm,
+}
+
+impl RoomKind {
+    pub fn is_dm(&self) -> bool {
+        matches!(self, RoomKind::Dm | RoomKind::GroupDm)
+    }
+}
+
+/// Who may post in a room, on top of the `post` permission
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PostingPolicy {
+    #[default]
+    Everyone,
+    /// Only admins and the owner may post
+    Admins,
+}
+
+/// Room settings, stored as a JSON document so new ones need no migration
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, FromJsonQueryResult)]
+#[serde(default)]
+pub struct RoomSettings {
+    /// Seconds each member has to wait between messages; 0 turns slow mode off
+    pub slow_mode_seconds: u32,
+    /// Seconds after which new messages expire; 0 keeps them
+    pub message_expiry_seconds: u32,
+    pub posting: PostingPolicy,
+}
+
 #[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
 #[sea_orm(table_name = "rooms")]
 pub struct Model {
     #[sea_orm(primary_key, auto_increment = true)]
     pub id: i32,
     
+    /// Empty for DMs, which clients name after their participants
     pub name: String,
     
+    pub visibility: RoomVisibility,
+    
+    pub kind: RoomKind,
+    
+    /// One line shown next to the name
+    pub topic: Option<String>,
+    
+    pub description: Option<String>,
+    
+    /// When the icon was last changed; `None` if the room has none
+    pub icon_updated_at: Option<DateTime>,
+    
+    #[sea_orm(column_type = "JsonBinary")]
+    pub settings: RoomSettings,
+    
     pub created_at: DateTime,
 }
 
+impl Model {
+    /// Where the icon is served; the version parameter changes with every upload so the URL
+    /// can be cached forever
+    pub fn icon_url(&self) -> Option<String> {
+        self.icon_updated_at.map(|updated_at| {
+            format!(
+                "/rooms/{}/icon?v={}",
+                self.id,
+                updated_at.and_utc().timestamp()
+            )
+        })
+    }
+}
+
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
     #[sea_orm(has_many = "super::message::Entity")]
@@ -34,19 +128,116 @@ impl Related<super::room_member::Entity> for Entity {
 
 impl ActiveModelBehavior for ActiveModel {}
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct RoomResponse {
     pub id: i32,
     pub name: String,
+    pub visibility: RoomVisibility,
+    pub kind: RoomKind,
+    pub topic: Option<String>,
+    pub description: Option<String>,
+    pub icon_url: Option<String>,
+    pub settings: RoomSettings,
     pub created_at: DateTime,
 }
 
 impl From<Model> for RoomResponse {
     fn from(room: Model) -> Self {
+        let icon_url = room.icon_url();
         RoomResponse {
             id: room.id,
             name: room.name,
+            visibility: room.visibility,
+            kind: room.kind,
+            topic: room.topic,
+            description: room.description,
+            icon_url,
+            settings: room.settings,
             created_at: room.created_at,
         }
     }
 }
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateRoomRequest {
+    pub name: String,
+    #[serde(default)]
+    pub visibility: RoomVisibility,
+}
+
+impl Validate for CreateRoomRequest {
+    fn validate(&self, _rules: &ValidationRules) -> Result<(), ValidationErrors> {
+        Validator::new().length("name", &self.name, 100).finish()
+    }
+}
+
+/// Start a DM: with one other user it is found or created, with several a group DM is created
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateDmRequest {
+    pub user_ids: Vec<i32>,
+    /// Only used for group DMs
+    pub name: Option<String>,
+}
+
+impl Validate for CreateDmRequest {
+    fn validate(&self, rules: &ValidationRules) -> Result<(), ValidationErrors> {
+        let max = rules.max_group_dm_participants.saturating_sub(1);
+
+        Validator::new()
+            .check("user_ids", !self.user_ids.is_empty(), "must not be empty")
+            .check(
+                "user_ids",
+                self.user_ids.len() <= max,
+                &format!("must list at most {} users", max),
+            )
+            .max_length("name", self.name.as_deref(), 100)
+            .finish()
+    }
+}
+
+/// Change a room's details; omitted fields are left alone and blank ones are cleared
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UpdateRoomRequest {
+    pub name: Option<String>,
+    pub topic: Option<String>,
+    pub description: Option<String>,
+    pub settings: Option<UpdateRoomSettings>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UpdateRoomSettings {
+    pub slow_mode_seconds: Option<u32>,
+    pub message_expiry_seconds: Option<u32>,
+    pub posting: Option<PostingPolicy>,
+}
+
+impl Validate for UpdateRoomRequest {
+    fn validate(&self, _rules: &ValidationRules) -> Result<(), ValidationErrors> {
+        let mut validator = Validator::new();
+        if let Some(name) = &self.name {
+            validator = validator.length("name", name, 100);
+        }
+        if let Some(settings) = &self.settings {
+            validator = validator
+                .check(
+                    "settings.slow_mode_seconds",
+                    settings
+                        .slow_mode_seconds
+                        .is_none_or(|seconds| seconds <= MAX_SLOW_MODE_SECONDS),
+                    &format!("must be at most {}", MAX_SLOW_MODE_SECONDS),
+                )
+                .check(
+                    "settings.message_expiry_seconds",
+                    settings
+                        .message_expiry_seconds
+                        .is_none_or(|seconds| seconds <= MAX_MESSAGE_EXPIRY_SECONDS),
+                    &format!("must be at most {}", MAX_MESSAGE_EXPIRY_SECONDS),
+                );
+        }
+
+        validator
+            .max_length("topic", self.topic.as_deref(), 250)
+            .max_length("description", self.description.as_deref(), 2000)
+            .finish()
+    }
+}
@@ -0,0 +1,70 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// A user kept out of a room: they can neither read, post nor rejoin it
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "room_bans")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = true)]
+    pub id: i32,
+
+    pub room_id: i32,
+
+    pub user_id: i32,
+
+    /// The member who issued the ban
+    pub banned_by: Option<i32>,
+
+    pub reason: Option<String>,
+
+    pub created_at: DateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::room::Entity",
+        from = "Column::RoomId",
+        to = "super::room::Column::Id"
+    )]
+    Room,
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::UserId",
+        to = "super::user::Column::Id"
+    )]
+    User,
+}
+
+impl Related<super::room::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Room.def()
+    }
+}
+
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BanResponse {
+    pub user_id: i32,
+    pub banned_by: Option<i32>,
+    pub reason: Option<String>,
+    pub created_at: DateTime,
+}
+
+impl From<Model> for BanResponse {
+    fn from(ban: Model) -> Self {
+        BanResponse {
+            user_id: ban.user_id,
+            banned_by: ban.banned_by,
+            reason: ban.reason,
+            created_at: ban.created_at,
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use super::room::{self, RoomResponse};
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// A pending invitation of one user into a room; it is deleted once answered
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "room_invitations")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = true)]
+    pub id: i32,
+
+    pub room_id: i32,
+
+    /// The invited user
+    pub user_id: i32,
+
+    pub invited_by: Option<i32>,
+
+    pub created_at: DateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::room::Entity",
+        from = "Column::RoomId",
+        to = "super::room::Column::Id"
+    )]
+    Room,
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::UserId",
+        to = "super::user::Column::Id"
+    )]
+    User,
+}
+
+impl Related<super::room::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Room.def()
+    }
+}
+
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateInvitationRequest {
+    pub user_id: i32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct InvitationResponse {
+    pub id: i32,
+    pub room: RoomResponse,
+    pub user_id: i32,
+    pub invited_by: Option<i32>,
+    pub created_at: DateTime,
+}
+
+impl InvitationResponse {
+    pub fn new(invitation: Model, room: room::Model) -> Self {
+        Self {
+            id: invitation.id,
+            room: room.into(),
+            user_id: invitation.user_id,
+            invited_by: invitation.invited_by,
+            created_at: invitation.created_at,
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use super::room_member::RoomRole;
+use crate::validation::{Validate, ValidationErrors, ValidationRules, Validator};
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Longest an invite can stay valid
+pub const MAX_INVITE_LIFETIME_HOURS: i64 = 24 * 365;
+
+/// A link anyone can use to join a room. Only a SHA-256 hash of the code is stored.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "room_invites")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = true)]
+    pub id: i32,
+
+    pub room_id: i32,
+
+    pub created_by: Option<i32>,
+
+    /// First characters of the code, so invites can be told apart
+    pub prefix: String,
+
+    #[sea_orm(unique)]
+    #[serde(skip_serializing)]
+    pub code_hash: String,
+
+    /// Role given to whoever joins with the invite
+    pub role: RoomRole,
+
+    /// `None` for unlimited uses
+    pub max_uses: Option<i32>,
+
+    pub uses: i32,
+
+    pub expires_at: Option<DateTime>,
+
+    pub created_at: DateTime,
+}
+
+impl Model {
+    /// Whether the invite can no longer be used
+    pub fn is_spent(&self, now: DateTime) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+            || self.max_uses.is_some_and(|max_uses| self.uses >= max_uses)
+    }
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::room::Entity",
+        from = "Column::RoomId",
+        to = "super::room::Column::Id"
+    )]
+    Room,
+}
+
+impl Related<super::room::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Room.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateInviteRequest {
+    /// Never expires if omitted
+    pub expires_in_hours: Option<i64>,
+    /// Unlimited if omitted
+    pub max_uses: Option<i32>,
+    /// Defaults to `member`
+    pub role: Option<RoomRole>,
+}
+
+impl Validate for CreateInviteRequest {
+    fn validate(&self, _rules: &ValidationRules) -> Result<(), ValidationErrors> {
+        Validator::new()
+            .check(
+                "expires_in_hours",
+                self.expires_in_hours
+                    .is_none_or(|hours| (1..=MAX_INVITE_LIFETIME_HOURS).contains(&hours)),
+                &format!("must be between 1 and {}", MAX_INVITE_LIFETIME_HOURS),
+            )
+            .check(
+                "max_uses",
+                self.max_uses.is_none_or(|uses| uses >= 1),
+                "must be at least 1",
+            )
+            .finish()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InviteResponse {
+    pub id: i32,
+    pub room_id: i32,
+    pub created_by: Option<i32>,
+    pub prefix: String,
+    pub role: RoomRole,
+    pub max_uses: Option<i32>,
+    pub uses: i32,
+    pub expires_at: Option<DateTime>,
+    pub created_at: DateTime,
+}
+
+impl From<Model> for InviteResponse {
+    fn from(invite: Model) -> Self {
+        Self {
+            id: invite.id,
+            room_id: invite.room_id,
+            created_by: invite.created_by,
+            prefix: invite.prefix,
+            role: invite.role,
+            max_uses: invite.max_uses,
+            uses: invite.uses,
+            expires_at: invite.expires_at,
+            created_at: invite.created_at,
+        }
+    }
+}
+
+/// Returned once when an invite is created; the full code cannot be retrieved again
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreatedInviteResponse {
+    pub code: String,
+    pub invite: InviteResponse,
+}
@@ -0,0 +1,92 @@
+use super::user;
+use crate::validation::{Validate, ValidationErrors, ValidationRules, Validator};
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// A user asking to be let into a private room; it is deleted once decided
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "room_join_requests")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = true)]
+    pub id: i32,
+
+    pub room_id: i32,
+
+    pub user_id: i32,
+
+    pub message: Option<String>,
+
+    pub created_at: DateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::room::Entity",
+        from = "Column::RoomId",
+        to = "super::room::Column::Id"
+    )]
+    Room,
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::UserId",
+        to = "super::user::Column::Id"
+    )]
+    User,
+}
+
+impl Related<super::room::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Room.def()
+    }
+}
+
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateJoinRequest {
+    /// A note for the people deciding
+    pub message: Option<String>,
+}
+
+impl Validate for CreateJoinRequest {
+    fn validate(&self, _rules: &ValidationRules) -> Result<(), ValidationErrors> {
+        Validator::new()
+            .max_length("message", self.message.as_deref(), 500)
+            .finish()
+    }
+}
+
+/// A join request with the public profile of who is asking
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct JoinRequestResponse {
+    pub id: i32,
+    pub room_id: i32,
+    pub user_id: i32,
+    pub username: String,
+    pub display_name: Option<String>,
+    pub avatar_url: Option<String>,
+    pub message: Option<String>,
+    pub created_at: DateTime,
+}
+
+impl JoinRequestResponse {
+    pub fn new(request: Model, user: &user::Model) -> Self {
+        Self {
+            id: request.id,
+            room_id: request.room_id,
+            user_id: request.user_id,
+            username: user.username.clone(),
+            display_name: user.display_name.clone(),
+            avatar_url: user.avatar_url(),
+            message: request.message,
+            created_at: request.created_at,
+        }
+    }
+}
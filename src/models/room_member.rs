@@ -1,6 +1,57 @@
+use super::user;
+use crate::validation::{Validate, ValidationErrors, ValidationRules, Validator};
 use sea_orm::entity::prelude::*;
 use serde::{Deserialize, Serialize};
 
+/// A member's standing in a room, from most to least powerful
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    EnumIter,
+    DeriveActiveEnum,
+    Serialize,
+    Deserialize,
+)]
+#[sea_orm(rs_type = "String", db_type = "String(Some(16))")]
+#[serde(rename_all = "kebab-case")]
+pub enum RoomRole {
+    /// Every room has exactly one; only ownership transfer changes it
+    #[sea_orm(string_value = "owner")]
+    Owner,
+    #[sea_orm(string_value = "admin")]
+    Admin,
+    #[sea_orm(string_value = "moderator")]
+    Moderator,
+    #[sea_orm(string_value = "member")]
+    Member,
+    /// May read but not post
+    #[sea_orm(string_value = "read-only")]
+    ReadOnly,
+}
+
+impl RoomRole {
+    /// Higher ranks may manage members of lower ranks
+    pub fn rank(&self) -> u8 {
+        match self {
+            RoomRole::Owner => 4,
+            RoomRole::Admin => 3,
+            RoomRole::Moderator => 2,
+            RoomRole::Member => 1,
+            RoomRole::ReadOnly => 0,
+        }
+    }
+
+    pub fn outranks(&self, other: RoomRole) -> bool {
+        self.rank() > other.rank()
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
 #[sea_orm(table_name = "room_members")]
 pub struct Model {
@@ -11,6 +62,8 @@ pub struct Model {
     
     pub user_id: i32,
     
+    pub role: RoomRole,
+    
     pub joined_at: DateTime,
 }
 
@@ -43,3 +96,57 @@ impl Related<super::user::Entity> for Entity {
 }
 
 impl ActiveModelBehavior for ActiveModel {}
+
+/// A room member with their public profile
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MemberResponse {
+    pub user_id: i32,
+    pub username: String,
+    pub display_name: Option<String>,
+    pub avatar_url: Option<String>,
+    pub is_bot: bool,
+    pub role: RoomRole,
+    pub joined_at: DateTime,
+}
+
+impl MemberResponse {
+    pub fn new(member: Model, user: &user::Model) -> Self {
+        Self {
+            user_id: member.user_id,
+            username: user.username.clone(),
+            display_name: user.display_name.clone(),
+            avatar_url: user.avatar_url(),
+            is_bot: user.is_bot,
+            role: member.role,
+            joined_at: member.joined_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AddMemberRequest {
+    pub user_id: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UpdateMemberRequest {
+    pub role: RoomRole,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TransferOwnershipRequest {
+    pub user_id: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BanMemberRequest {
+    pub reason: Option<String>,
+}
+
+impl Validate for BanMemberRequest {
+    fn validate(&self, _rules: &ValidationRules) -> Result<(), ValidationErrors> {
+        Validator::new()
+            .max_length("reason", self.reason.as_deref(), 500)
+            .finish()
+    }
+}
@@ -0,0 +1,138 @@
+use super::room_member::RoomRole;
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// Something a member may be allowed to do in a room
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    EnumIter,
+    DeriveActiveEnum,
+    Serialize,
+    Deserialize,
+)]
+#[sea_orm(rs_type = "String", db_type = "String(Some(32))")]
+#[serde(rename_all = "kebab-case")]
+pub enum Permission {
+    /// Send messages
+    #[sea_orm(string_value = "post")]
+    Post,
+    /// Edit messages of other members
+    #[sea_orm(string_value = "edit-others")]
+    EditOthers,
+    /// Delete messages of other members
+    #[sea_orm(string_value = "delete-others")]
+    DeleteOthers,
+    /// Pin and unpin messages
+    #[sea_orm(string_value = "pin")]
+    Pin,
+    /// Add people to the room
+    #[sea_orm(string_value = "invite")]
+    Invite,
+    /// Change member roles and the room's permission overrides
+    #[sea_orm(string_value = "manage-room")]
+    ManageRoom,
+    /// Remove and ban members
+    #[sea_orm(string_value = "ban")]
+    Ban,
+}
+
+impl Permission {
+    /// What `role` may do in rooms without overrides
+    pub fn defaults(role: RoomRole) -> BTreeSet<Permission> {
+        use Permission::*;
+
+        let permissions: &[Permission] = match role {
+            RoomRole::Owner | RoomRole::Admin => {
+                &[Post, EditOthers, DeleteOthers, Pin, Invite, ManageRoom, Ban]
+            }
+            RoomRole::Moderator => &[Post, DeleteOthers, Pin, Invite, Ban],
+            RoomRole::Member => &[Post, Invite],
+            RoomRole::ReadOnly => &[],
+        };
+        permissions.iter().copied().collect()
+    }
+}
+
+impl fmt::Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_value())
+    }
+}
+
+/// A room's deviation from the default permissions of a role. Owners cannot be overridden.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "room_role_permissions")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = true)]
+    pub id: i32,
+
+    pub room_id: i32,
+
+    pub role: RoomRole,
+
+    pub permission: Permission,
+
+    /// Whether the role gains (`true`) or loses (`false`) the permission
+    pub allowed: bool,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::room::Entity",
+        from = "Column::RoomId",
+        to = "super::room::Column::Id"
+    )]
+    Room,
+}
+
+impl Related<super::room::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Room.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+/// Grant (`allowed: true`) or deny a permission to a role in one room
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PermissionOverride {
+    pub role: RoomRole,
+    pub permission: Permission,
+    pub allowed: bool,
+}
+
+impl From<Model> for PermissionOverride {
+    fn from(entry: Model) -> Self {
+        PermissionOverride {
+            role: entry.role,
+            permission: entry.permission,
+            allowed: entry.allowed,
+        }
+    }
+}
+
+/// The permissions of every role in a room, and the overrides that produced them
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RoomPermissionsResponse {
+    pub roles: BTreeMap<RoomRole, BTreeSet<Permission>>,
+    pub overrides: Vec<PermissionOverride>,
+}
+
+/// What the caller may do in a room
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RoomAccessResponse {
+    pub room_id: i32,
+    /// `None` if the caller has not joined
+    pub role: Option<RoomRole>,
+    pub permissions: BTreeSet<Permission>,
+}
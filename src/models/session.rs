@@ -0,0 +1,70 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// A signed-in device. The id doubles as the refresh token family and the `sid` claim of the
+/// access tokens issued to it.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "sessions")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: Uuid,
+
+    pub user_id: i32,
+
+    /// Human-readable description of the device, e.g. "Firefox on Linux"
+    pub device_label: String,
+
+    pub user_agent: Option<String>,
+
+    /// Address the session was last used from
+    pub ip_address: Option<String>,
+
+    pub created_at: DateTime,
+
+    /// Updated on login and whenever the session refreshes its access token
+    pub last_used_at: DateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::UserId",
+        to = "super::user::Column::Id"
+    )]
+    User,
+}
+
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionResponse {
+    pub id: Uuid,
+    pub device_label: String,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub created_at: DateTime,
+    pub last_used_at: DateTime,
+    /// Whether this is the session making the request
+    pub current: bool,
+}
+
+impl SessionResponse {
+    pub fn new(session: Model, current_id: Option<Uuid>) -> Self {
+        Self {
+            current: current_id == Some(session.id),
+            id: session.id,
+            device_label: session.device_label,
+            user_agent: session.user_agent,
+            ip_address: session.ip_address,
+            created_at: session.created_at,
+            last_used_at: session.last_used_at,
+        }
+    }
+}
@@ -1,6 +1,23 @@
+use crate::validation::{Validate, ValidationErrors, ValidationRules, Validator};
 use sea_orm::entity::prelude::*;
+use sea_orm::sea_query::SimpleExpr;
 use serde::{Deserialize, Serialize};
 
+/// What a user may do across the whole server
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, EnumIter, DeriveActiveEnum, Serialize, Deserialize,
+)]
+#[sea_orm(rs_type = "String", db_type = "String(Some(16))")]
+#[serde(rename_all = "lowercase")]
+pub enum UserRole {
+    #[default]
+    #[sea_orm(string_value = "user")]
+    User,
+    /// May use the `/admin` endpoints
+    #[sea_orm(string_value = "admin")]
+    Admin,
+}
+
 #[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
 #[sea_orm(table_name = "users")]
 pub struct Model {
@@ -15,6 +32,74 @@ pub struct Model {
     pub username: String,
     
     pub created_at: DateTime,
+    
+    /// Access tokens issued before this instant are rejected (set by logout-all)
+    pub tokens_valid_after: Option<DateTime>,
+    
+    pub email_verified_at: Option<DateTime>,
+    
+    /// Bot accounts authenticate with API keys only and cannot sign in
+    pub is_bot: bool,
+    
+    /// The user who created and manages this bot
+    pub bot_owner_id: Option<i32>,
+    
+    /// Name shown instead of the username, if set
+    pub display_name: Option<String>,
+    
+    pub bio: Option<String>,
+    
+    /// Short free-form status, e.g. "On holiday until Monday"
+    pub status_text: Option<String>,
+    
+    /// IANA time zone name, e.g. "Europe/Berlin"
+    pub timezone: Option<String>,
+    
+    /// When the avatar was last changed; `None` if the user has none
+    pub avatar_updated_at: Option<DateTime>,
+    
+    /// Leave the user out of directory search results
+    pub hidden_from_directory: bool,
+    
+    /// Show the email address on the public profile
+    pub email_visible: bool,
+    
+    /// When the account was deleted. The row stays, stripped of personal data, so the user's
+    /// messages keep a "deleted user" attribution.
+    pub deleted_at: Option<DateTime>,
+    
+    pub role: UserRole,
+    
+    /// Set by an admin; disabled users cannot sign in and their tokens are refused
+    pub disabled_at: Option<DateTime>,
+    
+    /// Set by an admin; password logins are refused until the password is reset
+    pub password_reset_required: bool,
+}
+
+impl Model {
+    /// Where the avatar is served; the version parameter changes with every upload so the
+    /// image can be cached indefinitely
+    pub fn avatar_url(&self) -> Option<String> {
+        self.avatar_updated_at.map(|updated_at| {
+            format!(
+                "/users/{}/avatar?v={}",
+                self.id,
+                updated_at.and_utc().timestamp_millis()
+            )
+        })
+    }
+}
+
+/// Emails are stored and compared trimmed and lowercased, so the same address cannot make two
+/// accounts
+pub fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+/// Matches the user with `email`, including accounts stored before emails were normalized
+pub fn email_is(email: &str) -> SimpleExpr {
+    Expr::cust_with_values("lower(email) = $1", [normalize_email(email)])
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -47,25 +132,269 @@ pub struct RegisterRequest {
     pub username: String,
 }
 
+impl Validate for RegisterRequest {
+    fn validate(&self, rules: &ValidationRules) -> Result<(), ValidationErrors> {
+        Validator::new()
+            .email("email", &self.email)
+            .username("username", &self.username)
+            .password("password", &self.password, &rules.password)
+            .finish()
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LoginRequest {
     pub email: String,
     pub password: String,
 }
 
+impl Validate for LoginRequest {
+    fn validate(&self, _rules: &ValidationRules) -> Result<(), ValidationErrors> {
+        Validator::new()
+            .email_input("email", &self.email)
+            .password_input("password", &self.password)
+            .finish()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VerifyEmailRequest {
+    pub token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ResendVerificationRequest {
+    pub email: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ForgotPasswordRequest {
+    pub email: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ResetPasswordRequest {
+    pub token: String,
+    pub new_password: String,
+}
+
+impl Validate for ResetPasswordRequest {
+    fn validate(&self, rules: &ValidationRules) -> Result<(), ValidationErrors> {
+        Validator::new()
+            .password("new_password", &self.new_password, &rules.password)
+            .finish()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MagicLinkRequest {
+    pub email: String,
+}
+
+/// Returned by `POST /auth/magic-link`. The nonce must be kept on the requesting device and
+/// sent back with the token from the email.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MagicLinkResponse {
+    pub nonce: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MagicLinkVerifyQuery {
+    pub token: String,
+    pub nonce: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+impl Validate for ChangePasswordRequest {
+    fn validate(&self, rules: &ValidationRules) -> Result<(), ValidationErrors> {
+        Validator::new()
+            .password_input("current_password", &self.current_password)
+            .password("new_password", &self.new_password, &rules.password)
+            .finish()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateBotRequest {
+    pub username: String,
+}
+
+impl Validate for CreateBotRequest {
+    fn validate(&self, _rules: &ValidationRules) -> Result<(), ValidationErrors> {
+        Validator::new()
+            .username("username", &self.username)
+            .finish()
+    }
+}
+
+/// Changes to the caller's profile. Absent fields are left alone; empty strings clear the
+/// optional ones.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UpdateProfileRequest {
+    pub username: Option<String>,
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub status_text: Option<String>,
+    pub timezone: Option<String>,
+    pub hidden_from_directory: Option<bool>,
+    pub email_visible: Option<bool>,
+}
+
+impl Validate for UpdateProfileRequest {
+    fn validate(&self, _rules: &ValidationRules) -> Result<(), ValidationErrors> {
+        let mut validator = Validator::new();
+        if let Some(username) = &self.username {
+            validator = validator.username("username", username);
+        }
+        if let Some(timezone) = self.timezone.as_deref().map(str::trim) {
+            validator = validator.check(
+                "timezone",
+                timezone.is_empty() || timezone.parse::<chrono_tz::Tz>().is_ok(),
+                "must be an IANA time zone name, e.g. Europe/Berlin",
+            );
+        }
+
+        validator
+            .max_length("display_name", self.display_name.as_deref(), 64)
+            .max_length("bio", self.bio.as_deref(), 500)
+            .max_length("status_text", self.status_text.as_deref(), 140)
+            .finish()
+    }
+}
+
+/// The caller's own account, including private details
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UserResponse {
     pub id: i32,
     pub email: String,
     pub username: String,
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub status_text: Option<String>,
+    pub timezone: Option<String>,
+    pub avatar_url: Option<String>,
+    pub email_verified: bool,
+    pub is_bot: bool,
+    pub role: UserRole,
+    pub hidden_from_directory: bool,
+    pub email_visible: bool,
+    pub created_at: DateTime,
 }
 
 impl From<Model> for UserResponse {
     fn from(user: Model) -> Self {
         UserResponse {
+            avatar_url: user.avatar_url(),
             id: user.id,
             email: user.email,
             username: user.username,
+            display_name: user.display_name,
+            bio: user.bio,
+            status_text: user.status_text,
+            timezone: user.timezone,
+            email_verified: user.email_verified_at.is_some(),
+            is_bot: user.is_bot,
+            role: user.role,
+            hidden_from_directory: user.hidden_from_directory,
+            email_visible: user.email_visible,
+            created_at: user.created_at,
         }
     }
 }
+
+/// What anyone signed in can see about a user
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProfileResponse {
+    pub id: i32,
+    /// Only present if the user has made it visible
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    pub username: String,
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub status_text: Option<String>,
+    pub timezone: Option<String>,
+    pub avatar_url: Option<String>,
+    pub is_bot: bool,
+    /// The account was deleted; only its id and placeholder name remain
+    pub deleted: bool,
+    pub created_at: DateTime,
+}
+
+impl From<Model> for ProfileResponse {
+    fn from(user: Model) -> Self {
+        ProfileResponse {
+            avatar_url: user.avatar_url(),
+            email: user.email_visible.then_some(user.email),
+            id: user.id,
+            username: user.username,
+            display_name: user.display_name,
+            bio: user.bio,
+            status_text: user.status_text,
+            timezone: user.timezone,
+            is_bot: user.is_bot,
+            deleted: user.deleted_at.is_some(),
+            created_at: user.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UserSearchQuery {
+    /// Matched against the start of usernames and display names, then by similarity
+    pub q: String,
+    pub limit: Option<u64>,
+    pub offset: Option<u64>,
+}
+
+impl Validate for UserSearchQuery {
+    fn validate(&self, _rules: &ValidationRules) -> Result<(), ValidationErrors> {
+        Validator::new().length("q", &self.q, 64).finish()
+    }
+}
+
+/// A user as seen by admins: the full account plus its moderation state
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AdminUserResponse {
+    #[serde(flatten)]
+    pub user: UserResponse,
+    pub bot_owner_id: Option<i32>,
+    pub disabled_at: Option<DateTime>,
+    pub password_reset_required: bool,
+    pub deleted_at: Option<DateTime>,
+}
+
+impl From<Model> for AdminUserResponse {
+    fn from(user: Model) -> Self {
+        AdminUserResponse {
+            bot_owner_id: user.bot_owner_id,
+            disabled_at: user.disabled_at,
+            password_reset_required: user.password_reset_required,
+            deleted_at: user.deleted_at,
+            user: user.into(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AdminUserQuery {
+    /// Matched against the start of emails, usernames and display names
+    pub q: Option<String>,
+    pub role: Option<UserRole>,
+    pub disabled: Option<bool>,
+    pub limit: Option<u64>,
+    pub offset: Option<u64>,
+}
+
+impl Validate for AdminUserQuery {
+    fn validate(&self, _rules: &ValidationRules) -> Result<(), ValidationErrors> {
+        Validator::new()
+            .max_length("q", self.q.as_deref(), 254)
+            .finish()
+    }
+}
@@ -0,0 +1,70 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// How strongly a user wants to ignore another user
+#[derive(Clone, Copy, Debug, PartialEq, Eq, EnumIter, DeriveActiveEnum, Serialize, Deserialize)]
+#[sea_orm(rs_type = "String", db_type = "String(Some(10))")]
+#[serde(rename_all = "lowercase")]
+pub enum BlockKind {
+    /// Hides the target's messages and prevents them from starting DMs
+    #[sea_orm(string_value = "block")]
+    Block,
+    /// Only hides the target's messages
+    #[sea_orm(string_value = "mute")]
+    Mute,
+}
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "user_blocks")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = true)]
+    pub id: i32,
+
+    pub user_id: i32,
+
+    pub target_id: i32,
+
+    pub kind: BlockKind,
+
+    pub created_at: DateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::UserId",
+        to = "super::user::Column::Id"
+    )]
+    User,
+}
+
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlockResponse {
+    pub user_id: i32,
+    pub kind: BlockKind,
+    pub created_at: DateTime,
+}
+
+impl From<Model> for BlockResponse {
+    fn from(block: Model) -> Self {
+        BlockResponse {
+            user_id: block.target_id,
+            kind: block.kind,
+            created_at: block.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlockUserRequest {
+    pub kind: BlockKind,
+}
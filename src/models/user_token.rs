@@ -0,0 +1,59 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// What a single-use token may be exchanged for
+#[derive(Clone, Copy, Debug, PartialEq, Eq, EnumIter, DeriveActiveEnum, Serialize, Deserialize)]
+#[sea_orm(rs_type = "String", db_type = "String(Some(32))")]
+#[serde(rename_all = "snake_case")]
+pub enum TokenPurpose {
+    #[sea_orm(string_value = "email_verification")]
+    EmailVerification,
+    #[sea_orm(string_value = "password_reset")]
+    PasswordReset,
+    #[sea_orm(string_value = "magic_link")]
+    MagicLink,
+}
+
+/// Single-use, time-limited token delivered out of band (e.g. by email).
+/// Only a SHA-256 hash of the token is stored.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "user_tokens")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = true)]
+    pub id: i32,
+
+    pub user_id: i32,
+
+    pub purpose: TokenPurpose,
+
+    #[sea_orm(unique)]
+    pub token_hash: String,
+
+    /// SHA-256 of the nonce held by the device that asked for the token, if it is
+    /// bound to one
+    pub nonce_hash: Option<String>,
+
+    pub expires_at: DateTime,
+
+    pub used_at: Option<DateTime>,
+
+    pub created_at: DateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::UserId",
+        to = "super::user::Column::Id"
+    )]
+    User,
+}
+
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,73 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// TOTP second factor for a user. The row exists from enrollment; `enabled_at` is set once the
+/// user has proven they can produce codes.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "user_totp")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub user_id: i32,
+
+    /// Base32-encoded shared secret
+    #[serde(skip_serializing)]
+    pub secret: String,
+
+    pub enabled_at: Option<DateTime>,
+
+    /// Last accepted time step, so a code cannot be replayed
+    pub last_used_step: Option<i64>,
+
+    pub created_at: DateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::UserId",
+        to = "super::user::Column::Id"
+    )]
+    User,
+}
+
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TotpEnrollmentResponse {
+    /// Base32 secret for manual entry
+    pub secret: String,
+    pub otpauth_uri: String,
+    /// The otpauth URI rendered as an SVG QR code
+    pub qr_svg: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ConfirmTotpRequest {
+    pub code: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecoveryCodesResponse {
+    /// Shown once; only hashes are stored
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DisableTotpRequest {
+    pub password: String,
+    pub code: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MfaLoginRequest {
+    pub mfa_token: String,
+    /// A current TOTP code or an unused recovery code
+    pub code: String,
+}
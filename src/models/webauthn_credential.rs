@@ -0,0 +1,203 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// A passkey (WebAuthn public key credential) registered by a user
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "webauthn_credentials")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = true)]
+    pub id: i32,
+
+    pub user_id: i32,
+
+    /// Credential ID chosen by the authenticator, base64url-encoded
+    #[sea_orm(unique)]
+    pub credential_id: String,
+
+    /// COSE-encoded public key from the attested credential data
+    #[serde(skip_serializing)]
+    pub public_key: Vec<u8>,
+
+    /// Last signature counter reported by the authenticator (0 if it does not keep one)
+    pub sign_count: i64,
+
+    /// Authenticator model identifier (all zeros when not disclosed)
+    pub aaguid: Uuid,
+
+    /// Label chosen by the user
+    pub name: String,
+
+    pub created_at: DateTime,
+
+    pub last_used_at: Option<DateTime>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::UserId",
+        to = "super::user::Column::Id"
+    )]
+    User,
+}
+
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PasskeyResponse {
+    pub id: i32,
+    pub name: String,
+    pub aaguid: Uuid,
+    pub created_at: DateTime,
+    pub last_used_at: Option<DateTime>,
+}
+
+impl From<Model> for PasskeyResponse {
+    fn from(credential: Model) -> Self {
+        Self {
+            id: credential.id,
+            name: credential.name,
+            aaguid: credential.aaguid,
+            created_at: credential.created_at,
+            last_used_at: credential.last_used_at,
+        }
+    }
+}
+
+// WebAuthn ceremony options, in the JSON shape accepted by
+// `PublicKeyCredential.parseCreationOptionsFromJSON` / `parseRequestOptionsFromJSON`.
+// Binary values are base64url-encoded.
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialCreationOptions {
+    pub public_key: PublicKeyCredentialCreationOptions,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PublicKeyCredentialCreationOptions {
+    pub rp: RelyingParty,
+    pub user: PublicKeyCredentialUser,
+    pub challenge: String,
+    pub pub_key_cred_params: Vec<PublicKeyCredentialParameters>,
+    pub timeout: u64,
+    pub exclude_credentials: Vec<PublicKeyCredentialDescriptor>,
+    pub authenticator_selection: AuthenticatorSelection,
+    pub attestation: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RelyingParty {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PublicKeyCredentialUser {
+    pub id: String,
+    pub name: String,
+    pub display_name: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PublicKeyCredentialParameters {
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// COSE algorithm identifier
+    pub alg: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PublicKeyCredentialDescriptor {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub id: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticatorSelection {
+    pub resident_key: String,
+    pub user_verification: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialRequestOptions {
+    pub public_key: PublicKeyCredentialRequestOptions,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PublicKeyCredentialRequestOptions {
+    pub challenge: String,
+    pub rp_id: String,
+    pub timeout: u64,
+    pub allow_credentials: Vec<PublicKeyCredentialDescriptor>,
+    pub user_verification: String,
+}
+
+// Credentials returned by the browser, as produced by `PublicKeyCredential.toJSON()`
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RegistrationCredential {
+    pub raw_id: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub response: AttestationResponse,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub attestation_object: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticationCredential {
+    pub raw_id: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub response: AssertionResponse,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub authenticator_data: String,
+    pub signature: String,
+    pub user_handle: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RegisterPasskeyRequest {
+    /// Label for the passkey, e.g. "Laptop"
+    pub name: Option<String>,
+    pub credential: RegistrationCredential,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct PasskeyLoginStartRequest {
+    /// Limit the login to this account's passkeys; omit for discoverable credentials
+    pub email: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasskeyLoginRequest {
+    pub credential: AuthenticationCredential,
+}
@@ -0,0 +1,91 @@
+use crate::errors::Result;
+use crate::models::account_job::{DeleteAccountRequest, JobKind, JobResponse};
+use crate::services::jwt_service::Claims;
+use crate::utils::ClientInfo;
+use crate::validation::ValidatedJson;
+use crate::AppState;
+use axum::{
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use uuid::Uuid;
+
+/// Start collecting the caller's personal data; poll the returned job until it completes
+pub async fn start_export(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> Result<impl IntoResponse> {
+    let job = state
+        .account_job_service
+        .start(claims.user_id()?, JobKind::Export)
+        .await?;
+    Ok(accepted(job.into()))
+}
+
+/// Download the archive of the caller's latest completed export
+pub async fn download_export(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> Result<impl IntoResponse> {
+    let user_id = claims.user_id()?;
+    let (job, archive) = state.account_job_service.latest_export(user_id).await?;
+
+    let filename = format!(
+        "export-{}-{}.json",
+        user_id,
+        job.finished_at.unwrap_or(job.created_at).format("%Y%m%d")
+    );
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/json".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", filename),
+            ),
+            (header::CACHE_CONTROL, "no-store".to_string()),
+        ],
+        archive,
+    ))
+}
+
+/// Delete the caller's account. Messages stay in their rooms, attributed to a deleted user;
+/// everything else is removed and all of the caller's tokens stop working. The caller must
+/// re-authenticate first, since the deletion cannot be undone.
+pub async fn delete_me(
+    State(state): State<AppState>,
+    claims: Claims,
+    client: ClientInfo,
+    ValidatedJson(req): ValidatedJson<DeleteAccountRequest>,
+) -> Result<impl IntoResponse> {
+    state
+        .auth_service
+        .reauthenticate(&claims, req.password.as_deref(), req.code.as_deref(), &client)
+        .await?;
+
+    let job = state
+        .account_job_service
+        .start(claims.user_id()?, JobKind::Deletion)
+        .await?;
+    Ok(accepted(job.into()))
+}
+
+/// Status of an export or deletion job. Unauthenticated, since deletion revokes the tokens
+/// that started it; job ids are random and the status reveals nothing personal.
+pub async fn get_job(
+    State(state): State<AppState>,
+    Path(job_id): Path<Uuid>,
+) -> Result<Json<JobResponse>> {
+    let job = state.account_job_service.get(job_id).await?;
+    Ok(Json(job.into()))
+}
+
+fn accepted(job: JobResponse) -> impl IntoResponse {
+    (
+        StatusCode::ACCEPTED,
+        [(header::LOCATION, format!("/jobs/{}", job.id))],
+        Json(job),
+    )
+}
@@ -0,0 +1,119 @@
+use crate::errors::Result;
+use crate::models::user::{AdminUserQuery, AdminUserResponse};
+use crate::routes::room::forget_room;
+use crate::services::admin_service::SystemStats;
+use crate::services::connection_registry::CloseReason;
+use crate::utils::Admin;
+use crate::validation::Validate;
+use crate::AppState;
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+
+pub async fn list_users(
+    State(state): State<AppState>,
+    Query(query): Query<AdminUserQuery>,
+    _admin: Admin,
+) -> Result<Json<Vec<AdminUserResponse>>> {
+    query.validate(&state.validation)?;
+
+    let users = state.admin_service.list_users(&query).await?;
+    Ok(Json(users.into_iter().map(Into::into).collect()))
+}
+
+pub async fn get_user(
+    State(state): State<AppState>,
+    Path(user_id): Path<i32>,
+    _admin: Admin,
+) -> Result<Json<AdminUserResponse>> {
+    let user = state.admin_service.get_user(user_id).await?;
+    Ok(Json(user.into()))
+}
+
+/// Disable an account: it is signed out everywhere and cannot sign in or use API keys
+pub async fn disable_user(
+    State(state): State<AppState>,
+    Path(user_id): Path<i32>,
+    Admin(claims): Admin,
+) -> Result<Json<AdminUserResponse>> {
+    let user = state
+        .admin_service
+        .set_disabled(claims.user_id()?, user_id, true)
+        .await?;
+
+    state.auth_service.logout_all(user_id).await?;
+    state
+        .connections
+        .close_user(user_id, CloseReason::AccountDisabled);
+
+    Ok(Json(user.into()))
+}
+
+pub async fn enable_user(
+    State(state): State<AppState>,
+    Path(user_id): Path<i32>,
+    Admin(claims): Admin,
+) -> Result<Json<AdminUserResponse>> {
+    let user = state
+        .admin_service
+        .set_disabled(claims.user_id()?, user_id, false)
+        .await?;
+    Ok(Json(user.into()))
+}
+
+/// Sign the user out everywhere and refuse password logins until they pick a new password
+pub async fn force_password_reset(
+    State(state): State<AppState>,
+    Path(user_id): Path<i32>,
+    Admin(claims): Admin,
+) -> Result<StatusCode> {
+    state.auth_service.force_password_reset(user_id).await?;
+    state
+        .connections
+        .close_user(user_id, CloseReason::TokenRevoked);
+
+    tracing::warn!(
+        "Admin {} forced a password reset for user {}",
+        claims.user_id()?,
+        user_id
+    );
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Delete a room together with its messages
+pub async fn delete_room(
+    State(state): State<AppState>,
+    Path(room_id): Path<i32>,
+    Admin(claims): Admin,
+) -> Result<StatusCode> {
+    let deleted = state
+        .admin_service
+        .delete_room(claims.user_id()?, room_id)
+        .await?;
+
+    if deleted {
+        forget_room(&state, room_id).await;
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn delete_message(
+    State(state): State<AppState>,
+    Path(message_id): Path<i32>,
+    Admin(claims): Admin,
+) -> Result<StatusCode> {
+    state
+        .admin_service
+        .delete_message(claims.user_id()?, message_id)
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn stats(State(state): State<AppState>, _admin: Admin) -> Result<Json<SystemStats>> {
+    let mut stats = state.admin_service.stats().await?;
+    stats.live_connections = state.connections.count();
+    Ok(Json(stats))
+}
@@ -0,0 +1,54 @@
+use crate::errors::Result;
+use crate::models::api_key::{ApiKeyResponse, CreateApiKeyRequest, CreatedApiKeyResponse};
+use crate::services::api_key_service::api_key_jti;
+use crate::services::connection_registry::CloseReason;
+use crate::services::jwt_service::Claims;
+use crate::validation::ValidatedJson;
+use crate::AppState;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+
+/// List the caller's own API keys
+pub async fn list_api_keys(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> Result<Json<Vec<ApiKeyResponse>>> {
+    let keys = state.api_key_service.list(claims.user_id()?).await?;
+    Ok(Json(keys))
+}
+
+/// Create an API key for the caller. The key is only shown in this response.
+pub async fn create_api_key(
+    State(state): State<AppState>,
+    claims: Claims,
+    ValidatedJson(req): ValidatedJson<CreateApiKeyRequest>,
+) -> Result<(StatusCode, Json<CreatedApiKeyResponse>)> {
+    let key = state
+        .api_key_service
+        .create(claims.user_id()?, req)
+        .await?;
+    Ok((StatusCode::CREATED, Json(key)))
+}
+
+/// Delete one of the caller's API keys and close connections opened with it
+pub async fn delete_api_key(
+    State(state): State<AppState>,
+    Path(key_id): Path<i32>,
+    claims: Claims,
+) -> Result<StatusCode> {
+    let revoked = state
+        .api_key_service
+        .revoke(claims.user_id()?, key_id)
+        .await?;
+
+    if revoked {
+        state
+            .connections
+            .close_token(&api_key_jti(key_id), CloseReason::TokenRevoked);
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
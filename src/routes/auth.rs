@@ -1,21 +1,211 @@
 use crate::errors::Result;
-use crate::models::user::{LoginRequest, RegisterRequest};
-use crate::services::auth_service::AuthResponse;
+use crate::models::refresh_token::{LogoutRequest, RefreshRequest};
+use crate::models::user::{
+    ChangePasswordRequest, ForgotPasswordRequest, LoginRequest, MagicLinkRequest,
+    MagicLinkResponse, MagicLinkVerifyQuery, RegisterRequest, ResendVerificationRequest,
+    ResetPasswordRequest, UserResponse, VerifyEmailRequest,
+};
+use crate::models::user_totp::{
+    ConfirmTotpRequest, DisableTotpRequest, MfaLoginRequest, RecoveryCodesResponse,
+    TotpEnrollmentResponse,
+};
+use crate::services::auth_service::{AuthResponse, LoginResponse, RegisterResponse};
+use crate::services::connection_registry::CloseReason;
+use crate::services::jwt_service::Claims;
+use crate::utils::ClientInfo;
+use crate::validation::ValidatedJson;
 use crate::AppState;
-use axum::{extract::State, Json};
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
 
 pub async fn register(
     State(state): State<AppState>,
-    Json(req): Json<RegisterRequest>,
-) -> Result<Json<AuthResponse>> {
-    let response = state.auth_service.register(req).await?;
+    client: ClientInfo,
+    ValidatedJson(req): ValidatedJson<RegisterRequest>,
+) -> Result<Json<RegisterResponse>> {
+    let response = state.auth_service.register(req, &client).await?;
     Ok(Json(response))
 }
 
 pub async fn login(
     State(state): State<AppState>,
-    Json(req): Json<LoginRequest>,
+    client: ClientInfo,
+    ValidatedJson(req): ValidatedJson<LoginRequest>,
+) -> Result<Json<LoginResponse>> {
+    let response = state.auth_service.login(req, &client).await?;
+    Ok(Json(response))
+}
+
+/// Finish a login that requires a second factor
+pub async fn login_mfa(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    Json(req): Json<MfaLoginRequest>,
+) -> Result<Json<AuthResponse>> {
+    let response = state.auth_service.login_mfa(req, &client).await?;
+    Ok(Json(response))
+}
+
+/// Email a sign-in link; the returned nonce must be presented with the link's token
+pub async fn request_magic_link(
+    State(state): State<AppState>,
+    Json(req): Json<MagicLinkRequest>,
+) -> Result<(StatusCode, Json<MagicLinkResponse>)> {
+    let response = state.auth_service.request_magic_link(req).await?;
+    Ok((StatusCode::ACCEPTED, Json(response)))
+}
+
+/// Sign in with the token from a magic link
+pub async fn verify_magic_link(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    Query(query): Query<MagicLinkVerifyQuery>,
+) -> Result<Json<LoginResponse>> {
+    let response = state
+        .auth_service
+        .login_with_magic_link(query, &client)
+        .await?;
+    Ok(Json(response))
+}
+
+pub async fn enroll_totp(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> Result<Json<TotpEnrollmentResponse>> {
+    let enrollment = state
+        .auth_service
+        .begin_totp_enrollment(claims.user_id()?)
+        .await?;
+    Ok(Json(enrollment))
+}
+
+pub async fn confirm_totp(
+    State(state): State<AppState>,
+    claims: Claims,
+    Json(req): Json<ConfirmTotpRequest>,
+) -> Result<Json<RecoveryCodesResponse>> {
+    let recovery_codes = state
+        .auth_service
+        .confirm_totp_enrollment(claims.user_id()?, &req.code)
+        .await?;
+    Ok(Json(RecoveryCodesResponse { recovery_codes }))
+}
+
+pub async fn disable_totp(
+    State(state): State<AppState>,
+    claims: Claims,
+    client: ClientInfo,
+    Json(req): Json<DisableTotpRequest>,
+) -> Result<StatusCode> {
+    state
+        .auth_service
+        .disable_totp(claims.user_id()?, req, &client)
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn verify_email(
+    State(state): State<AppState>,
+    Json(req): Json<VerifyEmailRequest>,
+) -> Result<Json<UserResponse>> {
+    let user = state.auth_service.verify_email(req).await?;
+    Ok(Json(user))
+}
+
+/// Always accepted, whether or not the address belongs to an unverified account
+pub async fn resend_verification(
+    State(state): State<AppState>,
+    Json(req): Json<ResendVerificationRequest>,
+) -> Result<StatusCode> {
+    state.auth_service.resend_verification(req).await?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Always accepted, whether or not the address belongs to an account
+pub async fn forgot_password(
+    State(state): State<AppState>,
+    Json(req): Json<ForgotPasswordRequest>,
+) -> Result<StatusCode> {
+    state.auth_service.forgot_password(req).await?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Set a new password with a reset token; signs the user out everywhere
+pub async fn reset_password(
+    State(state): State<AppState>,
+    ValidatedJson(req): ValidatedJson<ResetPasswordRequest>,
+) -> Result<StatusCode> {
+    let user_id = state.auth_service.reset_password(req).await?;
+
+    state
+        .connections
+        .close_user(user_id, CloseReason::TokenRevoked);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Change the password of the current user; other sessions are signed out
+pub async fn change_password(
+    State(state): State<AppState>,
+    claims: Claims,
+    client: ClientInfo,
+    ValidatedJson(req): ValidatedJson<ChangePasswordRequest>,
+) -> Result<Json<AuthResponse>> {
+    let user_id = claims.user_id()?;
+    let response = state
+        .auth_service
+        .change_password(user_id, req, &client)
+        .await?;
+
+    state
+        .connections
+        .close_user(user_id, CloseReason::TokenRevoked);
+
+    Ok(Json(response))
+}
+
+pub async fn refresh(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    Json(req): Json<RefreshRequest>,
 ) -> Result<Json<AuthResponse>> {
-    let response = state.auth_service.login(req).await?;
+    let response = state.auth_service.refresh(req, &client).await?;
     Ok(Json(response))
 }
+
+/// End the current session (or, for tokens without one, revoke the access token and the given
+/// refresh token) and close its sockets
+pub async fn logout(
+    State(state): State<AppState>,
+    claims: Claims,
+    req: Option<Json<LogoutRequest>>,
+) -> Result<StatusCode> {
+    let refresh_token = req.as_ref().and_then(|Json(req)| req.refresh_token.as_deref());
+    state.auth_service.logout(&claims, refresh_token).await?;
+
+    state
+        .connections
+        .close_token(&claims.jti, CloseReason::TokenRevoked);
+    if let Some(session_id) = claims.session_id() {
+        state
+            .connections
+            .close_session(session_id, CloseReason::TokenRevoked);
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Revoke every token the user holds and close all of their sockets
+pub async fn logout_all(State(state): State<AppState>, claims: Claims) -> Result<StatusCode> {
+    let user_id = claims.user_id()?;
+    state.auth_service.logout_all(user_id).await?;
+
+    state
+        .connections
+        .close_user(user_id, CloseReason::TokenRevoked);
+
+    Ok(StatusCode::NO_CONTENT)
+}
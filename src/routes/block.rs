@@ -0,0 +1,50 @@
+use crate::errors::Result;
+use crate::models::user_block::{BlockResponse, BlockUserRequest};
+use crate::routes::websocket::refresh_hidden_senders;
+use crate::services::jwt_service::Claims;
+use crate::AppState;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+
+/// List the users the caller has blocked or muted
+pub async fn list_blocks(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> Result<Json<Vec<BlockResponse>>> {
+    let user_id = claims.user_id()?;
+
+    let blocks = state.block_service.list(user_id).await?;
+    Ok(Json(blocks))
+}
+
+/// Block or mute a user
+pub async fn block_user(
+    State(state): State<AppState>,
+    Path(target_id): Path<i32>,
+    claims: Claims,
+    Json(req): Json<BlockUserRequest>,
+) -> Result<Json<BlockResponse>> {
+    let user_id = claims.user_id()?;
+
+    let block = state.block_service.set(user_id, target_id, req.kind).await?;
+    refresh_hidden_senders(&state, user_id).await;
+
+    Ok(Json(block))
+}
+
+/// Remove a block or mute
+pub async fn unblock_user(
+    State(state): State<AppState>,
+    Path(target_id): Path<i32>,
+    claims: Claims,
+) -> Result<StatusCode> {
+    let user_id = claims.user_id()?;
+
+    state.block_service.remove(user_id, target_id).await?;
+    refresh_hidden_senders(&state, user_id).await;
+
+    Ok(StatusCode::NO_CONTENT)
+}
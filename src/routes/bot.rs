@@ -0,0 +1,89 @@
+use crate::errors::Result;
+use crate::models::api_key::{ApiKeyResponse, CreateApiKeyRequest, CreatedApiKeyResponse};
+use crate::models::user::{CreateBotRequest, UserResponse};
+use crate::services::api_key_service::api_key_jti;
+use crate::services::connection_registry::CloseReason;
+use crate::services::jwt_service::Claims;
+use crate::validation::ValidatedJson;
+use crate::AppState;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+
+/// List the bots the caller manages
+pub async fn list_bots(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> Result<Json<Vec<UserResponse>>> {
+    let bots = state.bot_service.list(claims.user_id()?).await?;
+    Ok(Json(bots))
+}
+
+pub async fn create_bot(
+    State(state): State<AppState>,
+    claims: Claims,
+    ValidatedJson(req): ValidatedJson<CreateBotRequest>,
+) -> Result<(StatusCode, Json<UserResponse>)> {
+    let bot = state.bot_service.create(claims.user_id()?, req).await?;
+    Ok((StatusCode::CREATED, Json(bot)))
+}
+
+/// Delete a bot along with its keys and messages
+pub async fn delete_bot(
+    State(state): State<AppState>,
+    Path(bot_id): Path<i32>,
+    claims: Claims,
+) -> Result<StatusCode> {
+    state
+        .bot_service
+        .delete(claims.user_id()?, bot_id)
+        .await?;
+
+    state
+        .connections
+        .close_user(bot_id, CloseReason::TokenRevoked);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn list_bot_api_keys(
+    State(state): State<AppState>,
+    Path(bot_id): Path<i32>,
+    claims: Claims,
+) -> Result<Json<Vec<ApiKeyResponse>>> {
+    let bot = state.bot_service.owned(claims.user_id()?, bot_id).await?;
+
+    let keys = state.api_key_service.list(bot.id).await?;
+    Ok(Json(keys))
+}
+
+/// Create an API key that authenticates as the bot. The key is only shown in this response.
+pub async fn create_bot_api_key(
+    State(state): State<AppState>,
+    Path(bot_id): Path<i32>,
+    claims: Claims,
+    ValidatedJson(req): ValidatedJson<CreateApiKeyRequest>,
+) -> Result<(StatusCode, Json<CreatedApiKeyResponse>)> {
+    let bot = state.bot_service.owned(claims.user_id()?, bot_id).await?;
+
+    let key = state.api_key_service.create(bot.id, req).await?;
+    Ok((StatusCode::CREATED, Json(key)))
+}
+
+pub async fn delete_bot_api_key(
+    State(state): State<AppState>,
+    Path((bot_id, key_id)): Path<(i32, i32)>,
+    claims: Claims,
+) -> Result<StatusCode> {
+    let bot = state.bot_service.owned(claims.user_id()?, bot_id).await?;
+
+    if state.api_key_service.revoke(bot.id, key_id).await? {
+        state
+            .connections
+            .close_token(&api_key_jti(key_id), CloseReason::TokenRevoked);
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,219 @@
+use crate::errors::Result;
+use crate::models::room_invitation::{CreateInvitationRequest, InvitationResponse};
+use crate::models::room_join_request::{CreateJoinRequest, JoinRequestResponse};
+use crate::models::room_permission::Permission;
+use crate::routes::websocket::{notify_user, UserEvent};
+use crate::services::jwt_service::Claims;
+use crate::validation::ValidatedJson;
+use crate::AppState;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+
+/// Invite a user into the room; they answer from their inbox
+pub async fn invite_user(
+    State(state): State<AppState>,
+    Path(room_id): Path<i32>,
+    claims: Claims,
+    Json(req): Json<CreateInvitationRequest>,
+) -> Result<(StatusCode, Json<InvitationResponse>)> {
+    let access = state.room_service.access(room_id, &claims).await?;
+    let invitation = state
+        .invitation_service
+        .invite(&access, req.user_id)
+        .await?;
+
+    let response = InvitationResponse::new(invitation, access.room);
+    notify_user(
+        &state,
+        response.user_id,
+        &UserEvent::InvitationReceived {
+            invitation: response.clone(),
+        },
+    )
+    .await;
+
+    Ok((StatusCode::CREATED, Json(response)))
+}
+
+pub async fn list_room_invitations(
+    State(state): State<AppState>,
+    Path(room_id): Path<i32>,
+    claims: Claims,
+) -> Result<Json<Vec<InvitationResponse>>> {
+    let access = state.room_service.access(room_id, &claims).await?;
+    let invitations = state.invitation_service.room_invitations(&access).await?;
+
+    Ok(Json(
+        invitations
+            .into_iter()
+            .map(|invitation| InvitationResponse::new(invitation, access.room.clone()))
+            .collect(),
+    ))
+}
+
+pub async fn cancel_invitation(
+    State(state): State<AppState>,
+    Path((room_id, user_id)): Path<(i32, i32)>,
+    claims: Claims,
+) -> Result<StatusCode> {
+    let access = state.room_service.access(room_id, &claims).await?;
+    state.invitation_service.cancel(&access, user_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Invitations waiting for the caller
+pub async fn list_my_invitations(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> Result<Json<Vec<InvitationResponse>>> {
+    let invitations = state.invitation_service.inbox(claims.user_id()?).await?;
+
+    Ok(Json(
+        invitations
+            .into_iter()
+            .map(|(invitation, room)| InvitationResponse::new(invitation, room))
+            .collect(),
+    ))
+}
+
+pub async fn accept_invitation(
+    State(state): State<AppState>,
+    Path(invitation_id): Path<i32>,
+    claims: Claims,
+) -> Result<Json<InvitationResponse>> {
+    respond(state, invitation_id, claims, true).await
+}
+
+pub async fn decline_invitation(
+    State(state): State<AppState>,
+    Path(invitation_id): Path<i32>,
+    claims: Claims,
+) -> Result<Json<InvitationResponse>> {
+    respond(state, invitation_id, claims, false).await
+}
+
+async fn respond(
+    state: AppState,
+    invitation_id: i32,
+    claims: Claims,
+    accept: bool,
+) -> Result<Json<InvitationResponse>> {
+    let user_id = claims.user_id()?;
+    let (invitation, room) = state
+        .invitation_service
+        .respond(user_id, invitation_id, accept)
+        .await?;
+
+    if let Some(inviter) = invitation.invited_by {
+        let event = if accept {
+            UserEvent::InvitationAccepted {
+                room_id: room.id,
+                user_id,
+            }
+        } else {
+            UserEvent::InvitationDeclined {
+                room_id: room.id,
+                user_id,
+            }
+        };
+        notify_user(&state, inviter, &event).await;
+    }
+
+    Ok(Json(InvitationResponse::new(invitation, room)))
+}
+
+/// Ask to be let into a private room. Answers the same whether or not a request was recorded.
+pub async fn request_to_join(
+    State(state): State<AppState>,
+    Path(room_id): Path<i32>,
+    claims: Claims,
+    ValidatedJson(req): ValidatedJson<CreateJoinRequest>,
+) -> Result<StatusCode> {
+    let Some((request, user)) = state
+        .invitation_service
+        .request_to_join(claims.user_id()?, room_id, req)
+        .await?
+    else {
+        return Ok(StatusCode::ACCEPTED);
+    };
+    let response = JoinRequestResponse::new(request, &user);
+
+    for member in state
+        .room_service
+        .members_with(room_id, Permission::Invite)
+        .await?
+    {
+        let event = UserEvent::JoinRequestReceived {
+            request: response.clone(),
+        };
+        notify_user(&state, member, &event).await;
+    }
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+pub async fn withdraw_join_request(
+    State(state): State<AppState>,
+    Path(room_id): Path<i32>,
+    claims: Claims,
+) -> Result<StatusCode> {
+    state
+        .invitation_service
+        .withdraw(claims.user_id()?, room_id)
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn list_join_requests(
+    State(state): State<AppState>,
+    Path(room_id): Path<i32>,
+    claims: Claims,
+) -> Result<Json<Vec<JoinRequestResponse>>> {
+    let access = state.room_service.access(room_id, &claims).await?;
+    let requests = state.invitation_service.join_requests(&access).await?;
+
+    Ok(Json(
+        requests
+            .into_iter()
+            .map(|(request, user)| JoinRequestResponse::new(request, &user))
+            .collect(),
+    ))
+}
+
+pub async fn approve_join_request(
+    State(state): State<AppState>,
+    Path((room_id, user_id)): Path<(i32, i32)>,
+    claims: Claims,
+) -> Result<StatusCode> {
+    let access = state.room_service.access(room_id, &claims).await?;
+    state
+        .invitation_service
+        .decide(&access, user_id, true)
+        .await?;
+
+    let event = UserEvent::JoinRequestApproved {
+        room: access.room.into(),
+    };
+    notify_user(&state, user_id, &event).await;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn reject_join_request(
+    State(state): State<AppState>,
+    Path((room_id, user_id)): Path<(i32, i32)>,
+    claims: Claims,
+) -> Result<StatusCode> {
+    let access = state.room_service.access(room_id, &claims).await?;
+    state
+        .invitation_service
+        .decide(&access, user_id, false)
+        .await?;
+
+    notify_user(&state, user_id, &UserEvent::JoinRequestRejected { room_id }).await;
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,63 @@
+use crate::errors::Result;
+use crate::models::room::RoomResponse;
+use crate::models::room_invite::{CreateInviteRequest, CreatedInviteResponse, InviteResponse};
+use crate::services::jwt_service::Claims;
+use crate::validation::ValidatedJson;
+use crate::AppState;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+
+/// Create an invite link; the code is only shown in this response
+pub async fn create_invite(
+    State(state): State<AppState>,
+    Path(room_id): Path<i32>,
+    claims: Claims,
+    ValidatedJson(req): ValidatedJson<CreateInviteRequest>,
+) -> Result<(StatusCode, Json<CreatedInviteResponse>)> {
+    let access = state.room_service.access(room_id, &claims).await?;
+    let (code, invite) = state.invite_service.create(&access, req).await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(CreatedInviteResponse {
+            code,
+            invite: invite.into(),
+        }),
+    ))
+}
+
+pub async fn list_invites(
+    State(state): State<AppState>,
+    Path(room_id): Path<i32>,
+    claims: Claims,
+) -> Result<Json<Vec<InviteResponse>>> {
+    let access = state.room_service.access(room_id, &claims).await?;
+    let invites = state.invite_service.list(&access).await?;
+    Ok(Json(invites.into_iter().map(Into::into).collect()))
+}
+
+pub async fn revoke_invite(
+    State(state): State<AppState>,
+    Path((room_id, invite_id)): Path<(i32, i32)>,
+    claims: Claims,
+) -> Result<StatusCode> {
+    let access = state.room_service.access(room_id, &claims).await?;
+    state.invite_service.revoke(&access, invite_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Join the room an invite is for
+pub async fn accept_invite(
+    State(state): State<AppState>,
+    Path(code): Path<String>,
+    claims: Claims,
+) -> Result<Json<RoomResponse>> {
+    let room = state
+        .invite_service
+        .accept(claims.user_id()?, &code)
+        .await?;
+    Ok(Json(room.into()))
+}
@@ -0,0 +1,17 @@
+use crate::AppState;
+use axum::{extract::State, http::header, response::IntoResponse, Json};
+
+/// How long clients may cache the key set. Keep new keys published for at least this long
+/// before signing with them.
+const JWKS_MAX_AGE_SECONDS: u32 = 300;
+
+/// Public keys other services can verify access tokens with
+pub async fn jwks(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        [(
+            header::CACHE_CONTROL,
+            format!("public, max-age={}", JWKS_MAX_AGE_SECONDS),
+        )],
+        Json(state.jwt_service.jwks().clone()),
+    )
+}
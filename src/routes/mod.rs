@@ -1,4 +1,16 @@
+pub mod account;
+pub mod admin;
+pub mod api_key;
 pub mod auth;
+pub mod block;
+pub mod bot;
+pub mod invitation;
+pub mod invite;
+pub mod jwks;
+pub mod oidc;
+pub mod passkey;
+pub mod profile;
 pub mod room;
+pub mod session;
 pub mod websocket;
-pub mod health;
\ No newline at end of file
+pub mod health;
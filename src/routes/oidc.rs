@@ -0,0 +1,67 @@
+use crate::errors::{AppError, Result};
+use crate::models::user_identity::{LinkIdentityResponse, OidcCallbackQuery};
+use crate::services::auth_service::LoginResponse;
+use crate::services::jwt_service::Claims;
+use crate::utils::ClientInfo;
+use crate::AppState;
+use axum::{
+    extract::{Path, Query, State},
+    response::Redirect,
+    Json,
+};
+
+/// Redirect the browser to the identity provider's login page
+pub async fn start(
+    State(state): State<AppState>,
+    Path(provider): Path<String>,
+) -> Result<Redirect> {
+    let url = state.oidc_service.authorization_url(&provider, None).await?;
+    Ok(Redirect::to(&url))
+}
+
+/// Start linking a provider identity to the caller's account. Returns the URL to open, since
+/// the bearer token cannot follow a redirect; the callback then links and signs in as usual.
+pub async fn start_link(
+    State(state): State<AppState>,
+    Path(provider): Path<String>,
+    claims: Claims,
+) -> Result<Json<LinkIdentityResponse>> {
+    let authorization_url = state
+        .oidc_service
+        .authorization_url(&provider, Some(claims.user_id()?))
+        .await?;
+    Ok(Json(LinkIdentityResponse { authorization_url }))
+}
+
+/// Handle the provider's redirect back with an authorization code
+pub async fn callback(
+    State(state): State<AppState>,
+    Path(provider): Path<String>,
+    client: ClientInfo,
+    Query(query): Query<OidcCallbackQuery>,
+) -> Result<Json<LoginResponse>> {
+    if let Some(error) = query.error {
+        tracing::warn!(
+            "OIDC provider {} returned error {}: {}",
+            provider,
+            error,
+            query.error_description.unwrap_or_default()
+        );
+        return Err(AppError::AuthError("Login with identity provider failed".to_string()));
+    }
+
+    let (Some(code), Some(oidc_state)) = (query.code, query.state) else {
+        return Err(AppError::BadRequest("Missing code or state".to_string()));
+    };
+
+    let identity = state
+        .oidc_service
+        .complete(&provider, &code, &oidc_state)
+        .await?;
+
+    let response = state
+        .auth_service
+        .login_with_identity(identity, &client)
+        .await?;
+    Ok(Json(response))
+}
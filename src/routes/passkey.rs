@@ -0,0 +1,86 @@
+use crate::errors::Result;
+use crate::models::webauthn_credential::{
+    CredentialCreationOptions, CredentialRequestOptions, PasskeyLoginRequest,
+    PasskeyLoginStartRequest, PasskeyResponse, RegisterPasskeyRequest,
+};
+use crate::services::auth_service::AuthResponse;
+use crate::services::jwt_service::Claims;
+use crate::utils::ClientInfo;
+use crate::AppState;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+
+/// Options for creating a passkey on the caller's account
+pub async fn start_registration(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> Result<Json<CredentialCreationOptions>> {
+    let options = state
+        .webauthn_service
+        .begin_registration(claims.user_id()?)
+        .await?;
+    Ok(Json(options))
+}
+
+/// Store the passkey created by the browser
+pub async fn finish_registration(
+    State(state): State<AppState>,
+    claims: Claims,
+    Json(req): Json<RegisterPasskeyRequest>,
+) -> Result<Json<PasskeyResponse>> {
+    let passkey = state
+        .webauthn_service
+        .finish_registration(claims.user_id()?, req)
+        .await?;
+    Ok(Json(passkey))
+}
+
+pub async fn list_passkeys(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> Result<Json<Vec<PasskeyResponse>>> {
+    let passkeys = state.webauthn_service.list(claims.user_id()?).await?;
+    Ok(Json(passkeys))
+}
+
+pub async fn delete_passkey(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    claims: Claims,
+) -> Result<StatusCode> {
+    state.webauthn_service.remove(claims.user_id()?, id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Options for signing in with a passkey. The body is optional.
+pub async fn start_login(
+    State(state): State<AppState>,
+    req: Option<Json<PasskeyLoginStartRequest>>,
+) -> Result<Json<CredentialRequestOptions>> {
+    let Json(req) = req.unwrap_or_default();
+    let options = state
+        .webauthn_service
+        .begin_authentication(req.email.as_deref())
+        .await?;
+    Ok(Json(options))
+}
+
+/// Verify the passkey assertion and issue tokens
+pub async fn finish_login(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    Json(req): Json<PasskeyLoginRequest>,
+) -> Result<Json<AuthResponse>> {
+    let user_id = state
+        .webauthn_service
+        .finish_authentication(req.credential)
+        .await?;
+    let response = state
+        .auth_service
+        .login_with_passkey(user_id, &client)
+        .await?;
+    Ok(Json(response))
+}
@@ -0,0 +1,124 @@
+use crate::errors::{AppError, Result};
+use crate::models::user::{ProfileResponse, UpdateProfileRequest, UserResponse, UserSearchQuery};
+use crate::services::jwt_service::Claims;
+use crate::services::profile_service::MAX_IMAGE_UPLOAD_BYTES;
+use crate::validation::{Validate, ValidatedJson};
+use crate::AppState;
+use axum::{
+    extract::{multipart::MultipartError, Multipart, Path, Query, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+
+/// Avatar URLs change with every upload, so the image itself never goes stale
+const AVATAR_MAX_AGE_SECONDS: u64 = 365 * 24 * 60 * 60;
+
+/// The caller's own account
+pub async fn get_me(State(state): State<AppState>, claims: Claims) -> Result<Json<UserResponse>> {
+    let user = state.profile_service.get(claims.user_id()?).await?;
+    Ok(Json(user.into()))
+}
+
+pub async fn update_me(
+    State(state): State<AppState>,
+    claims: Claims,
+    ValidatedJson(req): ValidatedJson<UpdateProfileRequest>,
+) -> Result<Json<UserResponse>> {
+    let user = state.profile_service.update(claims.user_id()?, req).await?;
+    Ok(Json(user.into()))
+}
+
+/// Replace the caller's avatar with the image in the `avatar` field of a multipart form
+pub async fn upload_avatar(
+    State(state): State<AppState>,
+    claims: Claims,
+    multipart: Multipart,
+) -> Result<Json<UserResponse>> {
+    let user_id = claims.user_id()?;
+    let upload = image_upload(multipart, "avatar", "Avatar").await?;
+
+    let user = state.profile_service.set_avatar(user_id, upload).await?;
+    Ok(Json(user.into()))
+}
+
+/// Read the image in the `field` field of a multipart form; `label` names it in errors
+pub async fn image_upload(mut multipart: Multipart, field: &str, label: &str) -> Result<Vec<u8>> {
+    let upload_error = |e: MultipartError| {
+        if e.status() == StatusCode::PAYLOAD_TOO_LARGE {
+            return AppError::BadRequest(format!(
+                "{} must be at most {} MB",
+                label,
+                MAX_IMAGE_UPLOAD_BYTES / (1024 * 1024)
+            ));
+        }
+        AppError::BadRequest(e.body_text())
+    };
+
+    while let Some(part) = multipart.next_field().await.map_err(upload_error)? {
+        if part.name() != Some(field) {
+            continue;
+        }
+
+        let upload = part.bytes().await.map_err(upload_error)?;
+        return Ok(upload.to_vec());
+    }
+
+    Err(AppError::BadRequest(format!("Missing {} field", field)))
+}
+
+pub async fn delete_avatar(State(state): State<AppState>, claims: Claims) -> Result<StatusCode> {
+    state
+        .profile_service
+        .delete_avatar(claims.user_id()?)
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Find users by username or display name, e.g. to invite them to a room
+pub async fn search_users(
+    State(state): State<AppState>,
+    Query(query): Query<UserSearchQuery>,
+    _claims: Claims,
+) -> Result<Json<Vec<ProfileResponse>>> {
+    query.validate(&state.validation)?;
+
+    let users = state
+        .profile_service
+        .search(&query.q, query.limit, query.offset)
+        .await?;
+    Ok(Json(users.into_iter().map(Into::into).collect()))
+}
+
+/// Public profile of any user
+pub async fn get_user(
+    State(state): State<AppState>,
+    Path(user_id): Path<i32>,
+    _claims: Claims,
+) -> Result<Json<ProfileResponse>> {
+    let user = state.profile_service.get(user_id).await?;
+    Ok(Json(user.into()))
+}
+
+/// Serve an avatar image. Unauthenticated, so it works in `<img>` tags.
+pub async fn get_avatar(
+    State(state): State<AppState>,
+    Path(user_id): Path<i32>,
+) -> Result<impl IntoResponse> {
+    let avatar = state
+        .profile_service
+        .avatar(user_id)
+        .await?
+        .ok_or(AppError::UserNotFound)?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, avatar.content_type),
+            (
+                header::CACHE_CONTROL,
+                format!("public, max-age={}, immutable", AVATAR_MAX_AGE_SECONDS),
+            ),
+        ],
+        avatar.data,
+    ))
+}
@@ -1,28 +1,59 @@
 use crate::errors::{AppError, Result};
-use crate::models::message::{CreateMessageRequest, MessageResponse};
-use crate::models::room::{Entity as Room, RoomResponse};
+use crate::models::message::{CreateMessageRequest, MessageHistoryQuery, MessageResponse};
+use crate::models::room::{
+    self, CreateDmRequest, CreateRoomRequest, PostingPolicy, RoomResponse, RoomVisibility,
+    UpdateRoomRequest,
+};
+use crate::models::room_ban::BanResponse;
+use crate::models::room_member::{
+    AddMemberRequest, BanMemberRequest, MemberResponse, RoomRole, TransferOwnershipRequest,
+    UpdateMemberRequest,
+};
+use crate::models::room_permission::{
+    Permission, PermissionOverride, RoomAccessResponse, RoomPermissionsResponse,
+};
+use crate::services::connection_registry::CloseReason;
 use crate::services::jwt_service::Claims;
+use crate::routes::profile::image_upload;
+use crate::routes::websocket::{broadcast_to_room, RoomEvent, WsBroadcast};
 use crate::services::redis_service::CacheKey;
+use crate::validation::ValidatedJson;
 use crate::AppState;
 use axum::{
-    extract::{Path, State},
+    extract::{Multipart, Path, Query, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
     Json,
 };
-use sea_orm::EntityTrait;
 
-/// Get all rooms with Redis caching
+/// Icon URLs change with every upload, so the image itself never goes stale
+const ICON_MAX_AGE_SECONDS: u64 = 365 * 24 * 60 * 60;
+
+/// Get the public rooms, plus the private rooms and DMs the caller belongs to
 pub async fn get_rooms(
     State(state): State<AppState>,
-    _claims: Claims, // JWT middleware will inject this
+    claims: Claims, // JWT middleware will inject this
 ) -> Result<Json<Vec<RoomResponse>>> {
-    let cache_key = CacheKey::rooms_list();
+    let mut rooms = public_rooms(&state).await?;
+
+    // Memberships change too often to cache per user; this is a single indexed lookup
+    let private = state.room_service.private_rooms(claims.user_id()?).await?;
+    rooms.extend(private.into_iter().map(RoomResponse::from));
+    rooms.sort_by_key(|room| room.id);
+
+    Ok(Json(rooms))
+}
+
+/// Public rooms are the same for everyone, so they are cached with Redis
+async fn public_rooms(state: &AppState) -> Result<Vec<RoomResponse>> {
+    let cache_key = CacheKey::public_rooms();
     
     // Try to get from cache if Redis is enabled
     if let Some(redis) = &state.redis {
         match redis.get::<Vec<RoomResponse>>(&cache_key).await {
             Ok(Some(cached_rooms)) => {
                 tracing::debug!("🎯 Returning {} rooms from cache", cached_rooms.len());
-                return Ok(Json(cached_rooms));
+                return Ok(cached_rooms);
             }
             Ok(None) => {
                 tracing::debug!("❌ Cache miss, fetching from database");
@@ -34,7 +65,7 @@ pub async fn get_rooms(
     }
     
     // Fetch from database
-    let rooms = Room::find().all(state.db.as_ref()).await?;
+    let rooms = state.room_service.public_rooms().await?;
     let responses: Vec<RoomResponse> = rooms.into_iter().map(|r| r.into()).collect();
     
     // Cache the result if Redis is enabled
@@ -46,20 +77,461 @@ pub async fn get_rooms(
         }
     }
     
-    Ok(Json(responses))
+    Ok(responses)
 }
 
 pub async fn create_message(
     State(state): State<AppState>,
     Path(room_id): Path<i32>,
     claims: Claims,
-    Json(req): Json<CreateMessageRequest>,
+    ValidatedJson(req): ValidatedJson<CreateMessageRequest>,
 ) -> Result<Json<MessageResponse>> {
-    let user_id = claims
-        .sub
-        .parse::<i32>()
-        .map_err(|_| AppError::InvalidToken)?;
+    let access = state
+        .room_service
+        .authorize(room_id, &claims, Permission::Post)
+        .await?;
+
+    let message = state.message_service.post(&access, req).await?;
+
+    // Deliver to live clients too, so bots can post over plain HTTP
+    let sender = state.profile_service.get(access.user_id).await?;
+    let broadcast = WsBroadcast::message(&sender, message.content.clone());
+    broadcast_to_room(&state, room_id, &broadcast).await;
 
-    let message = state.message_service.create_message(user_id, room_id, req).await?;
     Ok(Json(message))
 }
+
+/// Get room history, hiding messages from users the caller has blocked or muted
+pub async fn list_messages(
+    State(state): State<AppState>,
+    Path(room_id): Path<i32>,
+    Query(query): Query<MessageHistoryQuery>,
+    claims: Claims,
+) -> Result<Json<Vec<MessageResponse>>> {
+    let user_id = claims.user_id()?;
+    state.room_service.access(room_id, &claims).await?;
+
+    let hidden = state.block_service.hidden_senders(user_id).await?;
+    let messages = state
+        .message_service
+        .list_messages(room_id, query.before, query.limit, &hidden)
+        .await?;
+
+    Ok(Json(messages))
+}
+
+/// Drop what the server keeps about a deleted room: its channel, live sockets and cached listing
+pub async fn forget_room(state: &AppState, room_id: i32) {
+    state.rooms.write().await.remove(&room_id);
+    state
+        .connections
+        .close_room(room_id, CloseReason::RoomDeleted);
+    invalidate_rooms_cache(state).await;
+}
+
+async fn invalidate_rooms_cache(state: &AppState) {
+    if let Some(redis) = &state.redis {
+        if let Err(e) = redis.delete(&CacheKey::public_rooms()).await {
+            tracing::warn!("Failed to invalidate rooms cache: {}", e);
+        }
+    }
+}
+
+/// Create a room owned by the caller
+pub async fn create_room(
+    State(state): State<AppState>,
+    claims: Claims,
+    ValidatedJson(req): ValidatedJson<CreateRoomRequest>,
+) -> Result<(StatusCode, Json<RoomResponse>)> {
+    let room = state.room_service.create(claims.user_id()?, req).await?;
+    if room.visibility == RoomVisibility::Public {
+        invalidate_rooms_cache(&state).await;
+    }
+
+    Ok((StatusCode::CREATED, Json(room.into())))
+}
+
+pub async fn delete_room(
+    State(state): State<AppState>,
+    Path(room_id): Path<i32>,
+    claims: Claims,
+) -> Result<StatusCode> {
+    let access = state.room_service.access(room_id, &claims).await?;
+    state.room_service.delete(&access).await?;
+    forget_room(&state, room_id).await;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Change the room's name, topic, description or settings
+pub async fn update_room(
+    State(state): State<AppState>,
+    Path(room_id): Path<i32>,
+    claims: Claims,
+    ValidatedJson(req): ValidatedJson<UpdateRoomRequest>,
+) -> Result<Json<RoomResponse>> {
+    let access = state.room_service.access(room_id, &claims).await?;
+    let room = state.room_service.update(&access, req).await?;
+    announce_changes(&state, access.user_id, &access.room, &room).await?;
+
+    Ok(Json(room.into()))
+}
+
+/// Replace the room's icon with the image in the `icon` field of a multipart form
+pub async fn upload_icon(
+    State(state): State<AppState>,
+    Path(room_id): Path<i32>,
+    claims: Claims,
+    multipart: Multipart,
+) -> Result<Json<RoomResponse>> {
+    let access = state.room_service.access(room_id, &claims).await?;
+    let upload = image_upload(multipart, "icon", "Icon").await?;
+
+    let room = state.room_service.set_icon(&access, upload).await?;
+    announce_changes(&state, access.user_id, &access.room, &room).await?;
+
+    Ok(Json(room.into()))
+}
+
+pub async fn delete_icon(
+    State(state): State<AppState>,
+    Path(room_id): Path<i32>,
+    claims: Claims,
+) -> Result<StatusCode> {
+    let access = state.room_service.access(room_id, &claims).await?;
+    if let Some(room) = state.room_service.delete_icon(&access).await? {
+        announce_changes(&state, access.user_id, &access.room, &room).await?;
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Serve a room icon. Icons of public rooms need no authentication, so they work in `<img>`
+/// tags; those of other rooms only go to people with access, and are not kept in shared caches.
+pub async fn get_icon(
+    State(state): State<AppState>,
+    Path(room_id): Path<i32>,
+    claims: Option<Claims>,
+) -> Result<impl IntoResponse> {
+    let (icon, room) = state
+        .room_service
+        .icon(room_id)
+        .await?
+        .ok_or(AppError::RoomNotFound)?;
+
+    let cache = if room.visibility == RoomVisibility::Public {
+        "public"
+    } else {
+        // Answer outsiders as if there were no icon, so ids cannot be probed for rooms
+        let claims = claims.ok_or(AppError::RoomNotFound)?;
+        state.room_service.access(room_id, &claims).await?;
+        "private"
+    };
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, icon.content_type),
+            (
+                header::CACHE_CONTROL,
+                format!("{}, max-age={}, immutable", cache, ICON_MAX_AGE_SECONDS),
+            ),
+        ],
+        icon.data,
+    ))
+}
+
+/// Tell the room what `actor_id` changed: a `room_updated` event for clients to refresh, and a
+/// system message in the timeline. Does nothing if nothing changed.
+async fn announce_changes(
+    state: &AppState,
+    actor_id: i32,
+    before: &room::Model,
+    after: &room::Model,
+) -> Result<()> {
+    let changes = describe_changes(before, after);
+    if changes.is_empty() {
+        return Ok(());
+    }
+
+    let actor = state.profile_service.get(actor_id).await?;
+    let content = format!("{} {}", actor.username, join_phrases(&changes));
+    state
+        .message_service
+        .create_system_message(actor_id, after, content.clone())
+        .await?;
+
+    let event = RoomEvent::RoomUpdated {
+        room: after.clone().into(),
+    };
+    broadcast_to_room(state, after.id, &event).await;
+    broadcast_to_room(state, after.id, &WsBroadcast::system(&actor, content)).await;
+
+    if after.visibility == RoomVisibility::Public {
+        invalidate_rooms_cache(state).await;
+    }
+    Ok(())
+}
+
+/// What changed between two versions of a room, as phrases like `changed the topic to "..."`
+fn describe_changes(before: &room::Model, after: &room::Model) -> Vec<String> {
+    let mut changes = Vec::new();
+
+    if before.name != after.name {
+        changes.push(format!("renamed the room to \"{}\"", after.name));
+    }
+    if before.topic != after.topic {
+        changes.push(match &after.topic {
+            Some(topic) => format!("changed the topic to \"{}\"", topic),
+            None => "cleared the topic".to_string(),
+        });
+    }
+    if before.description != after.description {
+        changes.push(match after.description {
+            Some(_) => "changed the description".to_string(),
+            None => "cleared the description".to_string(),
+        });
+    }
+    if before.icon_updated_at != after.icon_updated_at {
+        changes.push(match after.icon_updated_at {
+            Some(_) => "changed the icon".to_string(),
+            None => "removed the icon".to_string(),
+        });
+    }
+
+    let (old, new) = (&before.settings, &after.settings);
+    if old.slow_mode_seconds != new.slow_mode_seconds {
+        changes.push(match new.slow_mode_seconds {
+            0 => "turned off slow mode".to_string(),
+            seconds => format!("set slow mode to {} seconds", seconds),
+        });
+    }
+    if old.message_expiry_seconds != new.message_expiry_seconds {
+        changes.push(match new.message_expiry_seconds {
+            0 => "stopped messages from expiring".to_string(),
+            seconds => format!("set new messages to expire after {} seconds", seconds),
+        });
+    }
+    if old.posting != new.posting {
+        changes.push(match new.posting {
+            PostingPolicy::Everyone => "let everyone post".to_string(),
+            PostingPolicy::Admins => "limited posting to admins".to_string(),
+        });
+    }
+
+    changes
+}
+
+/// "a", "a and b", "a, b and c"
+fn join_phrases(phrases: &[String]) -> String {
+    match phrases {
+        [] => String::new(),
+        [only] => only.clone(),
+        [rest @ .., last] => format!("{} and {}", rest.join(", "), last),
+    }
+}
+
+/// Open a DM with one user (reusing an existing one) or a new group DM with several
+pub async fn start_dm(
+    State(state): State<AppState>,
+    claims: Claims,
+    ValidatedJson(req): ValidatedJson<CreateDmRequest>,
+) -> Result<(StatusCode, Json<RoomResponse>)> {
+    let (room, created) = state.room_service.start_dm(claims.user_id()?, req).await?;
+
+    let status = if created {
+        StatusCode::CREATED
+    } else {
+        StatusCode::OK
+    };
+    Ok((status, Json(room.into())))
+}
+
+/// The caller's role and permissions in the room
+pub async fn get_my_access(
+    State(state): State<AppState>,
+    Path(room_id): Path<i32>,
+    claims: Claims,
+) -> Result<Json<RoomAccessResponse>> {
+    let access = state.room_service.access(room_id, &claims).await?;
+
+    Ok(Json(RoomAccessResponse {
+        room_id,
+        role: access.member_role,
+        permissions: access.permissions,
+    }))
+}
+
+pub async fn list_members(
+    State(state): State<AppState>,
+    Path(room_id): Path<i32>,
+    claims: Claims,
+) -> Result<Json<Vec<MemberResponse>>> {
+    state.room_service.access(room_id, &claims).await?;
+
+    let members = state.room_service.members(room_id).await?;
+    Ok(Json(
+        members
+            .into_iter()
+            .map(|(member, user)| MemberResponse::new(member, &user))
+            .collect(),
+    ))
+}
+
+pub async fn join_room(
+    State(state): State<AppState>,
+    Path(room_id): Path<i32>,
+    claims: Claims,
+) -> Result<StatusCode> {
+    let access = state.room_service.access(room_id, &claims).await?;
+    state.room_service.join(&access).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn leave_room(
+    State(state): State<AppState>,
+    Path(room_id): Path<i32>,
+    claims: Claims,
+) -> Result<StatusCode> {
+    let access = state.room_service.access(room_id, &claims).await?;
+    state.room_service.leave(&access).await?;
+    state
+        .connections
+        .close_room_member(room_id, access.user_id, CloseReason::LeftRoom);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn add_member(
+    State(state): State<AppState>,
+    Path(room_id): Path<i32>,
+    claims: Claims,
+    Json(req): Json<AddMemberRequest>,
+) -> Result<StatusCode> {
+    let access = state.room_service.access(room_id, &claims).await?;
+    state.room_service.add_member(&access, req.user_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Change a member's role; owners change hands through `transfer_ownership` instead
+pub async fn update_member(
+    State(state): State<AppState>,
+    Path((room_id, user_id)): Path<(i32, i32)>,
+    claims: Claims,
+    Json(req): Json<UpdateMemberRequest>,
+) -> Result<StatusCode> {
+    let access = state.room_service.access(room_id, &claims).await?;
+    state.room_service.set_role(&access, user_id, req.role).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Kick a member; unlike a ban, they may join again
+pub async fn remove_member(
+    State(state): State<AppState>,
+    Path((room_id, user_id)): Path<(i32, i32)>,
+    claims: Claims,
+) -> Result<StatusCode> {
+    let access = state.room_service.access(room_id, &claims).await?;
+    state.room_service.remove_member(&access, user_id).await?;
+    state
+        .connections
+        .close_room_member(room_id, user_id, CloseReason::RemovedFromRoom);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn list_bans(
+    State(state): State<AppState>,
+    Path(room_id): Path<i32>,
+    claims: Claims,
+) -> Result<Json<Vec<BanResponse>>> {
+    let access = state.room_service.access(room_id, &claims).await?;
+    let bans = state.room_service.bans(&access).await?;
+    Ok(Json(bans.into_iter().map(Into::into).collect()))
+}
+
+pub async fn ban_member(
+    State(state): State<AppState>,
+    Path((room_id, user_id)): Path<(i32, i32)>,
+    claims: Claims,
+    ValidatedJson(req): ValidatedJson<BanMemberRequest>,
+) -> Result<Json<BanResponse>> {
+    let access = state.room_service.access(room_id, &claims).await?;
+    let ban = state
+        .room_service
+        .ban_member(&access, user_id, req.reason)
+        .await?;
+    state
+        .connections
+        .close_room_member(room_id, user_id, CloseReason::RemovedFromRoom);
+
+    Ok(Json(ban.into()))
+}
+
+pub async fn unban_member(
+    State(state): State<AppState>,
+    Path((room_id, user_id)): Path<(i32, i32)>,
+    claims: Claims,
+) -> Result<StatusCode> {
+    let access = state.room_service.access(room_id, &claims).await?;
+    state.room_service.unban_member(&access, user_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn get_permissions(
+    State(state): State<AppState>,
+    Path(room_id): Path<i32>,
+    claims: Claims,
+) -> Result<Json<RoomPermissionsResponse>> {
+    state.room_service.access(room_id, &claims).await?;
+    Ok(Json(state.room_service.permissions(room_id).await?))
+}
+
+pub async fn set_permission(
+    State(state): State<AppState>,
+    Path(room_id): Path<i32>,
+    claims: Claims,
+    Json(req): Json<PermissionOverride>,
+) -> Result<Json<RoomPermissionsResponse>> {
+    let access = state.room_service.access(room_id, &claims).await?;
+    Ok(Json(state.room_service.set_override(&access, req).await?))
+}
+
+/// Return a role to its default for one permission
+pub async fn clear_permission(
+    State(state): State<AppState>,
+    Path((room_id, role, permission)): Path<(i32, RoomRole, Permission)>,
+    claims: Claims,
+) -> Result<Json<RoomPermissionsResponse>> {
+    let access = state.room_service.access(room_id, &claims).await?;
+    Ok(Json(
+        state
+            .room_service
+            .clear_override(&access, role, permission)
+            .await?,
+    ))
+}
+
+pub async fn transfer_ownership(
+    State(state): State<AppState>,
+    Path(room_id): Path<i32>,
+    claims: Claims,
+    Json(req): Json<TransferOwnershipRequest>,
+) -> Result<StatusCode> {
+    let access = state.room_service.access(room_id, &claims).await?;
+    state
+        .room_service
+        .transfer_ownership(&access, req.user_id)
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Delete a message: your own, or anyone's with the delete-others permission
+pub async fn delete_message(
+    State(state): State<AppState>,
+    Path((room_id, message_id)): Path<(i32, i32)>,
+    claims: Claims,
+) -> Result<StatusCode> {
+    let access = state.room_service.access(room_id, &claims).await?;
+    state.room_service.delete_message(&access, message_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
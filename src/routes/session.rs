@@ -0,0 +1,43 @@
+use crate::errors::Result;
+use crate::models::session::SessionResponse;
+use crate::services::connection_registry::CloseReason;
+use crate::services::jwt_service::Claims;
+use crate::AppState;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use uuid::Uuid;
+
+/// List the devices the caller is signed in on
+pub async fn list_sessions(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> Result<Json<Vec<SessionResponse>>> {
+    let sessions = state
+        .auth_service
+        .list_sessions(claims.user_id()?, claims.session_id())
+        .await?;
+    Ok(Json(sessions))
+}
+
+/// Sign one of the caller's sessions out and close its connections
+pub async fn delete_session(
+    State(state): State<AppState>,
+    Path(session_id): Path<Uuid>,
+    claims: Claims,
+) -> Result<StatusCode> {
+    let revoked = state
+        .auth_service
+        .revoke_session(claims.user_id()?, session_id)
+        .await?;
+
+    if revoked {
+        state
+            .connections
+            .close_session(session_id, CloseReason::TokenRevoked);
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -1,10 +1,19 @@
 use crate::errors::AppError;
+use crate::models::api_key::Scope;
 use crate::models::message::CreateMessageRequest;
+use crate::models::room::RoomResponse;
+use crate::models::room_invitation::InvitationResponse;
+use crate::models::room_join_request::JoinRequestResponse;
+use crate::models::room_permission::Permission;
+use crate::models::user;
+use crate::services::connection_registry::CloseReason;
 use crate::services::jwt_service::Claims;
+use crate::utils::authenticate;
+use crate::validation::{Validate, ValidationErrors};
 use crate::AppState;
 use axum::{
     extract::{
-        ws::{Message, WebSocket},
+        ws::{CloseFrame, Message, WebSocket},
         Path, Query, State, WebSocketUpgrade,
     },
     response::Response,
@@ -14,7 +23,8 @@ use futures::{
     SinkExt, StreamExt,
 };
 use serde::{Deserialize, Serialize};
-use tokio::sync::broadcast;
+use std::collections::HashSet;
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
 
 // WebSocket message types
 #[derive(Debug, Serialize, Deserialize)]
@@ -28,11 +38,142 @@ pub enum WsMessage {
 pub struct WsBroadcast {
     #[serde(rename = "type")]
     pub msg_type: String,
+    /// Username of the sender
     pub sender: String,
     pub sender_id: i32,
+    pub sender_display_name: Option<String>,
+    pub sender_avatar_url: Option<String>,
+    /// Whether the sender is a bot account
+    pub is_bot: bool,
     pub content: String,
 }
 
+impl WsBroadcast {
+    /// A chat message posted by `sender`
+    pub fn message(sender: &user::Model, content: String) -> Self {
+        Self {
+            msg_type: "message".to_string(),
+            sender: sender.username.clone(),
+            sender_id: sender.id,
+            sender_display_name: sender.display_name.clone(),
+            sender_avatar_url: sender.avatar_url(),
+            is_bot: sender.is_bot,
+            content,
+        }
+    }
+
+    /// A system message about something `actor` did to the room
+    pub fn system(actor: &user::Model, content: String) -> Self {
+        Self {
+            msg_type: "system".to_string(),
+            ..Self::message(actor, content)
+        }
+    }
+}
+
+/// Sent only to the connection a refused message came from
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WsReply {
+    /// Slow mode is on; the client may post again after `retry_after` seconds
+    SlowMode { retry_after: u64 },
+    /// The client may not post here, e.g. in an announcement room
+    Forbidden { reason: String },
+    /// The message broke the same rules as over HTTP
+    Invalid { fields: ValidationErrors },
+}
+
+/// Something that happened to a room, sent to everyone connected to it
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RoomEvent {
+    /// The room's details, icon or settings changed
+    RoomUpdated { room: RoomResponse },
+}
+
+/// Send a frame (a `WsBroadcast` or `RoomEvent`) to everyone connected to `room_id`, if anyone is
+pub async fn broadcast_to_room<T: Serialize>(state: &AppState, room_id: i32, frame: &T) {
+    let Some(tx) = state.rooms.read().await.get(&room_id).cloned() else {
+        return;
+    };
+
+    if let Ok(frame_json) = serde_json::to_string(frame) {
+        let _ = tx.send(frame_json);
+    }
+}
+
+/// Something that happened to a user, delivered on their own channel (`/ws/me`)
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum UserEvent {
+    /// Sent to the invited user
+    InvitationReceived { invitation: InvitationResponse },
+    /// Sent to whoever sent the invitation
+    InvitationAccepted { room_id: i32, user_id: i32 },
+    InvitationDeclined { room_id: i32, user_id: i32 },
+    /// Sent to the members who can decide on it
+    JoinRequestReceived { request: JoinRequestResponse },
+    /// Sent to whoever asked to join
+    JoinRequestApproved { room: RoomResponse },
+    JoinRequestRejected { room_id: i32 },
+}
+
+/// Send an event to every connection `user_id` has open on `/ws/me`, if any
+pub async fn notify_user(state: &AppState, user_id: i32, event: &UserEvent) {
+    let Some(tx) = state.user_channels.read().await.get(&user_id).cloned() else {
+        return;
+    };
+
+    if let Ok(event_json) = serde_json::to_string(event) {
+        let _ = tx.send(event_json);
+    }
+}
+
+/// Follow the senders hidden from `user_id`, loading them when their first connection opens
+async fn watch_hidden_senders(state: &AppState, user_id: i32) -> watch::Receiver<HashSet<i32>> {
+    // Loaded under the lock so a block made meanwhile is either in the list or refreshed into it
+    let mut lists = state.hidden_senders.write().await;
+    if let Some(tx) = lists.get(&user_id) {
+        return tx.subscribe();
+    }
+
+    let hidden = match state.block_service.hidden_senders(user_id).await {
+        Ok(hidden) => hidden,
+        Err(e) => {
+            tracing::error!("Failed to load block list for user {}: {:?}", user_id, e);
+            HashSet::new()
+        }
+    };
+    let (tx, rx) = watch::channel(hidden);
+    lists.insert(user_id, tx);
+    rx
+}
+
+/// Reload the senders hidden from `user_id` into their open room connections, if any.
+/// Call after their block list changes.
+pub async fn refresh_hidden_senders(state: &AppState, user_id: i32) {
+    // Held while loading so concurrent refreshes cannot publish an older list last
+    let lists = state.hidden_senders.write().await;
+    let Some(tx) = lists.get(&user_id) else {
+        return;
+    };
+
+    match state.block_service.hidden_senders(user_id).await {
+        Ok(hidden) => {
+            tx.send_replace(hidden);
+        }
+        Err(e) => tracing::error!("Failed to reload block list for user {}: {:?}", user_id, e),
+    }
+}
+
+/// Just enough of a broadcast frame to decide whether a client should see it
+#[derive(Debug, Deserialize)]
+struct BroadcastOrigin {
+    #[serde(rename = "type")]
+    msg_type: Option<String>,
+    sender_id: Option<i32>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct WsQuery {
     token: String,
@@ -44,12 +185,79 @@ pub async fn websocket_handler(
     Query(query): Query<WsQuery>,
     State(state): State<AppState>,
 ) -> Result<Response, AppError> {
-    // Verify JWT token
-    let claims = state.jwt_service.verify_token(&query.token)?;
+    // Verify the session token or API key; keys need at least read access
+    let claims = authenticate(&query.token, Some(Scope::MessagesRead), &state).await?;
+
+    // Banned users cannot listen in; whether they may post is checked per message
+    state.room_service.access(room_id, &claims).await?;
 
     Ok(ws.on_upgrade(move |socket| handle_socket(socket, room_id, claims, state)))
 }
 
+/// The caller's own event channel: invitations, join requests and their outcomes
+pub async fn user_websocket_handler(
+    ws: WebSocketUpgrade,
+    Query(query): Query<WsQuery>,
+    State(state): State<AppState>,
+) -> Result<Response, AppError> {
+    // Account events are not for API keys
+    let claims = authenticate(&query.token, None, &state).await?;
+
+    Ok(ws.on_upgrade(move |socket| handle_user_socket(socket, claims, state)))
+}
+
+async fn handle_user_socket(socket: WebSocket, claims: Claims, state: AppState) {
+    let (sender, mut receiver) = socket.split();
+    let Ok(user_id) = claims.user_id() else {
+        return;
+    };
+
+    let rx = {
+        let mut channels = state.user_channels.write().await;
+        channels
+            .entry(user_id)
+            .or_insert_with(|| broadcast::channel(32).0)
+            .subscribe()
+    };
+
+    let (registration, closed) =
+        state
+            .connections
+            .register(user_id, None, &claims.jti, claims.session_id());
+
+    // Nothing is expected from the client, so there is nothing to reply to
+    let (_replies, replies_rx) = mpsc::channel(1);
+    let mut send_task = tokio::spawn(send_messages(
+        sender,
+        rx,
+        replies_rx,
+        watch::channel(HashSet::new()).1,
+        closed,
+    ));
+
+    // Nothing is expected from the client; read only to notice when it goes away
+    let mut recv_task = tokio::spawn(async move { while let Some(Ok(_)) = receiver.next().await {} });
+
+    tokio::select! {
+        _ = &mut send_task => {
+            recv_task.abort();
+        },
+        _ = &mut recv_task => {
+            send_task.abort();
+        },
+    }
+    drop(registration);
+
+    // Forget the channel once the user's last connection is gone
+    let mut channels = state.user_channels.write().await;
+    if channels
+        .get(&user_id)
+        .is_some_and(|tx| tx.receiver_count() == 0)
+    {
+        channels.remove(&user_id);
+    }
+}
+
 async fn handle_socket(socket: WebSocket, room_id: i32, claims: Claims, state: AppState) {
     let (sender, receiver) = socket.split();
 
@@ -65,18 +273,49 @@ async fn handle_socket(socket: WebSocket, room_id: i32, claims: Claims, state: A
     let rx = tx.subscribe();
 
     let user_id = claims.sub.parse::<i32>().unwrap_or(0);
-    let username = claims.email.clone();
+
+    // Messages carry the sender's public profile, never their email
+    let profile = match state.profile_service.get(user_id).await {
+        Ok(profile) => profile,
+        Err(e) => {
+            tracing::error!("Failed to load profile of user {}: {:?}", user_id, e);
+            return;
+        }
+    };
+    let author = Author {
+        user_id,
+        profile,
+        can_write: claims.allows(Scope::MessagesWrite),
+        claims: claims.clone(),
+    };
+
+    // Senders this user has blocked or muted are filtered out of their stream
+    let hidden_senders = watch_hidden_senders(&state, user_id).await;
+
+    // Keep the connection registered so revoking its token closes it
+    let (_registration, closed) = state
+        .connections
+        .register(user_id, Some(room_id), &claims.jti, claims.session_id());
+
+    // Frames meant for this client alone, such as slow mode notices
+    let (replies, replies_rx) = mpsc::channel(8);
 
     // Spawn task to send messages to this client
-    let mut send_task = tokio::spawn(send_messages(sender, rx));
+    let mut send_task = tokio::spawn(send_messages(
+        sender,
+        rx,
+        replies_rx,
+        hidden_senders,
+        closed,
+    ));
 
     // Spawn task to receive messages from this client
     let mut recv_task = tokio::spawn(receive_messages(
         receiver,
         tx.clone(),
+        replies,
         room_id,
-        user_id,
-        username.clone(),
+        author,
         state.clone(),
     ));
 
@@ -87,58 +326,148 @@ async fn handle_socket(socket: WebSocket, room_id: i32, claims: Claims, state: A
         },
         _ = &mut recv_task => {
             send_task.abort();
+            // Wait for it to drop its block list subscription
+            let _ = send_task.await;
         },
     }
 
-    tracing::info!("WebSocket connection closed for user {} in room {}", username, room_id);
+    // Forget the block list once the user's last room connection is gone
+    let mut lists = state.hidden_senders.write().await;
+    if lists
+        .get(&user_id)
+        .is_some_and(|tx| tx.receiver_count() == 0)
+    {
+        lists.remove(&user_id);
+    }
+    drop(lists);
+
+    tracing::info!("WebSocket connection closed for user {} in room {}", user_id, room_id);
 }
 
 async fn send_messages(
     mut sender: SplitSink<WebSocket, Message>,
     mut rx: broadcast::Receiver<String>,
+    mut replies: mpsc::Receiver<String>,
+    hidden_senders: watch::Receiver<HashSet<i32>>,
+    mut closed: oneshot::Receiver<CloseReason>,
 ) {
-    while let Ok(msg) = rx.recv().await {
-        if sender.send(Message::Text(msg)).await.is_err() {
-            break;
+    loop {
+        tokio::select! {
+            Some(reply) = replies.recv() => {
+                if sender.send(Message::Text(reply)).await.is_err() {
+                    break;
+                }
+            }
+            msg = rx.recv() => {
+                let Ok(msg) = msg else { break };
+
+                if is_hidden(&msg, &hidden_senders.borrow()) {
+                    continue;
+                }
+
+                if sender.send(Message::Text(msg)).await.is_err() {
+                    break;
+                }
+            }
+            Ok(reason) = &mut closed => {
+                let frame = CloseFrame {
+                    code: reason.code(),
+                    reason: reason.message().into(),
+                };
+                let _ = sender.send(Message::Close(Some(frame))).await;
+                break;
+            }
         }
     }
 }
 
+fn is_hidden(msg: &str, hidden_senders: &HashSet<i32>) -> bool {
+    if hidden_senders.is_empty() {
+        return false;
+    }
+
+    // System messages are about the room, so they reach everyone
+    serde_json::from_str::<BroadcastOrigin>(msg)
+        .ok()
+        .filter(|origin| origin.msg_type.as_deref() != Some("system"))
+        .and_then(|origin| origin.sender_id)
+        .is_some_and(|sender_id| hidden_senders.contains(&sender_id))
+}
+
+/// Who is sending on a connection
+struct Author {
+    user_id: i32,
+    /// Loaded when the connection opens; later profile changes show up after reconnecting
+    profile: user::Model,
+    /// False for API keys without `messages:write`
+    can_write: bool,
+    /// Room permissions can change while connected, so posts are authorized one by one
+    claims: Claims,
+}
+
 async fn receive_messages(
     mut receiver: SplitStream<WebSocket>,
     tx: broadcast::Sender<String>,
+    replies: mpsc::Sender<String>,
     room_id: i32,
-    user_id: i32,
-    username: String,
+    author: Author,
     state: AppState,
 ) {
+    let user_id = author.user_id;
+
     while let Some(Ok(msg)) = receiver.next().await {
         if let Message::Text(text) = msg {
             // Parse incoming message
             if let Ok(ws_msg) = serde_json::from_str::<WsMessage>(&text) {
                 match ws_msg {
+                    WsMessage::Message { .. } if !author.can_write => {
+                        let reason = format!("API key is missing the {} scope", Scope::MessagesWrite);
+                        send_reply(&replies, WsReply::Forbidden { reason }).await;
+                    }
                     WsMessage::Message { content } => {
-                        // Save message to database
                         let create_req = CreateMessageRequest {
                             content: content.clone(),
                         };
+                        if let Err(fields) = create_req.validate(&state.validation) {
+                            send_reply(&replies, WsReply::Invalid { fields }).await;
+                            continue;
+                        }
 
-                        if let Err(e) = state
-                            .message_service
-                            .create_message(user_id, room_id, create_req)
+                        let access = match state
+                            .room_service
+                            .authorize(room_id, &author.claims, Permission::Post)
                             .await
                         {
-                            tracing::error!("Failed to save message: {:?}", e);
-                            continue;
+                            Ok(access) => access,
+                            Err(AppError::Forbidden(reason)) => {
+                                send_reply(&replies, WsReply::Forbidden { reason }).await;
+                                continue;
+                            }
+                            Err(e) => {
+                                tracing::debug!("Dropping message from user {} in room {}: {:?}", user_id, room_id, e);
+                                continue;
+                            }
+                        };
+
+                        // Save message to database, subject to the same rules as over HTTP
+                        match state.message_service.post(&access, create_req).await {
+                            Ok(_) => {}
+                            Err(AppError::SlowMode { retry_after }) => {
+                                send_reply(&replies, WsReply::SlowMode { retry_after }).await;
+                                continue;
+                            }
+                            Err(AppError::Forbidden(reason)) => {
+                                send_reply(&replies, WsReply::Forbidden { reason }).await;
+                                continue;
+                            }
+                            Err(e) => {
+                                tracing::error!("Failed to save message: {:?}", e);
+                                continue;
+                            }
                         }
 
                         // Broadcast to all clients in the room
-                        let broadcast = WsBroadcast {
-                            msg_type: "message".to_string(),
-                            sender: username.clone(),
-                            sender_id: user_id,
-                            content,
-                        };
+                        let broadcast = WsBroadcast::message(&author.profile, content);
 
                         if let Ok(broadcast_json) = serde_json::to_string(&broadcast) {
                             let _ = tx.send(broadcast_json);
@@ -149,3 +478,10 @@ async fn receive_messages(
         }
     }
 }
+
+/// Tell the connection behind `replies` why its message was refused
+async fn send_reply(replies: &mpsc::Sender<String>, reply: WsReply) {
+    if let Ok(reply_json) = serde_json::to_string(&reply) {
+        let _ = replies.send(reply_json).await;
+    }
+}
@@ -0,0 +1,419 @@
+use crate::errors::{AppError, Result};
+use crate::models::account_job::{self, Entity as AccountJob, JobKind, JobStatus};
+use crate::models::data_export::{
+    self, DataExport, Entity as DataExportEntity, ExportedAvatar, ExportedMembership,
+};
+use crate::models::room_member::RoomRole;
+use crate::models::{
+    api_key, login_lockout, message, recovery_code, refresh_token, room, room_ban, room_invitation, room_invite, room_join_request,
+    room_member,
+    session, user, user_avatar, user_block, user_identity, user_token, user_totp, webauthn_credential,
+};
+use crate::services::bot_service::UNUSABLE_PASSWORD_HASH;
+use crate::services::connection_registry::{CloseReason, ConnectionRegistry};
+use crate::services::message_service::not_expired;
+use crate::services::revocation_service::RevocationService;
+use crate::services::room_service::hand_over_rooms;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{Duration, Utc};
+use sea_orm::{
+    sea_query::{Condition, OnConflict},
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait, QueryFilter,
+    QueryOrder, Set, TransactionTrait,
+};
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// How long a finished export can be downloaded
+const EXPORT_RETENTION_DAYS: i64 = 7;
+/// Shown in place of the name of deleted accounts
+const DELETED_USER_NAME: &str = "Deleted user";
+
+/// Runs personal data exports and account deletions in the background.
+///
+/// Jobs are recorded in `account_jobs` so clients can poll their status. Both kinds are safe to
+/// run again, so jobs interrupted by a restart are simply resumed.
+#[derive(Clone)]
+pub struct AccountJobService {
+    db: DatabaseConnection,
+    revocation: Arc<RevocationService>,
+    connections: Arc<ConnectionRegistry>,
+}
+
+impl AccountJobService {
+    pub fn new(
+        db: DatabaseConnection,
+        revocation: Arc<RevocationService>,
+        connections: Arc<ConnectionRegistry>,
+    ) -> Self {
+        Self {
+            db,
+            revocation,
+            connections,
+        }
+    }
+
+    /// Start a job of `kind` for `user_id`, or return the one already under way
+    pub async fn start(&self, user_id: i32, kind: JobKind) -> Result<account_job::Model> {
+        let active = AccountJob::find()
+            .filter(account_job::Column::UserId.eq(user_id))
+            .filter(account_job::Column::Kind.eq(kind))
+            .filter(account_job::Column::Status.is_in([JobStatus::Pending, JobStatus::Running]))
+            .one(&self.db)
+            .await?;
+        if let Some(job) = active {
+            return Ok(job);
+        }
+
+        let job = account_job::ActiveModel {
+            id: Set(Uuid::new_v4()),
+            user_id: Set(user_id),
+            kind: Set(kind),
+            status: Set(JobStatus::Pending),
+            error: Set(None),
+            created_at: Set(Utc::now().naive_utc()),
+            started_at: Set(None),
+            finished_at: Set(None),
+        }
+        .insert(&self.db)
+        .await?;
+
+        tracing::info!("Queued {:?} job {} for user {}", kind, job.id, user_id);
+        self.spawn(job.clone());
+        Ok(job)
+    }
+
+    /// Restart jobs that were pending or running when the server stopped
+    pub async fn resume(&self) -> Result<()> {
+        let jobs = AccountJob::find()
+            .filter(account_job::Column::Status.is_in([JobStatus::Pending, JobStatus::Running]))
+            .order_by_asc(account_job::Column::CreatedAt)
+            .all(&self.db)
+            .await?;
+
+        for job in jobs {
+            tracing::info!("Resuming {:?} job {}", job.kind, job.id);
+            self.spawn(job);
+        }
+
+        Ok(())
+    }
+
+    pub async fn get(&self, job_id: Uuid) -> Result<account_job::Model> {
+        AccountJob::find_by_id(job_id)
+            .one(&self.db)
+            .await?
+            .ok_or(AppError::JobNotFound)
+    }
+
+    /// The archive of the user's latest completed export, if it has not expired
+    pub async fn latest_export(&self, user_id: i32) -> Result<(account_job::Model, Vec<u8>)> {
+        let latest = AccountJob::find()
+            .filter(account_job::Column::UserId.eq(user_id))
+            .filter(account_job::Column::Kind.eq(JobKind::Export))
+            .filter(account_job::Column::Status.eq(JobStatus::Completed))
+            .order_by_desc(account_job::Column::FinishedAt)
+            .find_also_related(DataExportEntity)
+            .one(&self.db)
+            .await?;
+
+        match latest {
+            Some((job, Some(export))) if export.expires_at > Utc::now().naive_utc() => {
+                Ok((job, export.data))
+            }
+            _ => Err(AppError::JobNotFound),
+        }
+    }
+
+    fn spawn(&self, job: account_job::Model) {
+        let service = self.clone();
+        tokio::spawn(async move { service.run(job).await });
+    }
+
+    async fn run(&self, job: account_job::Model) {
+        let (id, kind, user_id) = (job.id, job.kind, job.user_id);
+
+        let mut running: account_job::ActiveModel = job.into();
+        running.status = Set(JobStatus::Running);
+        running.started_at = Set(Some(Utc::now().naive_utc()));
+        if let Err(e) = running.update(&self.db).await {
+            tracing::error!("Failed to start job {}: {:?}", id, e);
+            return;
+        }
+
+        let outcome = match kind {
+            JobKind::Export => self.export(id, user_id).await,
+            JobKind::Deletion => self.delete_account(user_id).await,
+        };
+
+        let (status, error) = match outcome {
+            Ok(()) => {
+                tracing::info!("{:?} job {} for user {} completed", kind, id, user_id);
+                (JobStatus::Completed, None)
+            }
+            Err(e) => {
+                tracing::error!("{:?} job {} for user {} failed: {:?}", kind, id, user_id, e);
+                let error = match kind {
+                    JobKind::Export => "The export could not be created; please try again",
+                    JobKind::Deletion => "The account could not be deleted; please try again",
+                };
+                (JobStatus::Failed, Some(error.to_string()))
+            }
+        };
+
+        let finished = account_job::ActiveModel {
+            id: Set(id),
+            status: Set(status),
+            error: Set(error),
+            finished_at: Set(Some(Utc::now().naive_utc())),
+            ..Default::default()
+        };
+        if let Err(e) = finished.update(&self.db).await {
+            tracing::error!("Failed to record the outcome of job {}: {:?}", id, e);
+        }
+    }
+
+    /// Collect the user's personal data and store it as the archive of job `job_id`
+    async fn export(&self, job_id: Uuid, user_id: i32) -> Result<()> {
+        let user = user::Entity::find_by_id(user_id)
+            .one(&self.db)
+            .await?
+            .ok_or(AppError::UserNotFound)?;
+
+        let avatar = user_avatar::Entity::find_by_id(user_id)
+            .one(&self.db)
+            .await?
+            .map(|avatar| ExportedAvatar {
+                content_type: avatar.content_type,
+                data: STANDARD.encode(avatar.data),
+            });
+
+        let memberships = room_member::Entity::find()
+            .filter(room_member::Column::UserId.eq(user_id))
+            .find_also_related(room::Entity)
+            .order_by_asc(room_member::Column::JoinedAt)
+            .all(&self.db)
+            .await?
+            .into_iter()
+            .map(|(membership, room)| ExportedMembership {
+                room_id: membership.room_id,
+                room_name: room.map(|room| room.name).unwrap_or_default(),
+                role: membership.role,
+                joined_at: membership.joined_at,
+            })
+            .collect();
+
+        let messages = message::Entity::find()
+            .filter(message::Column::SenderId.eq(user_id))
+            .filter(not_expired())
+            .order_by_asc(message::Column::Id)
+            .all(&self.db)
+            .await?
+            .into_iter()
+            .map(Into::into)
+            .collect();
+
+        let blocks = user_block::Entity::find()
+            .filter(user_block::Column::UserId.eq(user_id))
+            .order_by_asc(user_block::Column::Id)
+            .all(&self.db)
+            .await?
+            .into_iter()
+            .map(Into::into)
+            .collect();
+
+        let bots = user::Entity::find()
+            .filter(user::Column::BotOwnerId.eq(user_id))
+            .order_by_asc(user::Column::Id)
+            .all(&self.db)
+            .await?
+            .into_iter()
+            .map(Into::into)
+            .collect();
+
+        let now = Utc::now().naive_utc();
+        let archive = DataExport {
+            exported_at: now,
+            profile: user.into(),
+            avatar,
+            memberships,
+            messages,
+            blocks,
+            bots,
+        };
+        let data =
+            serde_json::to_vec_pretty(&archive).map_err(|_| AppError::InternalServerError)?;
+
+        // Archives nobody downloaded in time are not kept around
+        DataExportEntity::delete_many()
+            .filter(data_export::Column::ExpiresAt.lt(now))
+            .exec(&self.db)
+            .await?;
+
+        // A resumed job replaces whatever its interrupted run left behind
+        DataExportEntity::insert(data_export::ActiveModel {
+            job_id: Set(job_id),
+            data: Set(data),
+            expires_at: Set(now + Duration::days(EXPORT_RETENTION_DAYS)),
+        })
+        .on_conflict(
+            OnConflict::column(data_export::Column::JobId)
+                .update_columns([data_export::Column::Data, data_export::Column::ExpiresAt])
+                .to_owned(),
+        )
+        .exec(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Strip the account (and the bots it owns) of personal data and credentials. The user rows
+    /// stay, so messages remain in every room's history under a "deleted user" attribution.
+    async fn delete_account(&self, user_id: i32) -> Result<()> {
+        let mut user_ids = vec![user_id];
+        user_ids.extend(
+            user::Entity::find()
+                .filter(user::Column::BotOwnerId.eq(user_id))
+                .all(&self.db)
+                .await?
+                .into_iter()
+                .map(|bot| bot.id),
+        );
+
+        let now = Utc::now().naive_utc();
+        let txn = self.db.begin().await?;
+
+        for id in &user_ids {
+            hand_over_rooms(&txn, *id).await?;
+        }
+        delete_personal_data(&txn, &user_ids).await?;
+
+        for id in &user_ids {
+            user::ActiveModel {
+                id: Set(*id),
+                // `.invalid` is reserved and never resolves; the address stays unique
+                email: Set(format!(
+                    "deleted-{}@deleted.invalid",
+                    Uuid::new_v4().simple()
+                )),
+                password_hash: Set(UNUSABLE_PASSWORD_HASH.to_string()),
+                username: Set(format!("deleted-{}", id)),
+                tokens_valid_after: Set(Some(now)),
+                email_verified_at: Set(None),
+                display_name: Set(Some(DELETED_USER_NAME.to_string())),
+                bio: Set(None),
+                status_text: Set(None),
+                timezone: Set(None),
+                avatar_updated_at: Set(None),
+                hidden_from_directory: Set(true),
+                email_visible: Set(false),
+                deleted_at: Set(Some(now)),
+                ..Default::default()
+            }
+            .update(&txn)
+            .await?;
+        }
+
+        txn.commit().await?;
+
+        for id in user_ids {
+            // Also mirrors the revocation to Redis, where token checks are answered from
+            self.revocation.revoke_all_for_user(id).await?;
+            self.connections.close_user(id, CloseReason::AccountDeleted);
+        }
+
+        Ok(())
+    }
+}
+
+/// Delete everything that belongs to `user_ids` except the user rows and their messages
+async fn delete_personal_data<C: ConnectionTrait>(db: &C, user_ids: &[i32]) -> Result<()> {
+    let ids = || user_ids.iter().copied();
+
+    api_key::Entity::delete_many()
+        .filter(api_key::Column::UserId.is_in(ids()))
+        .exec(db)
+        .await?;
+    session::Entity::delete_many()
+        .filter(session::Column::UserId.is_in(ids()))
+        .exec(db)
+        .await?;
+    refresh_token::Entity::delete_many()
+        .filter(refresh_token::Column::UserId.is_in(ids()))
+        .exec(db)
+        .await?;
+    user_token::Entity::delete_many()
+        .filter(user_token::Column::UserId.is_in(ids()))
+        .exec(db)
+        .await?;
+    user_totp::Entity::delete_many()
+        .filter(user_totp::Column::UserId.is_in(ids()))
+        .exec(db)
+        .await?;
+    recovery_code::Entity::delete_many()
+        .filter(recovery_code::Column::UserId.is_in(ids()))
+        .exec(db)
+        .await?;
+    user_identity::Entity::delete_many()
+        .filter(user_identity::Column::UserId.is_in(ids()))
+        .exec(db)
+        .await?;
+    webauthn_credential::Entity::delete_many()
+        .filter(webauthn_credential::Column::UserId.is_in(ids()))
+        .exec(db)
+        .await?;
+    user_avatar::Entity::delete_many()
+        .filter(user_avatar::Column::UserId.is_in(ids()))
+        .exec(db)
+        .await?;
+    user_block::Entity::delete_many()
+        .filter(
+            Condition::any()
+                .add(user_block::Column::UserId.is_in(ids()))
+                .add(user_block::Column::TargetId.is_in(ids())),
+        )
+        .exec(db)
+        .await?;
+    // Rooms nobody could take over stay owned by the tombstone until an admin transfers them
+    room_member::Entity::delete_many()
+        .filter(room_member::Column::UserId.is_in(ids()))
+        .filter(room_member::Column::Role.ne(RoomRole::Owner))
+        .exec(db)
+        .await?;
+    room_ban::Entity::delete_many()
+        .filter(room_ban::Column::UserId.is_in(ids()))
+        .exec(db)
+        .await?;
+    room_invite::Entity::delete_many()
+        .filter(room_invite::Column::CreatedBy.is_in(ids()))
+        .exec(db)
+        .await?;
+    room_invitation::Entity::delete_many()
+        .filter(
+            Condition::any()
+                .add(room_invitation::Column::UserId.is_in(ids()))
+                .add(room_invitation::Column::InvitedBy.is_in(ids())),
+        )
+        .exec(db)
+        .await?;
+    room_join_request::Entity::delete_many()
+        .filter(room_join_request::Column::UserId.is_in(ids()))
+        .exec(db)
+        .await?;
+    login_lockout::Entity::delete_many()
+        .filter(login_lockout::Column::UserId.is_in(ids()))
+        .exec(db)
+        .await?;
+
+    let export_jobs = AccountJob::find()
+        .filter(account_job::Column::UserId.is_in(ids()))
+        .filter(account_job::Column::Kind.eq(JobKind::Export))
+        .all(db)
+        .await?;
+    DataExportEntity::delete_many()
+        .filter(data_export::Column::JobId.is_in(export_jobs.into_iter().map(|job| job.id)))
+        .exec(db)
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,184 @@
+use crate::errors::{AppError, Result};
+use crate::models::message::{self, Entity as Message};
+use crate::models::room::Entity as Room;
+use crate::models::session::Entity as Session;
+use crate::models::user::{self, AdminUserQuery, Entity as User, UserRole};
+use crate::services::message_service::not_expired;
+use crate::services::profile_service::escape_like;
+use crate::services::revocation_service::RevocationService;
+use chrono::{Duration, Utc};
+use sea_orm::{
+    sea_query::Expr, ColumnTrait, DatabaseConnection, EntityTrait, PaginatorTrait, QueryFilter,
+    QueryOrder, QuerySelect,
+};
+use serde::Serialize;
+use std::sync::Arc;
+
+const DEFAULT_USER_LIST_LIMIT: u64 = 50;
+const MAX_USER_LIST_LIMIT: u64 = 200;
+
+/// Counts for the admin dashboard
+#[derive(Debug, Serialize)]
+pub struct SystemStats {
+    /// Human accounts that have not been deleted
+    pub users: u64,
+    pub admins: u64,
+    pub disabled_users: u64,
+    pub deleted_users: u64,
+    pub bots: u64,
+    pub rooms: u64,
+    pub messages: u64,
+    pub messages_last_24h: u64,
+    pub sessions: u64,
+    /// Open WebSocket connections on this instance
+    pub live_connections: usize,
+}
+
+/// Server-wide moderation: managing accounts and removing content
+#[derive(Clone)]
+pub struct AdminService {
+    db: DatabaseConnection,
+    revocation: Arc<RevocationService>,
+}
+
+impl AdminService {
+    pub fn new(db: DatabaseConnection, revocation: Arc<RevocationService>) -> Self {
+        Self { db, revocation }
+    }
+
+    /// List accounts, including bots and deleted ones, oldest first
+    pub async fn list_users(&self, query: &AdminUserQuery) -> Result<Vec<user::Model>> {
+        let mut select = User::find();
+
+        if let Some(q) = query.q.as_deref().map(str::trim).filter(|q| !q.is_empty()) {
+            let prefix = format!("{}%", escape_like(&q.to_lowercase()));
+            select = select.filter(Expr::cust_with_values(
+                "(lower(email) LIKE $1 OR lower(username) LIKE $1 OR lower(display_name) LIKE $1)",
+                [prefix],
+            ));
+        }
+        if let Some(role) = query.role {
+            select = select.filter(user::Column::Role.eq(role));
+        }
+        if let Some(disabled) = query.disabled {
+            select = select.filter(if disabled {
+                user::Column::DisabledAt.is_not_null()
+            } else {
+                user::Column::DisabledAt.is_null()
+            });
+        }
+
+        let users = select
+            .order_by_asc(user::Column::Id)
+            .limit(
+                query
+                    .limit
+                    .unwrap_or(DEFAULT_USER_LIST_LIMIT)
+                    .clamp(1, MAX_USER_LIST_LIMIT),
+            )
+            .offset(query.offset.unwrap_or(0))
+            .all(&self.db)
+            .await?;
+
+        Ok(users)
+    }
+
+    pub async fn get_user(&self, user_id: i32) -> Result<user::Model> {
+        User::find_by_id(user_id)
+            .one(&self.db)
+            .await?
+            .ok_or(AppError::UserNotFound)
+    }
+
+    /// Disable or re-enable an account. Callers sign a disabled user out everywhere.
+    pub async fn set_disabled(
+        &self,
+        admin_id: i32,
+        user_id: i32,
+        disabled: bool,
+    ) -> Result<user::Model> {
+        if disabled && admin_id == user_id {
+            return Err(AppError::BadRequest(
+                "You cannot disable your own account".to_string(),
+            ));
+        }
+
+        let user = self.get_user(user_id).await?;
+        if user.disabled_at.is_some() != disabled {
+            self.revocation.set_user_disabled(user_id, disabled).await?;
+            tracing::warn!(
+                "Admin {} {} user {}",
+                admin_id,
+                if disabled { "disabled" } else { "enabled" },
+                user_id
+            );
+        }
+
+        self.get_user(user_id).await
+    }
+
+    /// Delete a room with its messages and memberships. Returns whether it existed.
+    pub async fn delete_room(&self, admin_id: i32, room_id: i32) -> Result<bool> {
+        let deleted = Room::delete_by_id(room_id)
+            .exec(&self.db)
+            .await?
+            .rows_affected
+            > 0;
+        if deleted {
+            tracing::warn!("Admin {} deleted room {}", admin_id, room_id);
+        }
+        Ok(deleted)
+    }
+
+    pub async fn delete_message(&self, admin_id: i32, message_id: i32) -> Result<()> {
+        let deleted = Message::delete_by_id(message_id)
+            .exec(&self.db)
+            .await?
+            .rows_affected
+            > 0;
+        if deleted {
+            tracing::warn!("Admin {} deleted message {}", admin_id, message_id);
+        }
+        Ok(())
+    }
+
+    /// Counts over the whole database. `live_connections` is left for the caller to fill in.
+    pub async fn stats(&self) -> Result<SystemStats> {
+        let humans = || {
+            User::find()
+                .filter(user::Column::IsBot.eq(false))
+                .filter(user::Column::DeletedAt.is_null())
+        };
+
+        Ok(SystemStats {
+            users: humans().count(&self.db).await?,
+            admins: humans()
+                .filter(user::Column::Role.eq(UserRole::Admin))
+                .count(&self.db)
+                .await?,
+            disabled_users: humans()
+                .filter(user::Column::DisabledAt.is_not_null())
+                .count(&self.db)
+                .await?,
+            deleted_users: User::find()
+                .filter(user::Column::IsBot.eq(false))
+                .filter(user::Column::DeletedAt.is_not_null())
+                .count(&self.db)
+                .await?,
+            bots: User::find()
+                .filter(user::Column::IsBot.eq(true))
+                .filter(user::Column::DeletedAt.is_null())
+                .count(&self.db)
+                .await?,
+            rooms: Room::find().count(&self.db).await?,
+            messages: Message::find().filter(not_expired()).count(&self.db).await?,
+            messages_last_24h: Message::find()
+                .filter(not_expired())
+                .filter(message::Column::CreatedAt.gt(Utc::now().naive_utc() - Duration::hours(24)))
+                .count(&self.db)
+                .await?,
+            sessions: Session::find().count(&self.db).await?,
+            live_connections: 0,
+        })
+    }
+}
@@ -0,0 +1,156 @@
+use crate::errors::{AppError, Result};
+use crate::models::api_key::{
+    self, ApiKeyResponse, CreateApiKeyRequest, CreatedApiKeyResponse, Entity as ApiKey,
+};
+use crate::models::user::{self, Entity as User};
+use crate::services::jwt_service::{ApiKeyGrant, Claims};
+use crate::utils::{generate_opaque_token, hash_token};
+use chrono::{Duration, NaiveDateTime, Utc};
+use sea_orm::{
+    sea_query::Expr, ActiveModelTrait, ColumnTrait, Condition, DatabaseConnection, EntityTrait,
+    QueryFilter, QueryOrder, QuerySelect, Set,
+};
+
+/// Every API key starts with this, so keys are recognisable (and easy to scan for in leaks)
+pub const API_KEY_PREFIX: &str = "chat_";
+const API_KEY_BYTES: usize = 32;
+/// How much of the key is kept in clear for display
+const DISPLAY_PREFIX_LEN: usize = 12;
+/// `last_used_at` is only rewritten once per interval to avoid a write on every request
+const LAST_USED_RESOLUTION_SECONDS: i64 = 60;
+
+/// Token id used for connections opened with API key `key_id`, so revoking the key can
+/// close them
+pub fn api_key_jti(key_id: i32) -> String {
+    format!("api-key:{}", key_id)
+}
+
+#[derive(Clone)]
+pub struct ApiKeyService {
+    db: DatabaseConnection,
+}
+
+impl ApiKeyService {
+    pub fn new(db: DatabaseConnection) -> Self {
+        Self { db }
+    }
+
+    /// Create a key that authenticates as `user_id`. The key itself is returned only here.
+    pub async fn create(
+        &self,
+        user_id: i32,
+        req: CreateApiKeyRequest,
+    ) -> Result<CreatedApiKeyResponse> {
+        let name = req.name.trim();
+
+        let mut scopes = req.scopes;
+        scopes.sort_by_key(|scope| scope.as_str());
+        scopes.dedup();
+
+        let key = format!("{}{}", API_KEY_PREFIX, generate_opaque_token(API_KEY_BYTES));
+
+        let stored = api_key::ActiveModel {
+            user_id: Set(user_id),
+            name: Set(name.to_string()),
+            prefix: Set(key[..DISPLAY_PREFIX_LEN].to_string()),
+            key_hash: Set(hash_token(&key)),
+            scopes: Set(scopes
+                .iter()
+                .map(|scope| scope.as_str())
+                .collect::<Vec<_>>()
+                .join(" ")),
+            created_at: Set(Utc::now().naive_utc()),
+            ..Default::default()
+        }
+        .insert(&self.db)
+        .await?;
+
+        Ok(CreatedApiKeyResponse {
+            key,
+            api_key: stored.into(),
+        })
+    }
+
+    /// List the keys that authenticate as `user_id`, newest first
+    pub async fn list(&self, user_id: i32) -> Result<Vec<ApiKeyResponse>> {
+        let keys = ApiKey::find()
+            .filter(api_key::Column::UserId.eq(user_id))
+            .order_by_desc(api_key::Column::CreatedAt)
+            .all(&self.db)
+            .await?;
+
+        Ok(keys.into_iter().map(Into::into).collect())
+    }
+
+    /// Delete a key of `user_id`, returning whether it existed.
+    /// Callers should close connections opened with it.
+    pub async fn revoke(&self, user_id: i32, key_id: i32) -> Result<bool> {
+        let deleted = ApiKey::delete_many()
+            .filter(api_key::Column::Id.eq(key_id))
+            .filter(api_key::Column::UserId.eq(user_id))
+            .exec(&self.db)
+            .await?;
+
+        Ok(deleted.rows_affected > 0)
+    }
+
+    /// Resolve a presented key to the claims of the user it belongs to
+    pub async fn authenticate(&self, raw: &str) -> Result<Claims> {
+        let (key, user) = ApiKey::find()
+            .filter(api_key::Column::KeyHash.eq(hash_token(raw)))
+            .find_also_related(User)
+            .one(&self.db)
+            .await?
+            .and_then(|(key, user)| Some((key, user?)))
+            .ok_or(AppError::InvalidToken)?;
+
+        if user.disabled_at.is_some() {
+            return Err(AppError::AccountDisabled);
+        }
+
+        // A bot acts for its owner, so it stops working when the owner is disabled
+        if let Some(owner_id) = user.bot_owner_id {
+            let owner_disabled_at: Option<Option<NaiveDateTime>> = User::find_by_id(owner_id)
+                .select_only()
+                .column(user::Column::DisabledAt)
+                .into_tuple()
+                .one(&self.db)
+                .await?;
+
+            if owner_disabled_at.flatten().is_some() {
+                return Err(AppError::AccountDisabled);
+            }
+        }
+
+        let now = Utc::now().naive_utc();
+        ApiKey::update_many()
+            .col_expr(api_key::Column::LastUsedAt, Expr::value(now))
+            .filter(api_key::Column::Id.eq(key.id))
+            .filter(
+                Condition::any()
+                    .add(api_key::Column::LastUsedAt.is_null())
+                    .add(
+                        api_key::Column::LastUsedAt
+                            .lt(now - Duration::seconds(LAST_USED_RESOLUTION_SECONDS)),
+                    ),
+            )
+            .exec(&self.db)
+            .await?;
+
+        Ok(Claims {
+            sub: user.id.to_string(),
+            email: user.email,
+            // API keys do not expire; they are valid until deleted
+            exp: usize::MAX,
+            iat: key.created_at.and_utc().timestamp() as usize,
+            jti: api_key_jti(key.id),
+            sid: None,
+            role: user.role,
+            api_key: Some(ApiKeyGrant {
+                key_id: key.id,
+                scopes: key.scope_list(),
+                is_bot: user.is_bot,
+            }),
+        })
+    }
+}
@@ -1,35 +1,144 @@
+use crate::config::Config;
 use crate::errors::{AppError, Result};
-use crate::models::user::{self, Entity as User, LoginRequest, RegisterRequest, UserResponse};
-use crate::services::jwt_service::JwtService;
-use argon2::{
-    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
-    Argon2,
+use crate::models::refresh_token::RefreshRequest;
+use crate::models::session::SessionResponse;
+use crate::models::user::{
+    self, ChangePasswordRequest, Entity as User, ForgotPasswordRequest, LoginRequest,
+    MagicLinkRequest, MagicLinkResponse, MagicLinkVerifyQuery, RegisterRequest,
+    ResendVerificationRequest, ResetPasswordRequest, UserResponse, VerifyEmailRequest,
 };
-use chrono::Utc;
+use crate::models::user_identity::{self, Entity as UserIdentity};
+use crate::models::user_token::TokenPurpose;
+use crate::models::user_totp::{DisableTotpRequest, MfaLoginRequest, TotpEnrollmentResponse};
+use crate::services::jwt_service::{Claims, JwtService};
+use crate::services::login_throttle::LoginThrottle;
+use crate::services::mailer::{Email, Mailer};
+use crate::services::oidc_service::OidcIdentity;
+use crate::services::password_service::PasswordService;
+use crate::services::profile_service::{available_username, username_taken};
+use crate::services::redis_service::RedisService;
+use crate::services::refresh_token_service::RefreshTokenService;
+use crate::services::revocation_service::RevocationService;
+use crate::services::session_service::SessionService;
+use crate::services::totp_service::TotpService;
+use crate::services::user_token_service::UserTokenService;
+use crate::utils::{generate_opaque_token, ClientInfo};
+use chrono::{Duration, Utc};
 use sea_orm::{ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, Set};
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// How long an email verification link stays valid
+const EMAIL_VERIFICATION_TTL_HOURS: i64 = 24;
+
+/// How long a password reset link stays valid
+const PASSWORD_RESET_TTL_MINUTES: i64 = 60;
+
+/// How long a magic login link stays valid
+const MAGIC_LINK_TTL_MINUTES: i64 = 15;
+
+/// How recently a session must have started to confirm an irreversible action by itself
+const REAUTH_WINDOW_MINUTES: i64 = 5;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AuthResponse {
+    /// Short-lived access token (JWT)
     pub token: String,
+    /// Opaque token for `POST /auth/refresh`; rotated on every use
+    pub refresh_token: String,
+    /// Access token lifetime in seconds
+    pub expires_in: i64,
     pub user: UserResponse,
 }
 
+/// Result of registration: either signed in, or waiting for the email to be verified
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RegisterResponse {
+    Authenticated(AuthResponse),
+    VerificationRequired {
+        user: UserResponse,
+        verification_required: bool,
+    },
+}
+
+/// Result of the password step of login
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+#[allow(clippy::large_enum_variant)]
+pub enum LoginResponse {
+    Authenticated(AuthResponse),
+    /// Two-factor authentication is enabled; exchange `mfa_token` and a code at
+    /// `POST /auth/login/mfa` to finish signing in
+    MfaRequired {
+        mfa_token: String,
+        mfa_required: bool,
+    },
+}
+
 #[derive(Clone)]
 pub struct AuthService {
     db: DatabaseConnection,
     jwt_service: JwtService,
+    refresh_tokens: RefreshTokenService,
+    revocation: RevocationService,
+    passwords: PasswordService,
+    sessions: SessionService,
+    user_tokens: UserTokenService,
+    totp: TotpService,
+    throttle: Arc<LoginThrottle>,
+    mailer: Arc<dyn Mailer>,
+    /// Hash checked when the email is unknown, so failed logins take the same time either way
+    dummy_password_hash: String,
+    public_base_url: String,
+    require_email_verification: bool,
 }
 
 impl AuthService {
-    pub fn new(db: DatabaseConnection, jwt_service: JwtService) -> Self {
-        Self { db, jwt_service }
+    pub fn new(
+        db: DatabaseConnection,
+        jwt_service: JwtService,
+        refresh_tokens: RefreshTokenService,
+        revocation: RevocationService,
+        mailer: Arc<dyn Mailer>,
+        redis: Option<Arc<RedisService>>,
+        config: &Config,
+    ) -> Self {
+        let mut service = Self {
+            sessions: SessionService::new(db.clone(), config.refresh_token_ttl_days),
+            user_tokens: UserTokenService::new(db.clone()),
+            totp: TotpService::new(db.clone(), &config.totp_issuer),
+            throttle: Arc::new(LoginThrottle::new(db.clone(), redis)),
+            db,
+            jwt_service,
+            refresh_tokens,
+            revocation,
+            passwords: PasswordService::from_config(config)
+                .expect("Invalid password hashing configuration"),
+            mailer,
+            dummy_password_hash: String::new(),
+            public_base_url: config.public_base_url.trim_end_matches('/').to_string(),
+            require_email_verification: config.require_email_verification,
+        };
+
+        service.dummy_password_hash = service
+            .passwords
+            .hash(&generate_opaque_token(16))
+            .expect("Failed to hash dummy password");
+        service
     }
 
-    pub async fn register(&self, req: RegisterRequest) -> Result<AuthResponse> {
+    pub async fn register(
+        &self,
+        req: RegisterRequest,
+        client: &ClientInfo,
+    ) -> Result<RegisterResponse> {
+        let email = user::normalize_email(&req.email);
+
         // Check if user already exists
         let existing_user = User::find()
-            .filter(user::Column::Email.eq(&req.email))
+            .filter(user::email_is(&email))
             .one(&self.db)
             .await?;
 
@@ -37,12 +146,16 @@ impl AuthService {
             return Err(AppError::UserAlreadyExists);
         }
 
+        if username_taken(&self.db, &req.username, None).await? {
+            return Err(AppError::UsernameTaken);
+        }
+
         // Hash password
-        let password_hash = self.hash_password(&req.password)?;
+        let password_hash = self.passwords.hash(&req.password)?;
 
         // Create user
         let new_user = user::ActiveModel {
-            email: Set(req.email.clone()),
+            email: Set(email),
             password_hash: Set(password_hash),
             username: Set(req.username),
             created_at: Set(Utc::now().naive_utc()),
@@ -51,50 +164,737 @@ impl AuthService {
 
         let user = new_user.insert(&self.db).await?;
 
-        // Generate token
-        let token = self.jwt_service.generate_token(user.id, &user.email)?;
+        // A failed delivery should not fail registration; the user can ask for a resend
+        if let Err(e) = self.send_verification_email(&user).await {
+            tracing::error!("Failed to send verification email to user {}: {:?}", user.id, e);
+        }
 
-        Ok(AuthResponse {
-            token,
-            user: user.into(),
-        })
+        if self.require_email_verification {
+            return Ok(RegisterResponse::VerificationRequired {
+                user: user.into(),
+                verification_required: true,
+            });
+        }
+
+        Ok(RegisterResponse::Authenticated(self.start_session(user, client).await?))
     }
 
-    pub async fn login(&self, req: LoginRequest) -> Result<AuthResponse> {
-        // Find user
+    pub async fn login(&self, req: LoginRequest, client: &ClientInfo) -> Result<LoginResponse> {
+        self.throttle.check(&req.email, client.ip_address).await?;
+
+        // Find user; bots authenticate with API keys only
         let user = User::find()
-            .filter(user::Column::Email.eq(&req.email))
+            .filter(user::email_is(&req.email))
+            .one(&self.db)
+            .await?
+            .filter(|user| !user.is_bot);
+
+        // Unknown emails are checked against a dummy hash so they take as long as wrong passwords
+        let password_hash = user
+            .as_ref()
+            .map_or(self.dummy_password_hash.as_str(), |user| &user.password_hash);
+        let verified = self.passwords.verify(&req.password, password_hash);
+
+        let user = match (user, verified) {
+            (Some(user), Ok(())) => self.upgrade_password_hash(user, &req.password).await,
+            (user, _) => {
+                self.throttle
+                    .record_failure(&req.email, client.ip_address, user.map(|user| user.id))
+                    .await?;
+                return Err(AppError::InvalidCredentials);
+            }
+        };
+
+        ensure_enabled(&user)?;
+
+        if user.password_reset_required {
+            return Err(AppError::PasswordResetRequired);
+        }
+
+        if self.require_email_verification && user.email_verified_at.is_none() {
+            return Err(AppError::EmailNotVerified);
+        }
+
+        self.complete_login(user, client).await
+    }
+
+    /// Sign in with an identity asserted by an OpenID Connect provider.
+    ///
+    /// Known identities sign in their linked user. New identities are linked to the existing
+    /// account with the same verified email, or a new account is created, but only when the
+    /// provider vouches that the email is verified. Identities from a linking flow started by a
+    /// signed-in user are linked to that user.
+    pub async fn login_with_identity(
+        &self,
+        identity: OidcIdentity,
+        client: &ClientInfo,
+    ) -> Result<LoginResponse> {
+        let linked = UserIdentity::find()
+            .filter(user_identity::Column::Provider.eq(&identity.provider))
+            .filter(user_identity::Column::Subject.eq(&identity.subject))
+            .find_also_related(User)
+            .one(&self.db)
+            .await?;
+
+        let user = match (linked, identity.link_user_id) {
+            (Some((_, Some(user))), Some(link_user_id)) if user.id != link_user_id => {
+                return Err(AppError::BadRequest(
+                    "This identity is already linked to another account".to_string(),
+                ))
+            }
+            (Some((_, Some(user))), _) => user,
+            (_, Some(link_user_id)) => self.link_to_user(&identity, link_user_id).await?,
+            _ => self.link_identity(&identity).await?,
+        };
+
+        self.complete_login(user, client).await
+    }
+
+    /// Link an identity to the account of the signed-in user who asked for it
+    async fn link_to_user(&self, identity: &OidcIdentity, user_id: i32) -> Result<user::Model> {
+        let user = User::find_by_id(user_id)
+            .one(&self.db)
+            .await?
+            .filter(|user| user.deleted_at.is_none())
+            .ok_or(AppError::UserNotFound)?;
+
+        self.insert_identity(identity, user.id, identity.email.clone())
+            .await?;
+        Ok(user)
+    }
+
+    async fn link_identity(&self, identity: &OidcIdentity) -> Result<user::Model> {
+        let email = match (&identity.email, identity.email_verified) {
+            (Some(email), true) => user::normalize_email(email),
+            _ => {
+                return Err(AppError::AuthError(
+                    "Identity provider did not supply a verified email".to_string(),
+                ))
+            }
+        };
+
+        let now = Utc::now().naive_utc();
+
+        let existing = User::find()
+            .filter(user::email_is(&email))
+            .one(&self.db)
+            .await?;
+
+        let user = match existing {
+            Some(user) if user.email_verified_at.is_some() => user,
+            Some(_) => {
+                // Whoever registered the address never proved they own it, and may know the
+                // account's password; adopting it would let them share the SSO user's account
+                return Err(AppError::Forbidden(
+                    "An account with this email already exists; sign in to it and link this \
+                     provider from there"
+                        .to_string(),
+                ));
+            }
+            None => {
+                let name = identity
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| email.split('@').next().unwrap_or_default().to_string());
+                let username = available_username(&self.db, &name).await?;
+
+                // SSO-only accounts get a random password nobody knows; it can be reset later
+                let password_hash = self.passwords.hash(&generate_opaque_token(32))?;
+
+                user::ActiveModel {
+                    email: Set(email.clone()),
+                    password_hash: Set(password_hash),
+                    username: Set(username),
+                    created_at: Set(now),
+                    email_verified_at: Set(Some(now)),
+                    ..Default::default()
+                }
+                .insert(&self.db)
+                .await?
+            }
+        };
+
+        self.insert_identity(identity, user.id, Some(email)).await?;
+        Ok(user)
+    }
+
+    async fn insert_identity(
+        &self,
+        identity: &OidcIdentity,
+        user_id: i32,
+        email: Option<String>,
+    ) -> Result<()> {
+        user_identity::ActiveModel {
+            user_id: Set(user_id),
+            provider: Set(identity.provider.clone()),
+            subject: Set(identity.subject.clone()),
+            email: Set(email),
+            created_at: Set(Utc::now().naive_utc()),
+            ..Default::default()
+        }
+        .insert(&self.db)
+        .await?;
+
+        tracing::info!(
+            "Linked {} identity {} to user {}",
+            identity.provider,
+            identity.subject,
+            user_id
+        );
+        Ok(())
+    }
+
+    /// Email a single-use login link for `email`.
+    ///
+    /// Returns a nonce that must accompany the link's token, so the link only works on the
+    /// device that asked for it. Unknown addresses get a nonce too, so the endpoint cannot be
+    /// used to discover accounts.
+    pub async fn request_magic_link(&self, req: MagicLinkRequest) -> Result<MagicLinkResponse> {
+        let nonce = generate_opaque_token(32);
+
+        let user = User::find()
+            .filter(user::email_is(&req.email))
+            .one(&self.db)
+            .await?;
+
+        let Some(user) = user else {
+            return Ok(MagicLinkResponse { nonce });
+        };
+
+        let token = self
+            .user_tokens
+            .issue_for_device(
+                user.id,
+                TokenPurpose::MagicLink,
+                Duration::minutes(MAGIC_LINK_TTL_MINUTES),
+                &nonce,
+            )
+            .await?;
+
+        let link = format!("{}/magic-link?token={}", self.public_base_url, token);
+
+        self.mailer
+            .send(Email {
+                to: user.email.clone(),
+                subject: "Your sign-in link".to_string(),
+                body: format!(
+                    "Hi {},\n\nOpen this link to sign in:\n\n{}\n\n\
+                     The link expires in {} minutes, can be used once, and only works in the \
+                     browser or app where you asked for it. If you did not ask for it, \
+                     ignore this email.",
+                    user.username, link, MAGIC_LINK_TTL_MINUTES
+                ),
+            })
+            .await?;
+
+        Ok(MagicLinkResponse { nonce })
+    }
+
+    /// Exchange a magic link token and its device nonce for a session.
+    /// Two-factor authentication still applies.
+    pub async fn login_with_magic_link(
+        &self,
+        query: MagicLinkVerifyQuery,
+        client: &ClientInfo,
+    ) -> Result<LoginResponse> {
+        let user_id = self
+            .user_tokens
+            .consume_from_device(&query.token, TokenPurpose::MagicLink, &query.nonce)
+            .await?;
+
+        let user = User::find_by_id(user_id)
+            .one(&self.db)
+            .await?
+            .ok_or(AppError::InvalidToken)?;
+
+        // Receiving the link proves ownership of the address
+        let user = if user.email_verified_at.is_none() {
+            let mut user: user::ActiveModel = user.into();
+            user.email_verified_at = Set(Some(Utc::now().naive_utc()));
+            user.update(&self.db).await?
+        } else {
+            user
+        };
+
+        self.complete_login(user, client).await
+    }
+
+    /// Sign in a user who has just completed a passkey assertion. User verification by the
+    /// authenticator stands in for both factors, so TOTP is not asked for.
+    pub async fn login_with_passkey(
+        &self,
+        user_id: i32,
+        client: &ClientInfo,
+    ) -> Result<AuthResponse> {
+        let user = User::find_by_id(user_id)
             .one(&self.db)
             .await?
             .ok_or(AppError::InvalidCredentials)?;
 
-        // Verify password
-        self.verify_password(&req.password, &user.password_hash)?;
+        if self.require_email_verification && user.email_verified_at.is_none() {
+            return Err(AppError::EmailNotVerified);
+        }
+
+        self.start_session(user, client).await
+    }
+
+    /// Second step of login for users with two-factor authentication
+    pub async fn login_mfa(
+        &self,
+        req: MfaLoginRequest,
+        client: &ClientInfo,
+    ) -> Result<AuthResponse> {
+        let user_id = self.jwt_service.verify_mfa_token(&req.mfa_token)?;
+
+        let user = User::find_by_id(user_id)
+            .one(&self.db)
+            .await?
+            .ok_or(AppError::InvalidToken)?;
+
+        self.verify_code(&user, &req.code, client).await?;
+        self.start_session(user, client).await
+    }
+
+    /// Start TOTP enrollment, returning the secret as an otpauth URI and QR code
+    pub async fn begin_totp_enrollment(&self, user_id: i32) -> Result<TotpEnrollmentResponse> {
+        let user = User::find_by_id(user_id)
+            .one(&self.db)
+            .await?
+            .ok_or(AppError::UserNotFound)?;
+
+        self.totp.begin_enrollment(user.id, &user.email).await
+    }
+
+    /// Enable TOTP after checking a code; returns one-time recovery codes
+    pub async fn confirm_totp_enrollment(&self, user_id: i32, code: &str) -> Result<Vec<String>> {
+        self.totp.confirm_enrollment(user_id, code).await
+    }
+
+    /// Turn TOTP off. Requires both the password and a current code (or recovery code).
+    pub async fn disable_totp(
+        &self,
+        user_id: i32,
+        req: DisableTotpRequest,
+        client: &ClientInfo,
+    ) -> Result<()> {
+        let user = User::find_by_id(user_id)
+            .one(&self.db)
+            .await?
+            .ok_or(AppError::UserNotFound)?;
+
+        self.verify_password(&user, &req.password, client).await?;
+        let user = self.upgrade_password_hash(user, &req.password).await;
+        self.verify_code(&user, &req.code, client).await?;
+        self.totp.disable(user.id).await
+    }
+
+    /// Confirm that the caller of an irreversible action is the account holder, not merely
+    /// someone holding their token: by the current password, by a second-factor code, or by
+    /// having signed in within `REAUTH_WINDOW_MINUTES`. Wrong passwords and codes count towards
+    /// the login lockout.
+    pub async fn reauthenticate(
+        &self,
+        claims: &Claims,
+        password: Option<&str>,
+        code: Option<&str>,
+        client: &ClientInfo,
+    ) -> Result<()> {
+        let user = User::find_by_id(claims.user_id()?)
+            .one(&self.db)
+            .await?
+            .ok_or(AppError::UserNotFound)?;
+
+        if let Some(password) = password {
+            return self.verify_password(&user, password, client).await;
+        }
+
+        if let Some(code) = code {
+            return self.verify_code(&user, code, client).await;
+        }
+
+        let since = Utc::now().naive_utc() - Duration::minutes(REAUTH_WINDOW_MINUTES);
+        match claims.session_id() {
+            Some(session_id) if self.sessions.started_after(user.id, session_id, since).await? => {
+                Ok(())
+            }
+            _ => Err(AppError::Forbidden(
+                "Confirm with your password or a two-factor code, or sign in again".to_string(),
+            )),
+        }
+    }
+
+    /// Mark the email address behind a verification token as verified
+    pub async fn verify_email(&self, req: VerifyEmailRequest) -> Result<UserResponse> {
+        let user_id = self
+            .user_tokens
+            .consume(&req.token, TokenPurpose::EmailVerification)
+            .await?;
+
+        let user = User::find_by_id(user_id)
+            .one(&self.db)
+            .await?
+            .ok_or(AppError::InvalidToken)?;
+
+        if user.email_verified_at.is_some() {
+            return Ok(user.into());
+        }
+
+        let mut user: user::ActiveModel = user.into();
+        user.email_verified_at = Set(Some(Utc::now().naive_utc()));
+        let user = user.update(&self.db).await?;
+
+        Ok(user.into())
+    }
+
+    /// Send a fresh verification link. Unknown or already verified addresses are ignored
+    /// silently so the endpoint cannot be used to discover accounts.
+    pub async fn resend_verification(&self, req: ResendVerificationRequest) -> Result<()> {
+        let user = User::find()
+            .filter(user::email_is(&req.email))
+            .one(&self.db)
+            .await?;
+
+        match user {
+            Some(user) if user.email_verified_at.is_none() => {
+                self.send_verification_email(&user).await
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Email a password reset link. Unknown addresses are ignored silently so the endpoint
+    /// cannot be used to discover accounts.
+    pub async fn forgot_password(&self, req: ForgotPasswordRequest) -> Result<()> {
+        let user = User::find()
+            .filter(user::email_is(&req.email))
+            .one(&self.db)
+            .await?;
+
+        let Some(user) = user else {
+            return Ok(());
+        };
+
+        self.send_password_reset(&user).await
+    }
+
+    /// Make the user choose a new password: password logins are refused, every session is
+    /// signed out and a reset link is emailed. Other sign-in methods keep working.
+    pub async fn force_password_reset(&self, user_id: i32) -> Result<()> {
+        let user = User::find_by_id(user_id)
+            .one(&self.db)
+            .await?
+            .ok_or(AppError::UserNotFound)?;
+
+        let mut active: user::ActiveModel = user.into();
+        active.password_reset_required = Set(true);
+        let user = active.update(&self.db).await?;
+
+        self.logout_all(user_id).await?;
+
+        // Bots have no password and no mailbox
+        if user.is_bot {
+            return Ok(());
+        }
+        self.send_password_reset(&user).await
+    }
+
+    async fn send_password_reset(&self, user: &user::Model) -> Result<()> {
+        let token = self
+            .user_tokens
+            .issue(
+                user.id,
+                TokenPurpose::PasswordReset,
+                Duration::minutes(PASSWORD_RESET_TTL_MINUTES),
+            )
+            .await?;
+
+        let link = format!("{}/reset-password?token={}", self.public_base_url, token);
+
+        self.mailer
+            .send(Email {
+                to: user.email.clone(),
+                subject: "Reset your password".to_string(),
+                body: format!(
+                    "Hi {},\n\nSomeone asked to reset the password for your account. \
+                     To choose a new password, open this link:\n\n{}\n\n\
+                     The link expires in {} minutes and can be used once. \
+                     If you did not ask for this, ignore this email.",
+                    user.username, link, PASSWORD_RESET_TTL_MINUTES
+                ),
+            })
+            .await
+    }
+
+    /// Set a new password using a reset token and sign the user out everywhere.
+    /// Returns the user id so callers can close live connections.
+    pub async fn reset_password(&self, req: ResetPasswordRequest) -> Result<i32> {
+        let user_id = self
+            .user_tokens
+            .consume(&req.token, TokenPurpose::PasswordReset)
+            .await?;
+
+        let user = User::find_by_id(user_id)
+            .one(&self.db)
+            .await?
+            .ok_or(AppError::InvalidToken)?;
+
+        // Receiving the reset email proves ownership of the address
+        let verified_at = user.email_verified_at.or(Some(Utc::now().naive_utc()));
+
+        let mut user: user::ActiveModel = user.into();
+        user.password_hash = Set(self.passwords.hash(&req.new_password)?);
+        user.email_verified_at = Set(verified_at);
+        user.password_reset_required = Set(false);
+        user.update(&self.db).await?;
+
+        self.logout_all(user_id).await?;
+        Ok(user_id)
+    }
+
+    /// Change the password of a signed-in user after re-checking the current one.
+    /// Every existing session is revoked; the caller receives fresh tokens.
+    pub async fn change_password(
+        &self,
+        user_id: i32,
+        req: ChangePasswordRequest,
+        client: &ClientInfo,
+    ) -> Result<AuthResponse> {
+        let user = User::find_by_id(user_id)
+            .one(&self.db)
+            .await?
+            .ok_or(AppError::UserNotFound)?;
+
+        self.verify_password(&user, &req.current_password, client)
+            .await?;
 
-        // Generate token
-        let token = self.jwt_service.generate_token(user.id, &user.email)?;
+        let mut user: user::ActiveModel = user.into();
+        user.password_hash = Set(self.passwords.hash(&req.new_password)?);
+        user.password_reset_required = Set(false);
+        let user = user.update(&self.db).await?;
+
+        self.logout_all(user_id).await?;
+
+        self.start_session(user, client).await
+    }
+
+    /// Rotate a refresh token and issue a new access token alongside it
+    pub async fn refresh(&self, req: RefreshRequest, client: &ClientInfo) -> Result<AuthResponse> {
+        let (user_id, refresh_token, family_id) =
+            self.refresh_tokens.rotate(&req.refresh_token).await?;
+
+        let user = User::find_by_id(user_id)
+            .one(&self.db)
+            .await?
+            .ok_or(AppError::InvalidToken)?;
+        ensure_enabled(&user)?;
+
+        // Families issued before sessions were recorded have no session row
+        let session_id = self
+            .sessions
+            .touch(family_id, client)
+            .await?
+            .then_some(family_id);
+
+        self.auth_response(user, refresh_token, session_id)
+    }
+
+    /// Revoke the presented access token and the session it belongs to. Tokens without a
+    /// session revoke the refresh token family they came with, if given.
+    pub async fn logout(&self, claims: &Claims, refresh_token: Option<&str>) -> Result<()> {
+        self.revocation.revoke_token(claims).await?;
+
+        if let Some(session_id) = claims.session_id() {
+            self.revoke_session(claims.user_id()?, session_id).await?;
+        }
+
+        if let Some(refresh_token) = refresh_token {
+            self.refresh_tokens
+                .revoke_family_of(refresh_token, claims.user_id()?)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Revoke every access and refresh token the user holds
+    pub async fn logout_all(&self, user_id: i32) -> Result<()> {
+        self.revocation.revoke_all_for_user(user_id).await?;
+        self.refresh_tokens.revoke_all_for_user(user_id).await?;
+        self.sessions.delete_all(user_id).await
+    }
+
+    /// List where the user is signed in, marking the session `current_id`
+    pub async fn list_sessions(
+        &self,
+        user_id: i32,
+        current_id: Option<Uuid>,
+    ) -> Result<Vec<SessionResponse>> {
+        let sessions = self.sessions.list(user_id).await?;
+
+        Ok(sessions
+            .into_iter()
+            .map(|session| SessionResponse::new(session, current_id))
+            .collect())
+    }
+
+    /// Sign a session out: its refresh tokens stop working and its access tokens are rejected
+    /// immediately. Returns whether the session existed; callers should close its connections.
+    pub async fn revoke_session(&self, user_id: i32, session_id: Uuid) -> Result<bool> {
+        if !self.sessions.delete(user_id, session_id).await? {
+            return Ok(false);
+        }
+
+        self.refresh_tokens.revoke_family(session_id).await?;
+        self.revocation.revoke_session(session_id).await?;
+
+        Ok(true)
+    }
+
+    async fn send_verification_email(&self, user: &user::Model) -> Result<()> {
+        let token = self
+            .user_tokens
+            .issue(
+                user.id,
+                TokenPurpose::EmailVerification,
+                Duration::hours(EMAIL_VERIFICATION_TTL_HOURS),
+            )
+            .await?;
+
+        let link = format!("{}/verify-email?token={}", self.public_base_url, token);
+
+        self.mailer
+            .send(Email {
+                to: user.email.clone(),
+                subject: "Verify your email address".to_string(),
+                body: format!(
+                    "Hi {},\n\nConfirm your email address by opening this link:\n\n{}\n\n\
+                     The link expires in {} hours. If you did not sign up, ignore this email.",
+                    user.username, link, EMAIL_VERIFICATION_TTL_HOURS
+                ),
+            })
+            .await
+    }
+
+    /// Issue tokens for a user who passed the first factor, or ask for the second one.
+    /// Failed login attempts are only forgotten once no second factor is pending.
+    async fn complete_login(
+        &self,
+        user: user::Model,
+        client: &ClientInfo,
+    ) -> Result<LoginResponse> {
+        ensure_enabled(&user)?;
+
+        if self.totp.is_enabled(user.id).await? {
+            return Ok(LoginResponse::MfaRequired {
+                mfa_token: self.jwt_service.generate_mfa_token(user.id)?,
+                mfa_required: true,
+            });
+        }
+
+        self.throttle.record_success(&user.email).await;
+        Ok(LoginResponse::Authenticated(self.start_session(user, client).await?))
+    }
+
+    /// Record a new session for `user` and issue its first tokens
+    /// Check the password of a known user, counting a wrong one towards the login lockout of
+    /// the account and the client IP
+    async fn verify_password(
+        &self,
+        user: &user::Model,
+        password: &str,
+        client: &ClientInfo,
+    ) -> Result<()> {
+        self.throttle.check(&user.email, client.ip_address).await?;
+
+        if self.passwords.verify(password, &user.password_hash).is_err() {
+            self.throttle
+                .record_failure(&user.email, client.ip_address, Some(user.id))
+                .await?;
+            return Err(AppError::InvalidCredentials);
+        }
+
+        self.throttle.record_success(&user.email).await;
+        Ok(())
+    }
+
+    /// Check a second-factor code of a known user, counting a wrong one towards the login
+    /// lockout of the account and the client IP
+    async fn verify_code(&self, user: &user::Model, code: &str, client: &ClientInfo) -> Result<()> {
+        self.throttle.check(&user.email, client.ip_address).await?;
+
+        if let Err(err) = self.totp.verify(user.id, code).await {
+            if matches!(err, AppError::InvalidTwoFactorCode) {
+                self.throttle
+                    .record_failure(&user.email, client.ip_address, Some(user.id))
+                    .await?;
+            }
+            return Err(err);
+        }
+
+        self.throttle.record_success(&user.email).await;
+        Ok(())
+    }
+
+    async fn start_session(&self, user: user::Model, client: &ClientInfo) -> Result<AuthResponse> {
+        ensure_enabled(&user)?;
+        let session_id = self.sessions.start(user.id, client).await?;
+        let refresh_token = self.refresh_tokens.issue(user.id, session_id).await?;
+        self.auth_response(user, refresh_token, Some(session_id))
+    }
+
+    fn auth_response(
+        &self,
+        user: user::Model,
+        refresh_token: String,
+        session_id: Option<Uuid>,
+    ) -> Result<AuthResponse> {
+        let token = self
+            .jwt_service
+            .generate_token(user.id, &user.email, user.role, session_id)?;
 
         Ok(AuthResponse {
             token,
+            refresh_token,
+            expires_in: self.jwt_service.expires_in(),
             user: user.into(),
         })
     }
 
-    fn hash_password(&self, password: &str) -> Result<String> {
-        let salt = SaltString::generate(&mut OsRng);
-        let argon2 = Argon2::default();
+    /// Rehash a just-verified password if its hash predates the current hashing parameters.
+    /// Failures are logged and the old hash kept; the login goes ahead either way.
+    async fn upgrade_password_hash(&self, user: user::Model, password: &str) -> user::Model {
+        if !self.passwords.needs_rehash(&user.password_hash) {
+            return user;
+        }
 
-        argon2
-            .hash_password(password.as_bytes(), &salt)
-            .map(|hash| hash.to_string())
-            .map_err(|_| AppError::PasswordHashError)
-    }
+        let password_hash = match self.passwords.hash(password) {
+            Ok(hash) => hash,
+            Err(e) => {
+                tracing::error!("Failed to rehash password of user {}: {:?}", user.id, e);
+                return user;
+            }
+        };
 
-    fn verify_password(&self, password: &str, hash: &str) -> Result<()> {
-        let parsed_hash = PasswordHash::new(hash).map_err(|_| AppError::PasswordHashError)?;
+        let user_id = user.id;
+        let mut active: user::ActiveModel = user.clone().into();
+        active.password_hash = Set(password_hash);
+        match active.update(&self.db).await {
+            Ok(updated) => {
+                tracing::info!("Upgraded password hash of user {}", user_id);
+                updated
+            }
+            Err(e) => {
+                tracing::error!("Failed to store rehashed password of user {}: {:?}", user_id, e);
+                user
+            }
+        }
+    }
+}
 
-        Argon2::default()
-            .verify_password(password.as_bytes(), &parsed_hash)
-            .map_err(|_| AppError::InvalidCredentials)
+/// Refuse users an admin has disabled
+fn ensure_enabled(user: &user::Model) -> Result<()> {
+    if user.disabled_at.is_some() {
+        return Err(AppError::AccountDisabled);
     }
+    Ok(())
 }
@@ -0,0 +1,94 @@
+use crate::errors::{AppError, Result};
+use crate::models::user::Entity as User;
+use crate::models::user_block::{self, BlockKind, BlockResponse, Entity as UserBlock};
+use chrono::Utc;
+use sea_orm::{
+    sea_query::OnConflict, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder,
+    QuerySelect, Set,
+};
+use std::collections::HashSet;
+
+/// Manages per-user block and mute lists
+#[derive(Clone)]
+pub struct BlockService {
+    db: DatabaseConnection,
+}
+
+impl BlockService {
+    pub fn new(db: DatabaseConnection) -> Self {
+        Self { db }
+    }
+
+    /// Block or mute `target_id` on behalf of `user_id`, replacing any existing entry
+    pub async fn set(&self, user_id: i32, target_id: i32, kind: BlockKind) -> Result<BlockResponse> {
+        if user_id == target_id {
+            return Err(AppError::BadRequest("You cannot block yourself".to_string()));
+        }
+
+        User::find_by_id(target_id)
+            .one(&self.db)
+            .await?
+            .ok_or(AppError::UserNotFound)?;
+
+        let entry = user_block::ActiveModel {
+            user_id: Set(user_id),
+            target_id: Set(target_id),
+            kind: Set(kind),
+            created_at: Set(Utc::now().naive_utc()),
+            ..Default::default()
+        };
+
+        UserBlock::insert(entry)
+            .on_conflict(
+                OnConflict::columns([user_block::Column::UserId, user_block::Column::TargetId])
+                    .update_columns([user_block::Column::Kind, user_block::Column::CreatedAt])
+                    .to_owned(),
+            )
+            .exec(&self.db)
+            .await?;
+
+        let block = UserBlock::find()
+            .filter(user_block::Column::UserId.eq(user_id))
+            .filter(user_block::Column::TargetId.eq(target_id))
+            .one(&self.db)
+            .await?
+            .ok_or(AppError::InternalServerError)?;
+
+        Ok(block.into())
+    }
+
+    /// Remove a block or mute; removing a missing entry is not an error
+    pub async fn remove(&self, user_id: i32, target_id: i32) -> Result<()> {
+        UserBlock::delete_many()
+            .filter(user_block::Column::UserId.eq(user_id))
+            .filter(user_block::Column::TargetId.eq(target_id))
+            .exec(&self.db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// List everyone `user_id` has blocked or muted, newest first
+    pub async fn list(&self, user_id: i32) -> Result<Vec<BlockResponse>> {
+        let blocks = UserBlock::find()
+            .filter(user_block::Column::UserId.eq(user_id))
+            .order_by_desc(user_block::Column::CreatedAt)
+            .all(&self.db)
+            .await?;
+
+        Ok(blocks.into_iter().map(Into::into).collect())
+    }
+
+    /// Senders whose messages should be hidden from `user_id` (blocked and muted)
+    pub async fn hidden_senders(&self, user_id: i32) -> Result<HashSet<i32>> {
+        let ids: Vec<i32> = UserBlock::find()
+            .select_only()
+            .column(user_block::Column::TargetId)
+            .filter(user_block::Column::UserId.eq(user_id))
+            .into_tuple()
+            .all(&self.db)
+            .await?;
+
+        Ok(ids.into_iter().collect())
+    }
+}
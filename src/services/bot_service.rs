@@ -0,0 +1,77 @@
+use crate::errors::{AppError, Result};
+use crate::models::user::{self, CreateBotRequest, Entity as User, UserResponse};
+use crate::services::profile_service::username_taken;
+use chrono::Utc;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, Set,
+};
+use uuid::Uuid;
+
+/// Placeholder stored as the password hash of bots. It is not a valid hash, so no password
+/// can ever match it.
+pub const UNUSABLE_PASSWORD_HASH: &str = "!";
+
+/// Bot accounts owned and managed by regular users
+#[derive(Clone)]
+pub struct BotService {
+    db: DatabaseConnection,
+}
+
+impl BotService {
+    pub fn new(db: DatabaseConnection) -> Self {
+        Self { db }
+    }
+
+    pub async fn create(&self, owner_id: i32, req: CreateBotRequest) -> Result<UserResponse> {
+        if username_taken(&self.db, &req.username, None).await? {
+            return Err(AppError::UsernameTaken);
+        }
+
+        // Bots have no mailbox; `.invalid` is reserved and never resolves
+        let email = format!("bot-{}@bots.invalid", Uuid::new_v4().simple());
+
+        let bot = user::ActiveModel {
+            email: Set(email),
+            password_hash: Set(UNUSABLE_PASSWORD_HASH.to_string()),
+            username: Set(req.username),
+            created_at: Set(Utc::now().naive_utc()),
+            is_bot: Set(true),
+            bot_owner_id: Set(Some(owner_id)),
+            ..Default::default()
+        }
+        .insert(&self.db)
+        .await?;
+
+        tracing::info!("User {} created bot {}", owner_id, bot.id);
+
+        Ok(bot.into())
+    }
+
+    pub async fn list(&self, owner_id: i32) -> Result<Vec<UserResponse>> {
+        let bots = User::find()
+            .filter(user::Column::BotOwnerId.eq(owner_id))
+            .order_by_asc(user::Column::Id)
+            .all(&self.db)
+            .await?;
+
+        Ok(bots.into_iter().map(Into::into).collect())
+    }
+
+    /// Look up a bot, making sure `owner_id` manages it
+    pub async fn owned(&self, owner_id: i32, bot_id: i32) -> Result<user::Model> {
+        User::find_by_id(bot_id)
+            .filter(user::Column::BotOwnerId.eq(owner_id))
+            .one(&self.db)
+            .await?
+            .ok_or(AppError::UserNotFound)
+    }
+
+    /// Delete a bot together with its keys and messages
+    pub async fn delete(&self, owner_id: i32, bot_id: i32) -> Result<()> {
+        let bot = self.owned(owner_id, bot_id).await?;
+        User::delete_by_id(bot.id).exec(&self.db).await?;
+
+        tracing::info!("User {} deleted bot {}", owner_id, bot_id);
+        Ok(())
+    }
+}
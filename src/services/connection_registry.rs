@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+use uuid::Uuid;
+
+/// Why the server is closing a live WebSocket
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CloseReason {
+    TokenRevoked,
+    AccountDeleted,
+    AccountDisabled,
+    /// Kicked or banned from the room the socket is connected to
+    RemovedFromRoom,
+    RoomDeleted,
+    /// The user left the room the socket is connected to
+    LeftRoom,
+}
+
+impl CloseReason {
+    /// Application-defined WebSocket close code (4000-4999 range)
+    pub fn code(&self) -> u16 {
+        match self {
+            CloseReason::TokenRevoked => 4001,
+            CloseReason::AccountDeleted => 4002,
+            CloseReason::AccountDisabled => 4003,
+            CloseReason::RemovedFromRoom => 4004,
+            CloseReason::RoomDeleted => 4005,
+            CloseReason::LeftRoom => 4006,
+        }
+    }
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            CloseReason::TokenRevoked => "Token revoked",
+            CloseReason::AccountDeleted => "Account deleted",
+            CloseReason::AccountDisabled => "Account disabled",
+            CloseReason::RemovedFromRoom => "Removed from room",
+            CloseReason::RoomDeleted => "Room deleted",
+            CloseReason::LeftRoom => "Left room",
+        }
+    }
+}
+
+struct LiveConnection {
+    user_id: i32,
+    /// `None` for the user's own event channel
+    room_id: Option<i32>,
+    jti: String,
+    session_id: Option<Uuid>,
+    close: oneshot::Sender<CloseReason>,
+}
+
+/// Tracks open WebSocket connections so they can be closed when their credentials are revoked
+#[derive(Default)]
+pub struct ConnectionRegistry {
+    connections: Mutex<HashMap<Uuid, LiveConnection>>,
+}
+
+/// Keeps a connection registered; dropping it unregisters the connection
+pub struct ConnectionGuard {
+    id: Uuid,
+    registry: Arc<ConnectionRegistry>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.registry.lock().remove(&self.id);
+    }
+}
+
+impl ConnectionRegistry {
+    /// Register a connection to `room_id` (or to the user's event channel) authenticated by the
+    /// token `jti`, issued to `session_id`.
+    ///
+    /// The returned receiver fires if the connection should be closed by the server.
+    pub fn register(
+        self: &Arc<Self>,
+        user_id: i32,
+        room_id: Option<i32>,
+        jti: &str,
+        session_id: Option<Uuid>,
+    ) -> (ConnectionGuard, oneshot::Receiver<CloseReason>) {
+        let id = Uuid::new_v4();
+        let (close, closed) = oneshot::channel();
+
+        self.lock().insert(
+            id,
+            LiveConnection {
+                user_id,
+                room_id,
+                jti: jti.to_string(),
+                session_id,
+                close,
+            },
+        );
+
+        let guard = ConnectionGuard {
+            id,
+            registry: Arc::clone(self),
+        };
+
+        (guard, closed)
+    }
+
+    /// Close every connection opened with the token `jti`
+    pub fn close_token(&self, jti: &str, reason: CloseReason) -> usize {
+        self.close_where(|conn| conn.jti == jti, reason)
+    }
+
+    /// Close every connection opened with tokens of the session `session_id`
+    pub fn close_session(&self, session_id: Uuid, reason: CloseReason) -> usize {
+        self.close_where(|conn| conn.session_id == Some(session_id), reason)
+    }
+
+    /// Close every connection belonging to `user_id`
+    pub fn close_user(&self, user_id: i32, reason: CloseReason) -> usize {
+        self.close_where(|conn| conn.user_id == user_id, reason)
+    }
+
+    /// Close the connections of `user_id` to `room_id`
+    pub fn close_room_member(&self, room_id: i32, user_id: i32, reason: CloseReason) -> usize {
+        self.close_where(
+            |conn| conn.room_id == Some(room_id) && conn.user_id == user_id,
+            reason,
+        )
+    }
+
+    /// Close every connection to `room_id`
+    pub fn close_room(&self, room_id: i32, reason: CloseReason) -> usize {
+        self.close_where(|conn| conn.room_id == Some(room_id), reason)
+    }
+
+    /// Number of open connections
+    pub fn count(&self) -> usize {
+        self.lock().len()
+    }
+
+    fn close_where(&self, predicate: impl Fn(&LiveConnection) -> bool, reason: CloseReason) -> usize {
+        let mut connections = self.lock();
+        let ids: Vec<Uuid> = connections
+            .iter()
+            .filter(|(_, conn)| predicate(conn))
+            .map(|(id, _)| *id)
+            .collect();
+
+        for id in &ids {
+            if let Some(conn) = connections.remove(id) {
+                let _ = conn.close.send(reason);
+            }
+        }
+
+        ids.len()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<Uuid, LiveConnection>> {
+        // A panic while holding the lock cannot leave the map inconsistent, so recover from poison
+        self.connections
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
@@ -0,0 +1,305 @@
+use crate::errors::{AppError, Result};
+use crate::models::room::{self, Entity as Room, RoomKind, RoomVisibility};
+use crate::models::room_invitation::{self, Entity as RoomInvitation};
+use crate::models::room_join_request::{self, CreateJoinRequest, Entity as RoomJoinRequest};
+use crate::models::room_permission::Permission;
+use crate::models::user::{self, Entity as User};
+use crate::services::rate_limiter::RateLimiter;
+use crate::services::room_service::{RoomAccess, RoomService};
+use chrono::Utc;
+use sea_orm::{
+    sea_query::OnConflict, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder,
+    Set,
+};
+use std::sync::Arc;
+
+/// Join requests a user may send within `JOIN_REQUEST_WINDOW_SECONDS`
+const JOIN_REQUEST_LIMIT: u64 = 10;
+const JOIN_REQUEST_WINDOW_SECONDS: u64 = 60 * 60;
+
+/// Invitations of specific users into rooms, and requests to join private rooms
+#[derive(Clone)]
+pub struct InvitationService {
+    db: DatabaseConnection,
+    rooms: Arc<RoomService>,
+    limiter: Arc<RateLimiter>,
+}
+
+impl InvitationService {
+    pub fn new(db: DatabaseConnection, rooms: Arc<RoomService>, limiter: Arc<RateLimiter>) -> Self {
+        Self { db, rooms, limiter }
+    }
+
+    /// Invite `user_id` into the room; needs `invite`. Inviting someone twice keeps the first
+    /// invitation.
+    pub async fn invite(
+        &self,
+        access: &RoomAccess,
+        user_id: i32,
+    ) -> Result<room_invitation::Model> {
+        access.require(Permission::Invite)?;
+        self.rooms.require_user(user_id).await?;
+
+        if self.rooms.member(access.room.id, user_id).await?.is_some() {
+            return Err(AppError::BadRequest(
+                "That user is already in this room".to_string(),
+            ));
+        }
+        self.rooms.check_admission(&access.room, user_id).await?;
+        if self.rooms.blocked_between(access.user_id, user_id).await? {
+            return Err(AppError::Forbidden(
+                "You cannot invite this user".to_string(),
+            ));
+        }
+
+        RoomInvitation::insert(room_invitation::ActiveModel {
+            room_id: Set(access.room.id),
+            user_id: Set(user_id),
+            invited_by: Set(Some(access.user_id)),
+            created_at: Set(Utc::now().naive_utc()),
+            ..Default::default()
+        })
+        .on_conflict(
+            OnConflict::columns([
+                room_invitation::Column::RoomId,
+                room_invitation::Column::UserId,
+            ])
+            .do_nothing()
+            .to_owned(),
+        )
+        .exec_without_returning(&self.db)
+        .await?;
+
+        RoomInvitation::find()
+            .filter(room_invitation::Column::RoomId.eq(access.room.id))
+            .filter(room_invitation::Column::UserId.eq(user_id))
+            .one(&self.db)
+            .await?
+            .ok_or(AppError::InternalServerError)
+    }
+
+    /// Pending invitations into the room; needs `invite`
+    pub async fn room_invitations(
+        &self,
+        access: &RoomAccess,
+    ) -> Result<Vec<room_invitation::Model>> {
+        access.require(Permission::Invite)?;
+
+        Ok(RoomInvitation::find()
+            .filter(room_invitation::Column::RoomId.eq(access.room.id))
+            .order_by_desc(room_invitation::Column::CreatedAt)
+            .all(&self.db)
+            .await?)
+    }
+
+    /// Withdraw the invitation of `user_id`; anyone may withdraw their own, others' need
+    /// `manage-room`
+    pub async fn cancel(&self, access: &RoomAccess, user_id: i32) -> Result<()> {
+        let Some(invitation) = RoomInvitation::find()
+            .filter(room_invitation::Column::RoomId.eq(access.room.id))
+            .filter(room_invitation::Column::UserId.eq(user_id))
+            .one(&self.db)
+            .await?
+        else {
+            return Ok(());
+        };
+
+        if invitation.invited_by != Some(access.user_id) {
+            access.require(Permission::ManageRoom)?;
+        }
+
+        RoomInvitation::delete_by_id(invitation.id)
+            .exec(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    /// Invitations waiting for `user_id`, newest first
+    pub async fn inbox(&self, user_id: i32) -> Result<Vec<(room_invitation::Model, room::Model)>> {
+        Ok(RoomInvitation::find()
+            .filter(room_invitation::Column::UserId.eq(user_id))
+            .find_also_related(Room)
+            .order_by_desc(room_invitation::Column::CreatedAt)
+            .all(&self.db)
+            .await?
+            .into_iter()
+            .filter_map(|(invitation, room)| Some((invitation, room?)))
+            .collect())
+    }
+
+    /// Accept or decline an invitation of `user_id`. Accepting makes them a member.
+    pub async fn respond(
+        &self,
+        user_id: i32,
+        invitation_id: i32,
+        accept: bool,
+    ) -> Result<(room_invitation::Model, room::Model)> {
+        let (invitation, room) = RoomInvitation::find_by_id(invitation_id)
+            .filter(room_invitation::Column::UserId.eq(user_id))
+            .find_also_related(Room)
+            .one(&self.db)
+            .await?
+            .and_then(|(invitation, room)| Some((invitation, room?)))
+            .ok_or(AppError::InvitationNotFound)?;
+
+        if accept && self.rooms.member(room.id, user_id).await?.is_none() {
+            if self.rooms.ban(room.id, user_id).await?.is_some() {
+                return Err(AppError::Forbidden(
+                    "You are banned from this room".to_string(),
+                ));
+            }
+            self.rooms.check_admission(&room, user_id).await?;
+            self.rooms.add(room.id, user_id).await?;
+        }
+
+        RoomInvitation::delete_by_id(invitation.id)
+            .exec(&self.db)
+            .await?;
+
+        tracing::info!(
+            "User {} {} the invitation into room {}",
+            user_id,
+            if accept { "accepted" } else { "declined" },
+            room.id
+        );
+        Ok((invitation, room))
+    }
+
+    /// Ask to be let into a private room. Asking again replaces the message.
+    ///
+    /// Returns the request only if one was recorded. Missing, public and direct rooms, rooms
+    /// the user is already in and rooms they are banned from are ignored without saying so,
+    /// so the endpoint cannot be used to find out which private rooms exist.
+    pub async fn request_to_join(
+        &self,
+        user_id: i32,
+        room_id: i32,
+        req: CreateJoinRequest,
+    ) -> Result<Option<(room_join_request::Model, user::Model)>> {
+        self.limiter
+            .hit(
+                "join_request",
+                &user_id.to_string(),
+                JOIN_REQUEST_LIMIT,
+                JOIN_REQUEST_WINDOW_SECONDS,
+            )
+            .await?;
+
+        let room = Room::find_by_id(room_id)
+            .one(&self.db)
+            .await?
+            .filter(|room| room.kind == RoomKind::Channel)
+            .filter(|room| room.visibility != RoomVisibility::Public);
+        let Some(room) = room else {
+            return Ok(None);
+        };
+
+        if self.rooms.member(room.id, user_id).await?.is_some()
+            || self.rooms.ban(room.id, user_id).await?.is_some()
+        {
+            return Ok(None);
+        }
+
+        let message = req
+            .message
+            .map(|message| message.trim().to_string())
+            .filter(|message| !message.is_empty());
+        RoomJoinRequest::insert(room_join_request::ActiveModel {
+            room_id: Set(room.id),
+            user_id: Set(user_id),
+            message: Set(message),
+            created_at: Set(Utc::now().naive_utc()),
+            ..Default::default()
+        })
+        .on_conflict(
+            OnConflict::columns([
+                room_join_request::Column::RoomId,
+                room_join_request::Column::UserId,
+            ])
+            .update_column(room_join_request::Column::Message)
+            .to_owned(),
+        )
+        .exec_without_returning(&self.db)
+        .await?;
+
+        self.join_request(room.id, user_id)
+            .await?
+            .ok_or(AppError::InternalServerError)
+            .map(Some)
+    }
+
+    /// Withdraw the caller's request to join `room_id`
+    pub async fn withdraw(&self, user_id: i32, room_id: i32) -> Result<()> {
+        RoomJoinRequest::delete_many()
+            .filter(room_join_request::Column::RoomId.eq(room_id))
+            .filter(room_join_request::Column::UserId.eq(user_id))
+            .exec(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    /// Pending requests to join the room, oldest first; needs `invite`
+    pub async fn join_requests(
+        &self,
+        access: &RoomAccess,
+    ) -> Result<Vec<(room_join_request::Model, user::Model)>> {
+        access.require(Permission::Invite)?;
+
+        Ok(RoomJoinRequest::find()
+            .filter(room_join_request::Column::RoomId.eq(access.room.id))
+            .find_also_related(User)
+            .order_by_asc(room_join_request::Column::CreatedAt)
+            .all(&self.db)
+            .await?
+            .into_iter()
+            .filter_map(|(request, user)| Some((request, user?)))
+            .collect())
+    }
+
+    /// Approve or reject the request of `user_id`; needs `invite`. Approving makes them a member.
+    pub async fn decide(
+        &self,
+        access: &RoomAccess,
+        user_id: i32,
+        approve: bool,
+    ) -> Result<room_join_request::Model> {
+        access.require(Permission::Invite)?;
+
+        let (request, _) = self
+            .join_request(access.room.id, user_id)
+            .await?
+            .ok_or(AppError::JoinRequestNotFound)?;
+
+        if approve {
+            self.rooms.check_admission(&access.room, user_id).await?;
+            self.rooms.add(access.room.id, user_id).await?;
+        }
+
+        RoomJoinRequest::delete_by_id(request.id)
+            .exec(&self.db)
+            .await?;
+
+        tracing::info!(
+            "User {} {} the request of user {} to join room {}",
+            access.user_id,
+            if approve { "approved" } else { "rejected" },
+            user_id,
+            access.room.id
+        );
+        Ok(request)
+    }
+
+    async fn join_request(
+        &self,
+        room_id: i32,
+        user_id: i32,
+    ) -> Result<Option<(room_join_request::Model, user::Model)>> {
+        Ok(RoomJoinRequest::find()
+            .filter(room_join_request::Column::RoomId.eq(room_id))
+            .filter(room_join_request::Column::UserId.eq(user_id))
+            .find_also_related(User)
+            .one(&self.db)
+            .await?
+            .and_then(|(request, user)| Some((request, user?))))
+    }
+}
@@ -0,0 +1,189 @@
+use crate::errors::{AppError, Result};
+use crate::models::room::{self, Entity as Room, RoomKind};
+use crate::models::room_invite::{self, CreateInviteRequest, Entity as RoomInvite};
+use crate::models::room_member::RoomRole;
+use crate::models::room_permission::Permission;
+use crate::services::room_service::{set_member_role, RoomAccess, RoomService};
+use crate::utils::{generate_opaque_token, hash_token};
+use chrono::{Duration, Utc};
+use sea_orm::{
+    sea_query::{Condition, Expr},
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, Set,
+    TransactionTrait,
+};
+use std::sync::Arc;
+
+/// Random bytes in an invite code (128 bits)
+const INVITE_CODE_BYTES: usize = 16;
+
+/// Characters of the code kept in the clear to tell invites apart
+const DISPLAY_PREFIX_LEN: usize = 6;
+
+/// Invite links that let people join a room
+#[derive(Clone)]
+pub struct InviteService {
+    db: DatabaseConnection,
+    rooms: Arc<RoomService>,
+}
+
+impl InviteService {
+    pub fn new(db: DatabaseConnection, rooms: Arc<RoomService>) -> Self {
+        Self { db, rooms }
+    }
+
+    /// Create an invite to the room. Needs `invite`; invites granting a role above `member`
+    /// also need `manage-room` and a higher role than the one granted.
+    ///
+    /// Returns the code, which is not stored, and the invite.
+    pub async fn create(
+        &self,
+        access: &RoomAccess,
+        req: CreateInviteRequest,
+    ) -> Result<(String, room_invite::Model)> {
+        access.require(Permission::Invite)?;
+
+        if access.room.kind == RoomKind::Dm {
+            return Err(AppError::BadRequest(
+                "Start a group DM to talk with more people".to_string(),
+            ));
+        }
+
+        let role = req.role.unwrap_or(RoomRole::Member);
+        if role.outranks(RoomRole::Member) {
+            access.require(Permission::ManageRoom)?;
+            access.require_outranks(role)?;
+        }
+
+        let now = Utc::now().naive_utc();
+        let code = generate_opaque_token(INVITE_CODE_BYTES);
+
+        let invite = room_invite::ActiveModel {
+            room_id: Set(access.room.id),
+            created_by: Set(Some(access.user_id)),
+            prefix: Set(code[..DISPLAY_PREFIX_LEN].to_string()),
+            code_hash: Set(hash_token(&code)),
+            role: Set(role),
+            max_uses: Set(req.max_uses),
+            uses: Set(0),
+            expires_at: Set(req
+                .expires_in_hours
+                .map(|hours| now + Duration::hours(hours))),
+            created_at: Set(now),
+            ..Default::default()
+        }
+        .insert(&self.db)
+        .await?;
+
+        tracing::info!(
+            "User {} created invite {} to room {}",
+            access.user_id,
+            invite.id,
+            access.room.id
+        );
+        Ok((code, invite))
+    }
+
+    /// Invites to the room: all of them with `manage-room`, otherwise the caller's own
+    pub async fn list(&self, access: &RoomAccess) -> Result<Vec<room_invite::Model>> {
+        access.require(Permission::Invite)?;
+
+        let mut query = RoomInvite::find().filter(room_invite::Column::RoomId.eq(access.room.id));
+        if !access.can(Permission::ManageRoom) {
+            query = query.filter(room_invite::Column::CreatedBy.eq(access.user_id));
+        }
+
+        Ok(query
+            .order_by_desc(room_invite::Column::CreatedAt)
+            .all(&self.db)
+            .await?)
+    }
+
+    /// Revoke an invite; anyone may revoke their own, others' need `manage-room`
+    pub async fn revoke(&self, access: &RoomAccess, invite_id: i32) -> Result<()> {
+        let Some(invite) = RoomInvite::find_by_id(invite_id)
+            .filter(room_invite::Column::RoomId.eq(access.room.id))
+            .one(&self.db)
+            .await?
+        else {
+            return Ok(());
+        };
+
+        if invite.created_by != Some(access.user_id) {
+            access.require(Permission::ManageRoom)?;
+        }
+
+        RoomInvite::delete_by_id(invite.id).exec(&self.db).await?;
+        Ok(())
+    }
+
+    /// Join the room `code` invites to. Members keep their role and do not use up the invite.
+    pub async fn accept(&self, user_id: i32, code: &str) -> Result<room::Model> {
+        let now = Utc::now().naive_utc();
+
+        let invite = RoomInvite::find()
+            .filter(room_invite::Column::CodeHash.eq(hash_token(code)))
+            .one(&self.db)
+            .await?
+            .ok_or(AppError::InviteNotFound)?;
+
+        let room = Room::find_by_id(invite.room_id)
+            .one(&self.db)
+            .await?
+            .ok_or(AppError::InviteNotFound)?;
+
+        if self.rooms.ban(room.id, user_id).await?.is_some() {
+            return Err(AppError::Forbidden(
+                "You are banned from this room".to_string(),
+            ));
+        }
+
+        if self.rooms.member(room.id, user_id).await?.is_some() {
+            return Ok(room);
+        }
+        if invite.is_spent(now) {
+            return Err(AppError::InviteExpired);
+        }
+        self.rooms.check_admission(&room, user_id).await?;
+
+        let txn = self.db.begin().await?;
+
+        // Count the use only while the invite is still valid, so concurrent redemptions cannot
+        // go past `max_uses`
+        let claimed = RoomInvite::update_many()
+            .col_expr(
+                room_invite::Column::Uses,
+                Expr::col(room_invite::Column::Uses).add(1),
+            )
+            .filter(room_invite::Column::Id.eq(invite.id))
+            .filter(
+                Condition::any()
+                    .add(room_invite::Column::MaxUses.is_null())
+                    .add(
+                        Expr::col(room_invite::Column::Uses)
+                            .lt(Expr::col(room_invite::Column::MaxUses)),
+                    ),
+            )
+            .filter(
+                Condition::any()
+                    .add(room_invite::Column::ExpiresAt.is_null())
+                    .add(room_invite::Column::ExpiresAt.gt(now)),
+            )
+            .exec(&txn)
+            .await?;
+        if claimed.rows_affected == 0 {
+            return Err(AppError::InviteExpired);
+        }
+
+        set_member_role(&txn, room.id, user_id, invite.role).await?;
+
+        txn.commit().await?;
+
+        tracing::info!(
+            "User {} joined room {} with invite {}",
+            user_id,
+            room.id,
+            invite.id
+        );
+        Ok(room)
+    }
+}
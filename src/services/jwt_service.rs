@@ -1,40 +1,276 @@
+use crate::config::Config;
 use crate::errors::{AppError, Result};
+use crate::models::api_key::Scope;
+use crate::models::user::UserRole;
+use anyhow::{anyhow, bail, Context};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use chrono::{Duration, Utc};
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use jsonwebtoken::jwk::{
+    AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, KeyAlgorithm,
+    OctetKeyPairParameters, PublicKeyUse, RSAKeyParameters,
+};
+use jsonwebtoken::{
+    decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation,
+};
+use rsa::pkcs8::{der::Document, DecodePublicKey, ObjectIdentifier, SubjectPublicKeyInfoRef};
+use rsa::traits::PublicKeyParts;
+use rsa::RsaPublicKey;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use uuid::Uuid;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Claims {
     pub sub: String, // user id
     pub email: String,
     pub exp: usize, // expiration time
+    pub iat: usize, // issued at
+    pub jti: String, // unique token id, used for revocation
+    /// Session the token was issued to; absent for API keys and older tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>,
+    /// Server-wide role at the time the token was issued; absent in older tokens
+    #[serde(default)]
+    pub role: UserRole,
+    /// Set when the request was authenticated with an API key instead of a session token
+    #[serde(skip)]
+    pub api_key: Option<ApiKeyGrant>,
+}
+
+/// What an API key lets its holder do
+#[derive(Debug, Clone)]
+pub struct ApiKeyGrant {
+    pub key_id: i32,
+    pub scopes: Vec<Scope>,
+    pub is_bot: bool,
+}
+
+impl Claims {
+    /// Parse the numeric user id out of `sub`
+    pub fn user_id(&self) -> Result<i32> {
+        self.sub.parse::<i32>().map_err(|_| AppError::InvalidToken)
+    }
+
+    /// The session the token belongs to, if any
+    pub fn session_id(&self) -> Option<Uuid> {
+        self.sid.as_deref().and_then(|sid| Uuid::parse_str(sid).ok())
+    }
+
+    /// Whether the caller may act with `scope`. Session tokens are not limited by scopes.
+    pub fn allows(&self, scope: Scope) -> bool {
+        self.api_key
+            .as_ref()
+            .is_none_or(|grant| grant.scopes.contains(&scope))
+    }
+
+    /// Whether the token was issued to an admin. Admin endpoints confirm the role with the
+    /// database, since it may have changed since.
+    pub fn is_admin(&self) -> bool {
+        self.role == UserRole::Admin
+    }
+
+    /// Whether the caller is a bot account (bots can only authenticate with API keys)
+    pub fn is_bot(&self) -> bool {
+        self.api_key.as_ref().is_some_and(|grant| grant.is_bot)
+    }
+}
+
+/// Audience of the short-lived token handed out between password and second-factor checks
+const MFA_AUDIENCE: &str = "mfa";
+/// How long a user has to enter their second factor
+const MFA_TOKEN_TTL_MINUTES: i64 = 5;
+
+/// Claims of an "MFA pending" token. It carries an `aud` claim, so `verify_token` rejects it
+/// and it cannot be used as an access token.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MfaClaims {
+    pub sub: String, // user id
+    pub aud: String,
+    pub exp: usize,
+}
+
+/// Object identifiers of the public key types accepted for signing
+const RSA_ENCRYPTION_OID: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.1");
+const ED25519_OID: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.101.112");
+
+/// A key tokens are verified with
+#[derive(Clone)]
+struct VerificationKey {
+    algorithm: Algorithm,
+    key: DecodingKey,
 }
 
 #[derive(Clone)]
 pub struct JwtService {
-    secret: String,
-    expiration_hours: i64,
+    algorithm: Algorithm,
+    /// `kid` header of issued tokens; `None` for the shared secret
+    key_id: Option<String>,
+    encoding_key: EncodingKey,
+    /// Public keys by `kid`: the signing key plus keys kept for rotation
+    public_keys: HashMap<String, VerificationKey>,
+    /// Shared secret, accepted for tokens without a `kid`
+    secret: Option<DecodingKey>,
+    jwks: JwkSet,
+    expiration_minutes: i64,
 }
 
 impl JwtService {
-    pub fn new(secret: &str, expiration_hours: i64) -> Self {
+    /// Sign with the shared secret (HS256)
+    pub fn new(secret: &str, expiration_minutes: i64) -> Self {
         Self {
-            secret: secret.to_string(),
-            expiration_hours,
+            algorithm: Algorithm::HS256,
+            key_id: None,
+            encoding_key: EncodingKey::from_secret(secret.as_bytes()),
+            public_keys: HashMap::new(),
+            secret: Some(DecodingKey::from_secret(secret.as_bytes())),
+            jwks: JwkSet { keys: Vec::new() },
+            expiration_minutes,
         }
     }
-    
-    fn encoding_key(&self) -> EncodingKey {
-        EncodingKey::from_secret(self.secret.as_bytes())
+
+    /// Sign with the key pair from `JWT_PRIVATE_KEY_FILE`/`JWT_PUBLIC_KEY_FILE` when set,
+    /// otherwise with `JWT_SECRET`. Public keys in `JWT_VERIFICATION_KEY_FILES` are accepted
+    /// too, and `JWT_SECRET` keeps being accepted for tokens issued before the switch.
+    pub fn from_config(config: &Config) -> anyhow::Result<Self> {
+        let Some(private_key_file) = &config.jwt_private_key_file else {
+            let secret = config
+                .jwt_secret
+                .as_deref()
+                .ok_or_else(|| anyhow!("JWT_SECRET or JWT_PRIVATE_KEY_FILE is required"))?;
+            return Ok(Self::new(secret, config.access_token_ttl_minutes));
+        };
+        let public_key_file = config
+            .jwt_public_key_file
+            .as_ref()
+            .ok_or_else(|| anyhow!("JWT_PUBLIC_KEY_FILE is required with JWT_PRIVATE_KEY_FILE"))?;
+
+        let signing_jwk = load_public_key(public_key_file)?;
+        let algorithm = jwk_algorithm(&signing_jwk);
+        let private_pem = fs::read(private_key_file)
+            .with_context(|| format!("reading {}", private_key_file.display()))?;
+        let encoding_key = match algorithm {
+            Algorithm::RS256 => EncodingKey::from_rsa_pem(&private_pem),
+            _ => EncodingKey::from_ed_pem(&private_pem),
+        }
+        .with_context(|| format!("parsing {}", private_key_file.display()))?;
+
+        let mut jwks = JwkSet {
+            keys: vec![signing_jwk.clone()],
+        };
+        for file in &config.jwt_verification_key_files {
+            let jwk = load_public_key(file)?;
+            if !jwks
+                .keys
+                .iter()
+                .any(|known| known.common.key_id == jwk.common.key_id)
+            {
+                jwks.keys.push(jwk);
+            }
+        }
+
+        let public_keys = jwks
+            .keys
+            .iter()
+            .map(|jwk| {
+                let key = VerificationKey {
+                    algorithm: jwk_algorithm(jwk),
+                    key: DecodingKey::from_jwk(jwk)?,
+                };
+                Ok((jwk.common.key_id.clone().unwrap_or_default(), key))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        let service = Self {
+            algorithm,
+            key_id: signing_jwk.common.key_id.clone(),
+            encoding_key,
+            public_keys,
+            secret: config
+                .jwt_secret
+                .as_deref()
+                .map(|secret| DecodingKey::from_secret(secret.as_bytes())),
+            jwks,
+            expiration_minutes: config.access_token_ttl_minutes,
+        };
+
+        // Catch a private key that does not belong to the public key at startup
+        let probe = service
+            .generate_token(0, "", UserRole::User, None)
+            .map_err(|_| anyhow!("signing with {} failed", private_key_file.display()))?;
+        service.verify_token(&probe).map_err(|_| {
+            anyhow!(
+                "{} does not match {}",
+                private_key_file.display(),
+                public_key_file.display()
+            )
+        })?;
+
+        tracing::info!(
+            "Signing tokens with {:?} key {}",
+            algorithm,
+            service.key_id.as_deref().unwrap_or_default()
+        );
+        Ok(service)
     }
-    
-    fn decoding_key(&self) -> DecodingKey {
-        DecodingKey::from_secret(self.secret.as_bytes())
+
+    /// Lifetime of issued access tokens in seconds
+    pub fn expires_in(&self) -> i64 {
+        self.expiration_minutes * 60
     }
 
-    pub fn generate_token(&self, user_id: i32, email: &str) -> Result<String> {
-        let expiration = Utc::now()
-            .checked_add_signed(Duration::hours(self.expiration_hours))
+    /// Public keys tokens may be signed with, for `/.well-known/jwks.json`.
+    /// Empty when signing with the shared secret.
+    pub fn jwks(&self) -> &JwkSet {
+        &self.jwks
+    }
+
+    fn header(&self) -> Header {
+        Header {
+            kid: self.key_id.clone(),
+            ..Header::new(self.algorithm)
+        }
+    }
+
+    /// Decode `token` with the key named by its `kid` header (or the shared secret when it
+    /// has none), accepting only that key's algorithm
+    fn decode<T: DeserializeOwned>(
+        &self,
+        token: &str,
+        mut validation: Validation,
+    ) -> jsonwebtoken::errors::Result<T> {
+        use jsonwebtoken::errors::ErrorKind;
+
+        let header = decode_header(token)?;
+        let (algorithm, key) = match &header.kid {
+            Some(kid) => self
+                .public_keys
+                .get(kid)
+                .map(|verification| (verification.algorithm, &verification.key)),
+            None => self
+                .secret
+                .as_ref()
+                .map(|secret| (Algorithm::HS256, secret)),
+        }
+        .ok_or(ErrorKind::InvalidSignature)?;
+
+        validation.algorithms = vec![algorithm];
+        decode::<T>(token, key, &validation).map(|data| data.claims)
+    }
+
+    pub fn generate_token(
+        &self,
+        user_id: i32,
+        email: &str,
+        role: UserRole,
+        session_id: Option<Uuid>,
+    ) -> Result<String> {
+        let now = Utc::now();
+        let expiration = now
+            .checked_add_signed(Duration::minutes(self.expiration_minutes))
             .expect("valid timestamp")
             .timestamp() as usize;
 
@@ -42,18 +278,121 @@ impl JwtService {
             sub: user_id.to_string(),
             email: email.to_string(),
             exp: expiration,
+            iat: now.timestamp() as usize,
+            jti: Uuid::new_v4().to_string(),
+            sid: session_id.map(|id| id.to_string()),
+            role,
+            api_key: None,
         };
 
-        encode(&Header::default(), &claims, &self.encoding_key())
+        encode(&self.header(), &claims, &self.encoding_key)
             .map_err(|_| AppError::InternalServerError)
     }
 
+    /// Issue a token proving the password step of login succeeded for `user_id`
+    pub fn generate_mfa_token(&self, user_id: i32) -> Result<String> {
+        let expiration = Utc::now()
+            .checked_add_signed(Duration::minutes(MFA_TOKEN_TTL_MINUTES))
+            .expect("valid timestamp")
+            .timestamp() as usize;
+
+        let claims = MfaClaims {
+            sub: user_id.to_string(),
+            aud: MFA_AUDIENCE.to_string(),
+            exp: expiration,
+        };
+
+        encode(&self.header(), &claims, &self.encoding_key)
+            .map_err(|_| AppError::InternalServerError)
+    }
+
+    /// Verify an MFA pending token and return the user id it was issued for
+    pub fn verify_mfa_token(&self, token: &str) -> Result<i32> {
+        let mut validation = Validation::default();
+        validation.set_audience(&[MFA_AUDIENCE]);
+        validation.set_required_spec_claims(&["exp", "aud", "sub"]);
+
+        let claims = self.decode::<MfaClaims>(token, validation).map_err(|err| {
+            tracing::warn!("MFA token verification failed: {:?}", err);
+            AppError::InvalidToken
+        })?;
+
+        claims
+            .sub
+            .parse::<i32>()
+            .map_err(|_| AppError::InvalidToken)
+    }
+
     pub fn verify_token(&self, token: &str) -> Result<Claims> {
-        decode::<Claims>(token, &self.decoding_key(), &Validation::default())
-            .map(|data| data.claims)
+        self.decode::<Claims>(token, Validation::default())
             .map_err(|err| {
                 tracing::warn!("Token verification failed: {:?}", err);
                 AppError::InvalidToken
             })
     }
 }
+
+fn jwk_algorithm(jwk: &Jwk) -> Algorithm {
+    match jwk.algorithm {
+        AlgorithmParameters::RSA(_) => Algorithm::RS256,
+        _ => Algorithm::EdDSA,
+    }
+}
+
+/// Read an RSA or Ed25519 public key (PEM, SubjectPublicKeyInfo) as a JWK whose `kid` is its
+/// RFC 7638 thumbprint, so every instance derives the same id for the same key
+fn load_public_key(path: &Path) -> anyhow::Result<Jwk> {
+    let pem = fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+    let (_, document) =
+        Document::from_pem(&pem).with_context(|| format!("parsing {}", path.display()))?;
+    let spki = SubjectPublicKeyInfoRef::try_from(document.as_bytes())
+        .with_context(|| format!("{} is not a PUBLIC KEY", path.display()))?;
+
+    let (key_algorithm, algorithm, thumbprint_input) = match spki.algorithm.oid {
+        RSA_ENCRYPTION_OID => {
+            let key = RsaPublicKey::from_public_key_der(document.as_bytes())?;
+            let n = URL_SAFE_NO_PAD.encode(key.n().to_bytes_be());
+            let e = URL_SAFE_NO_PAD.encode(key.e().to_bytes_be());
+            let thumbprint_input = format!(r#"{{"e":"{}","kty":"RSA","n":"{}"}}"#, e, n);
+            let params = RSAKeyParameters {
+                key_type: Default::default(),
+                n,
+                e,
+            };
+            (
+                KeyAlgorithm::RS256,
+                AlgorithmParameters::RSA(params),
+                thumbprint_input,
+            )
+        }
+        ED25519_OID => {
+            let x = URL_SAFE_NO_PAD.encode(spki.subject_public_key.raw_bytes());
+            let thumbprint_input = format!(r#"{{"crv":"Ed25519","kty":"OKP","x":"{}"}}"#, x);
+            let params = OctetKeyPairParameters {
+                key_type: Default::default(),
+                curve: EllipticCurve::Ed25519,
+                x,
+            };
+            (
+                KeyAlgorithm::EdDSA,
+                AlgorithmParameters::OctetKeyPair(params),
+                thumbprint_input,
+            )
+        }
+        oid => bail!(
+            "{}: unsupported key type {} (use RSA or Ed25519)",
+            path.display(),
+            oid
+        ),
+    };
+
+    Ok(Jwk {
+        common: CommonParameters {
+            public_key_use: Some(PublicKeyUse::Signature),
+            key_algorithm: Some(key_algorithm),
+            key_id: Some(URL_SAFE_NO_PAD.encode(Sha256::digest(thumbprint_input))),
+            ..Default::default()
+        },
+        algorithm,
+    })
+}
@@ -0,0 +1,259 @@
+use crate::errors::{AppError, Result};
+use crate::models::login_lockout;
+use crate::models::user::normalize_email;
+use crate::services::redis_service::{CacheKey, RedisService};
+use chrono::Utc;
+use sea_orm::{ActiveModelTrait, DatabaseConnection, Set};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Failed attempts are forgotten this long after the last one
+const FAILURE_WINDOW_SECONDS: u64 = 60 * 60;
+/// Delay after the first failure beyond the free attempts; doubles with every further failure
+const BACKOFF_BASE_SECONDS: u64 = 1;
+/// How long a lockout lasts
+const LOCKOUT_SECONDS: u64 = 15 * 60;
+
+/// What failed attempts are counted against
+#[derive(Clone, Copy, Debug)]
+enum Scope {
+    Account,
+    Ip,
+}
+
+impl Scope {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Scope::Account => "account",
+            Scope::Ip => "ip",
+        }
+    }
+
+    /// Failures allowed before backoff starts
+    fn free_attempts(&self) -> u64 {
+        match self {
+            Scope::Account => 3,
+            // One address may legitimately serve many users (NAT, offices)
+            Scope::Ip => 20,
+        }
+    }
+
+    /// Failures after which the subject is locked out
+    fn lockout_after(&self) -> u64 {
+        match self {
+            Scope::Account => 10,
+            Scope::Ip => 100,
+        }
+    }
+
+    /// How long to refuse logins after the `failures`-th failure, if at all
+    fn delay(&self, failures: u64) -> Option<u64> {
+        if failures >= self.lockout_after() {
+            return Some(LOCKOUT_SECONDS);
+        }
+
+        let excess = failures.checked_sub(self.free_attempts() + 1)?;
+        Some(
+            BACKOFF_BASE_SECONDS
+                .saturating_mul(1 << excess.min(20))
+                .min(LOCKOUT_SECONDS),
+        )
+    }
+}
+
+struct Attempts {
+    failures: u64,
+    expires: Instant,
+    locked_until: Option<Instant>,
+}
+
+/// Throttles password (and second factor) attempts per account and per client IP with
+/// exponential backoff, then a temporary lockout.
+///
+/// Counters live in Redis when available so every instance sees them, and in process memory
+/// otherwise.
+pub struct LoginThrottle {
+    db: DatabaseConnection,
+    redis: Option<Arc<RedisService>>,
+    memory: Mutex<HashMap<String, Attempts>>,
+}
+
+impl LoginThrottle {
+    pub fn new(db: DatabaseConnection, redis: Option<Arc<RedisService>>) -> Self {
+        Self {
+            db,
+            redis,
+            memory: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Refuse the attempt if the account or the client IP is backing off or locked out
+    pub async fn check(&self, email: &str, ip: Option<IpAddr>) -> Result<()> {
+        let mut retry_after = 0;
+        for (scope, subject) in subjects(email, ip) {
+            retry_after = retry_after.max(self.locked_for(scope, &subject).await);
+        }
+
+        if retry_after > 0 {
+            return Err(AppError::TooManyAttempts { retry_after });
+        }
+
+        Ok(())
+    }
+
+    /// Count a failed attempt, backing off or locking out the account and IP as needed.
+    /// `user_id` is the account the email belongs to, if any.
+    pub async fn record_failure(
+        &self,
+        email: &str,
+        ip: Option<IpAddr>,
+        user_id: Option<i32>,
+    ) -> Result<()> {
+        for (scope, subject) in subjects(email, ip) {
+            let failures = self.increment(scope, &subject).await;
+            let Some(delay) = scope.delay(failures) else {
+                continue;
+            };
+
+            self.lock(scope, &subject, delay).await;
+
+            if delay == LOCKOUT_SECONDS {
+                tracing::warn!(
+                    "Locking out {} {} for {}s after {} failed logins",
+                    scope.as_str(),
+                    subject,
+                    delay,
+                    failures
+                );
+
+                login_lockout::ActiveModel {
+                    scope: Set(scope.as_str().to_string()),
+                    subject: Set(subject),
+                    user_id: Set(user_id),
+                    ip_address: Set(ip.map(|ip| ip.to_string())),
+                    failures: Set(failures.min(i32::MAX as u64) as i32),
+                    locked_until: Set(
+                        Utc::now().naive_utc() + chrono::Duration::seconds(delay as i64)
+                    ),
+                    created_at: Set(Utc::now().naive_utc()),
+                    ..Default::default()
+                }
+                .insert(&self.db)
+                .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Forget the failures of an account after a successful login. Failures of the IP are
+    /// kept, so a valid login cannot be used to reset an attack on other accounts.
+    pub async fn record_success(&self, email: &str) {
+        let subject = normalize_email(email);
+
+        if let Some(redis) = &self.redis {
+            for key in [
+                CacheKey::login_failures(Scope::Account.as_str(), &subject),
+                CacheKey::login_lock(Scope::Account.as_str(), &subject),
+            ] {
+                if let Err(e) = redis.delete(&key).await {
+                    tracing::warn!("Failed to clear login failures in Redis: {}", e);
+                }
+            }
+        }
+
+        self.memory().remove(&memory_key(Scope::Account, &subject));
+    }
+
+    async fn locked_for(&self, scope: Scope, subject: &str) -> u64 {
+        if let Some(redis) = &self.redis {
+            match redis
+                .ttl(&CacheKey::login_lock(scope.as_str(), subject))
+                .await
+            {
+                Ok(ttl) => return ttl.max(0) as u64,
+                Err(e) => tracing::warn!("Redis error: {}. Checking login lock in memory.", e),
+            }
+        }
+
+        let now = Instant::now();
+        self.memory()
+            .get(&memory_key(scope, subject))
+            .and_then(|attempts| attempts.locked_until)
+            .filter(|until| *until > now)
+            // Round up so clients never retry a moment too early
+            .map(|until| (until - now).as_millis().div_ceil(1000) as u64)
+            .unwrap_or(0)
+    }
+
+    async fn increment(&self, scope: Scope, subject: &str) -> u64 {
+        if let Some(redis) = &self.redis {
+            match redis
+                .incr_with_ttl(
+                    &CacheKey::login_failures(scope.as_str(), subject),
+                    FAILURE_WINDOW_SECONDS as usize,
+                )
+                .await
+            {
+                Ok(failures) => return failures.max(0) as u64,
+                Err(e) => tracing::warn!("Redis error: {}. Counting login failures in memory.", e),
+            }
+        }
+
+        let now = Instant::now();
+        let mut memory = self.memory();
+        memory.retain(|_, attempts| attempts.expires > now);
+
+        let attempts = memory
+            .entry(memory_key(scope, subject))
+            .or_insert(Attempts {
+                failures: 0,
+                expires: now,
+                locked_until: None,
+            });
+        attempts.failures += 1;
+        attempts.expires = now + Duration::from_secs(FAILURE_WINDOW_SECONDS);
+        attempts.failures
+    }
+
+    async fn lock(&self, scope: Scope, subject: &str, seconds: u64) {
+        if let Some(redis) = &self.redis {
+            match redis
+                .set_with_ttl(
+                    &CacheKey::login_lock(scope.as_str(), subject),
+                    &true,
+                    seconds as usize,
+                )
+                .await
+            {
+                Ok(()) => return,
+                Err(e) => tracing::warn!("Redis error: {}. Keeping login lock in memory.", e),
+            }
+        }
+
+        let until = Instant::now() + Duration::from_secs(seconds);
+        if let Some(attempts) = self.memory().get_mut(&memory_key(scope, subject)) {
+            attempts.locked_until = Some(until);
+            attempts.expires = attempts.expires.max(until);
+        }
+    }
+
+    fn memory(&self) -> std::sync::MutexGuard<'_, HashMap<String, Attempts>> {
+        self.memory.lock().unwrap_or_else(|p| p.into_inner())
+    }
+}
+
+/// The counters an attempt for `email` from `ip` is checked against
+fn subjects(email: &str, ip: Option<IpAddr>) -> Vec<(Scope, String)> {
+    let mut subjects = vec![(Scope::Account, normalize_email(email))];
+    if let Some(ip) = ip {
+        subjects.push((Scope::Ip, ip.to_string()));
+    }
+    subjects
+}
+
+fn memory_key(scope: Scope, subject: &str) -> String {
+    format!("{}:{}", scope.as_str(), subject)
+}
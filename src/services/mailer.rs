@@ -0,0 +1,145 @@
+use crate::config::{Config, MailBackend};
+use crate::errors::{AppError, Result};
+use axum::async_trait;
+use chrono::Utc;
+use lettre::{
+    message::Mailbox, transport::smtp::authentication::Credentials, AsyncSmtpTransport,
+    AsyncTransport, Message, Tokio1Executor,
+};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// A plain-text email ready to be delivered
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Delivers transactional email (verification links, password resets, ...)
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, email: Email) -> Result<()>;
+}
+
+/// Build the mailer selected by `MAIL_BACKEND`
+pub fn from_config(config: &Config) -> anyhow::Result<Arc<dyn Mailer>> {
+    let mailer: Arc<dyn Mailer> = match config.mail_backend {
+        MailBackend::Log => Arc::new(LogMailer),
+        MailBackend::File => Arc::new(FileMailer::new(config.mail_dir.clone())),
+        MailBackend::Smtp => Arc::new(SmtpMailer::new(config)?),
+    };
+
+    Ok(mailer)
+}
+
+/// Sends mail through an SMTP relay
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    pub fn new(config: &Config) -> anyhow::Result<Self> {
+        let host = config
+            .smtp_host
+            .as_deref()
+            .ok_or_else(|| anyhow::anyhow!("SMTP_HOST is required when MAIL_BACKEND=smtp"))?;
+
+        let mut builder = AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?
+            .port(config.smtp_port);
+
+        if let (Some(username), Some(password)) = (&config.smtp_username, &config.smtp_password) {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+
+        Ok(Self {
+            transport: builder.build(),
+            from: config.mail_from.parse()?,
+        })
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, email: Email) -> Result<()> {
+        let to: Mailbox = email
+            .to
+            .parse()
+            .map_err(|_| AppError::BadRequest("Invalid email address".to_string()))?;
+
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(email.subject)
+            .body(email.body)
+            .map_err(|e| {
+                tracing::error!("Failed to build email: {}", e);
+                AppError::InternalServerError
+            })?;
+
+        self.transport.send(message).await.map_err(|e| {
+            tracing::error!("Failed to send email via SMTP: {}", e);
+            AppError::InternalServerError
+        })?;
+
+        Ok(())
+    }
+}
+
+/// Writes each email to a file in a directory, for development and tests
+pub struct FileMailer {
+    dir: PathBuf,
+}
+
+impl FileMailer {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, email: Email) -> Result<()> {
+        tokio::fs::create_dir_all(&self.dir).await.map_err(|e| {
+            tracing::error!("Failed to create mail directory {:?}: {}", self.dir, e);
+            AppError::InternalServerError
+        })?;
+
+        let file_name = format!(
+            "{}-{}.eml",
+            Utc::now().format("%Y%m%dT%H%M%S%.3f"),
+            uuid::Uuid::new_v4()
+        );
+        let path = self.dir.join(file_name);
+        let contents = format!(
+            "To: {}\nSubject: {}\n\n{}\n",
+            email.to, email.subject, email.body
+        );
+
+        tokio::fs::write(&path, contents).await.map_err(|e| {
+            tracing::error!("Failed to write email to {:?}: {}", path, e);
+            AppError::InternalServerError
+        })?;
+
+        tracing::info!("📧 Email to {} written to {:?}", email.to, path);
+        Ok(())
+    }
+}
+
+/// Logs emails instead of sending them
+pub struct LogMailer;
+
+#[async_trait]
+impl Mailer for LogMailer {
+    async fn send(&self, email: Email) -> Result<()> {
+        tracing::info!(
+            "📧 Email to {}\nSubject: {}\n\n{}",
+            email.to,
+            email.subject,
+            email.body
+        );
+        Ok(())
+    }
+}
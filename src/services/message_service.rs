@@ -1,7 +1,24 @@
-use crate::errors::Result;
-use crate::models::message::{self, CreateMessageRequest, MessageResponse};
-use chrono::Utc;
-use sea_orm::{ActiveModelTrait, DatabaseConnection, Set};
+use crate::db::{advisory_xact_lock, LockSpace};
+use crate::errors::{AppError, Result};
+use crate::models::message::{
+    self, CreateMessageRequest, Entity as Message, MessageKind, MessageResponse,
+};
+use crate::models::room;
+use crate::models::room_post_time::{self, Entity as RoomPostTime};
+use crate::services::room_service::RoomAccess;
+use chrono::{Duration, Utc};
+use sea_orm::{
+    sea_query::{Condition, OnConflict}, ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection,
+    EntityTrait, QueryFilter, QueryOrder, QuerySelect, Set, TransactionTrait,
+};
+use std::collections::HashSet;
+
+/// How often expired messages are deleted
+const EXPIRY_SWEEP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Default and maximum page size for message history
+const DEFAULT_HISTORY_LIMIT: u64 = 50;
+const MAX_HISTORY_LIMIT: u64 = 100;
 
 #[derive(Clone)]
 pub struct MessageService {
@@ -13,22 +30,165 @@ impl MessageService {
         Self { db }
     }
 
-    pub async fn create_message(
+    /// Post a message as the caller of `access`, who must already hold `post`. Announcement
+    /// mode and slow mode are enforced here, so every way of posting obeys them alike.
+    pub async fn post(
         &self,
-        sender_id: i32,
-        room_id: i32,
+        access: &RoomAccess,
         req: CreateMessageRequest,
     ) -> Result<MessageResponse> {
-        let new_message = message::ActiveModel {
-            sender_id: Set(sender_id),
-            room_id: Set(room_id),
-            content: Set(req.content),
-            created_at: Set(Utc::now().naive_utc()),
-            ..Default::default()
-        };
+        access.require_posting()?;
+
+        let delay = access.slow_mode_seconds();
+        if delay == 0 {
+            return insert(&self.db, access.user_id, &access.room, req.content, MessageKind::User)
+                .await;
+        }
+
+        let txn = self.db.begin().await?;
+
+        // Take turns with other posts by the same user in the same room, so concurrent requests
+        // cannot all pass the check below. The wait counts from the last post, even if that
+        // message has since been deleted. See `LockSpace` for how lock keys are namespaced.
+        advisory_xact_lock(&txn, LockSpace::SlowMode, access.room.id, access.user_id).await?;
+
+        let now = Utc::now().naive_utc();
+        let last = RoomPostTime::find_by_id((access.room.id, access.user_id))
+            .one(&txn)
+            .await?;
+        if let Some(last) = last {
+            let wait = last.posted_at + Duration::seconds(delay.into()) - now;
+            if wait > Duration::zero() {
+                // Round up, so retrying after that long always succeeds
+                let retry_after = (wait.num_milliseconds() as u64).div_ceil(1000);
+                return Err(AppError::SlowMode { retry_after });
+            }
+        }
+
+        let message =
+            insert(&txn, access.user_id, &access.room, req.content, MessageKind::User).await?;
+
+        RoomPostTime::insert(room_post_time::ActiveModel {
+            room_id: Set(access.room.id),
+            user_id: Set(access.user_id),
+            posted_at: Set(now),
+        })
+        .on_conflict(
+            OnConflict::columns([room_post_time::Column::RoomId, room_post_time::Column::UserId])
+                .update_column(room_post_time::Column::PostedAt)
+                .to_owned(),
+        )
+        .exec_without_returning(&txn)
+        .await?;
 
-        let message = new_message.insert(&self.db).await?;
+        txn.commit().await?;
+        Ok(message)
+    }
+
+    /// Record in the timeline something `actor_id` did to the room
+    pub async fn create_system_message(
+        &self,
+        actor_id: i32,
+        room: &room::Model,
+        content: String,
+    ) -> Result<MessageResponse> {
+        insert(&self.db, actor_id, room, content, MessageKind::System).await
+    }
+
+    /// Delete every message past its expiry, returning how many went
+    pub async fn delete_expired(&self) -> Result<u64> {
+        let deleted = Message::delete_many()
+            .filter(message::Column::ExpiresAt.lte(Utc::now().naive_utc()))
+            .exec(&self.db)
+            .await?;
+
+        Ok(deleted.rows_affected)
+    }
 
-        Ok(message.into())
+    /// Delete expired messages every `EXPIRY_SWEEP_INTERVAL` in the background. Reads filter
+    /// them out in the meantime.
+    pub fn spawn_expiry_sweeper(&self) {
+        let service = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(EXPIRY_SWEEP_INTERVAL);
+            loop {
+                interval.tick().await;
+                match service.delete_expired().await {
+                    Ok(0) => {}
+                    Ok(deleted) => tracing::info!("Deleted {} expired messages", deleted),
+                    Err(e) => tracing::error!("Failed to delete expired messages: {:?}", e),
+                }
+            }
+        });
     }
+
+    /// Fetch a page of room history, newest first, skipping messages from `hidden_senders`.
+    /// System messages are always kept.
+    pub async fn list_messages(
+        &self,
+        room_id: i32,
+        before_id: Option<i32>,
+        limit: Option<u64>,
+        hidden_senders: &HashSet<i32>,
+    ) -> Result<Vec<MessageResponse>> {
+        let limit = limit.unwrap_or(DEFAULT_HISTORY_LIMIT).clamp(1, MAX_HISTORY_LIMIT);
+
+        let mut query = Message::find()
+            .filter(message::Column::RoomId.eq(room_id))
+            .filter(not_expired());
+
+        if let Some(before_id) = before_id {
+            query = query.filter(message::Column::Id.lt(before_id));
+        }
+
+        if !hidden_senders.is_empty() {
+            query = query.filter(
+                Condition::any()
+                    .add(message::Column::SenderId.is_not_in(hidden_senders.iter().copied()))
+                    .add(message::Column::Kind.eq(MessageKind::System)),
+            );
+        }
+
+        let messages = query
+            .order_by_desc(message::Column::Id)
+            .limit(limit)
+            .all(&self.db)
+            .await?;
+
+        Ok(messages.into_iter().map(Into::into).collect())
+    }
+}
+
+/// Save a message, expiring it as the room's settings ask
+async fn insert<C: ConnectionTrait>(
+    db: &C,
+    sender_id: i32,
+    room: &room::Model,
+    content: String,
+    kind: MessageKind,
+) -> Result<MessageResponse> {
+    let now = Utc::now().naive_utc();
+    let expiry = room.settings.message_expiry_seconds;
+
+    let new_message = message::ActiveModel {
+        sender_id: Set(sender_id),
+        room_id: Set(room.id),
+        content: Set(content),
+        kind: Set(kind),
+        expires_at: Set((expiry > 0).then(|| now + Duration::seconds(expiry.into()))),
+        created_at: Set(now),
+        ..Default::default()
+    };
+
+    let message = new_message.insert(db).await?;
+
+    Ok(message.into())
+}
+
+/// Matches messages that have not expired yet. Expired ones linger until the sweeper deletes
+/// them, so every read of messages applies this.
+pub fn not_expired() -> Condition {
+    Condition::any()
+        .add(message::Column::ExpiresAt.is_null())
+        .add(message::Column::ExpiresAt.gt(Utc::now().naive_utc()))
 }
@@ -1,4 +1,26 @@
+pub mod account_job_service;
+pub mod admin_service;
+pub mod api_key_service;
 pub mod auth_service;
+pub mod block_service;
+pub mod bot_service;
+pub mod connection_registry;
+pub mod invitation_service;
+pub mod invite_service;
 pub mod jwt_service;
+pub mod login_throttle;
+pub mod mailer;
 pub mod message_service;
+pub mod password_service;
+pub mod oidc_service;
+pub mod pending_store;
+pub mod profile_service;
+pub mod rate_limiter;
 pub mod redis_service;
+pub mod refresh_token_service;
+pub mod revocation_service;
+pub mod room_service;
+pub mod session_service;
+pub mod totp_service;
+pub mod user_token_service;
+pub mod webauthn_service;
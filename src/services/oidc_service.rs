@@ -0,0 +1,328 @@
+use crate::config::OidcProviderConfig;
+use crate::errors::{AppError, Result};
+use crate::services::pending_store::PendingStore;
+use crate::services::redis_service::{CacheKey, RedisService};
+use crate::utils::generate_opaque_token;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use serde::{Deserialize, Deserializer, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+
+/// How long a user has to complete the login at the provider
+const PENDING_LOGIN_TTL: Duration = Duration::from_secs(10 * 60);
+/// How long discovery documents and key sets are cached
+const METADATA_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// The subset of the discovery document we rely on
+#[derive(Debug, Clone, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+/// State kept between `/start` and the callback
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PendingLogin {
+    provider: String,
+    code_verifier: String,
+    nonce: String,
+    /// Set when a signed-in user is linking the identity to their account
+    #[serde(default)]
+    link_user_id: Option<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct IdTokenClaims {
+    sub: String,
+    nonce: Option<String>,
+    email: Option<String>,
+    #[serde(default, deserialize_with = "bool_or_string")]
+    email_verified: bool,
+    name: Option<String>,
+    preferred_username: Option<String>,
+}
+
+/// Identity asserted by a provider after a successful login
+#[derive(Debug, Clone)]
+pub struct OidcIdentity {
+    pub provider: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    /// Best available display name (preferred username, then name)
+    pub name: Option<String>,
+    /// The signed-in user who started the flow to link this identity, if any
+    pub link_user_id: Option<i32>,
+}
+
+/// OpenID Connect authorization-code flow with PKCE against the configured providers
+pub struct OidcService {
+    http: reqwest::Client,
+    providers: HashMap<String, OidcProviderConfig>,
+    metadata: RwLock<HashMap<String, (ProviderMetadata, Instant)>>,
+    jwks: RwLock<HashMap<String, (JwkSet, Instant)>>,
+    /// Pending logins by `state` parameter
+    pending: PendingStore<PendingLogin>,
+}
+
+impl OidcService {
+    pub fn new(providers: Vec<OidcProviderConfig>, redis: Option<Arc<RedisService>>) -> Self {
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .expect("Failed to build HTTP client");
+
+        Self {
+            http,
+            providers: providers.into_iter().map(|p| (p.name.clone(), p)).collect(),
+            metadata: RwLock::new(HashMap::new()),
+            jwks: RwLock::new(HashMap::new()),
+            pending: PendingStore::new(redis, CacheKey::oidc_state, PENDING_LOGIN_TTL),
+        }
+    }
+
+    /// Build the provider's authorization URL for a new login attempt, or for linking the
+    /// identity to `link_user_id`'s account
+    pub async fn authorization_url(
+        &self,
+        provider_name: &str,
+        link_user_id: Option<i32>,
+    ) -> Result<String> {
+        let provider = self.provider(provider_name)?;
+        let metadata = self.metadata(provider).await?;
+
+        let state = generate_opaque_token(32);
+        let pending = PendingLogin {
+            provider: provider.name.clone(),
+            code_verifier: generate_opaque_token(32),
+            nonce: generate_opaque_token(32),
+            link_user_id,
+        };
+        let code_challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(pending.code_verifier.as_bytes()));
+
+        let mut url = reqwest::Url::parse(&metadata.authorization_endpoint).map_err(|e| {
+            AppError::UpstreamError(format!("Invalid authorization endpoint: {}", e))
+        })?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &provider.client_id)
+            .append_pair("redirect_uri", &provider.redirect_uri)
+            .append_pair("scope", &provider.scopes)
+            .append_pair("state", &state)
+            .append_pair("nonce", &pending.nonce)
+            .append_pair("code_challenge", &code_challenge)
+            .append_pair("code_challenge_method", "S256");
+
+        self.pending.put(&state, pending).await;
+
+        Ok(url.into())
+    }
+
+    /// Exchange the authorization code from the callback and validate the ID token
+    pub async fn complete(&self, provider_name: &str, code: &str, state: &str) -> Result<OidcIdentity> {
+        let provider = self.provider(provider_name)?;
+
+        let pending = self
+            .pending
+            .take(state)
+            .await
+            .filter(|pending| pending.provider == provider.name)
+            .ok_or_else(|| AppError::AuthError("Invalid or expired login state".to_string()))?;
+
+        let metadata = self.metadata(provider).await?;
+
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", provider.redirect_uri.as_str()),
+            ("client_id", provider.client_id.as_str()),
+            ("code_verifier", pending.code_verifier.as_str()),
+        ];
+        if let Some(secret) = &provider.client_secret {
+            form.push(("client_secret", secret.as_str()));
+        }
+
+        let response = self
+            .http
+            .post(&metadata.token_endpoint)
+            .form(&form)
+            .send()
+            .await
+            .map_err(|e| AppError::UpstreamError(format!("Token request failed: {}", e)))?;
+
+        if !response.status().is_success() {
+            tracing::warn!(
+                "OIDC provider {} rejected code exchange with status {}",
+                provider.name,
+                response.status()
+            );
+            return Err(AppError::AuthError("Login with identity provider failed".to_string()));
+        }
+
+        let tokens: TokenResponse = response
+            .json()
+            .await
+            .map_err(|e| AppError::UpstreamError(format!("Invalid token response: {}", e)))?;
+
+        let claims = self.verify_id_token(provider, &metadata, &tokens.id_token).await?;
+
+        if claims.nonce.as_deref() != Some(pending.nonce.as_str()) {
+            return Err(AppError::AuthError("ID token nonce mismatch".to_string()));
+        }
+
+        Ok(OidcIdentity {
+            provider: provider.name.clone(),
+            subject: claims.sub,
+            email: claims.email,
+            email_verified: claims.email_verified,
+            name: claims.preferred_username.or(claims.name),
+            link_user_id: pending.link_user_id,
+        })
+    }
+
+    fn provider(&self, name: &str) -> Result<&OidcProviderConfig> {
+        self.providers
+            .get(name)
+            .ok_or_else(|| AppError::BadRequest(format!("Unknown identity provider '{}'", name)))
+    }
+
+    async fn verify_id_token(
+        &self,
+        provider: &OidcProviderConfig,
+        metadata: &ProviderMetadata,
+        id_token: &str,
+    ) -> Result<IdTokenClaims> {
+        let header = decode_header(id_token)
+            .map_err(|_| AppError::AuthError("Malformed ID token".to_string()))?;
+
+        // Only accept asymmetric signatures verifiable with the provider's published keys
+        if matches!(header.alg, Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512) {
+            return Err(AppError::AuthError("Unsupported ID token algorithm".to_string()));
+        }
+
+        let key = self.decoding_key(provider, metadata, header.kid.as_deref()).await?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_audience(&[&provider.client_id]);
+        validation.set_issuer(&[&metadata.issuer]);
+
+        decode::<IdTokenClaims>(id_token, &key, &validation)
+            .map(|data| data.claims)
+            .map_err(|e| {
+                tracing::warn!("ID token from {} failed validation: {:?}", provider.name, e);
+                AppError::AuthError("Invalid ID token".to_string())
+            })
+    }
+
+    /// Find the signing key for `kid`, refetching the key set once in case keys were rotated
+    async fn decoding_key(
+        &self,
+        provider: &OidcProviderConfig,
+        metadata: &ProviderMetadata,
+        kid: Option<&str>,
+    ) -> Result<DecodingKey> {
+        for force_refresh in [false, true] {
+            let jwks = self.jwks(provider, metadata, force_refresh).await?;
+
+            let jwk = match kid {
+                Some(kid) => jwks.find(kid),
+                None if jwks.keys.len() == 1 => jwks.keys.first(),
+                None => None,
+            };
+
+            if let Some(jwk) = jwk {
+                return DecodingKey::from_jwk(jwk)
+                    .map_err(|_| AppError::AuthError("Unusable ID token signing key".to_string()));
+            }
+        }
+
+        Err(AppError::AuthError("Unknown ID token signing key".to_string()))
+    }
+
+    async fn metadata(&self, provider: &OidcProviderConfig) -> Result<ProviderMetadata> {
+        if let Some((metadata, fetched_at)) = self.metadata.read().await.get(&provider.name) {
+            if fetched_at.elapsed() < METADATA_TTL {
+                return Ok(metadata.clone());
+            }
+        }
+
+        let url = format!("{}/.well-known/openid-configuration", provider.issuer);
+        let metadata: ProviderMetadata = self.fetch_json(&url).await?;
+
+        if metadata.issuer.trim_end_matches('/') != provider.issuer {
+            return Err(AppError::UpstreamError(format!(
+                "Discovery issuer '{}' does not match configured issuer '{}'",
+                metadata.issuer, provider.issuer
+            )));
+        }
+
+        self.metadata
+            .write()
+            .await
+            .insert(provider.name.clone(), (metadata.clone(), Instant::now()));
+
+        Ok(metadata)
+    }
+
+    async fn jwks(
+        &self,
+        provider: &OidcProviderConfig,
+        metadata: &ProviderMetadata,
+        force_refresh: bool,
+    ) -> Result<JwkSet> {
+        if !force_refresh {
+            if let Some((jwks, fetched_at)) = self.jwks.read().await.get(&provider.name) {
+                if fetched_at.elapsed() < METADATA_TTL {
+                    return Ok(jwks.clone());
+                }
+            }
+        }
+
+        let jwks: JwkSet = self.fetch_json(&metadata.jwks_uri).await?;
+
+        self.jwks
+            .write()
+            .await
+            .insert(provider.name.clone(), (jwks.clone(), Instant::now()));
+
+        Ok(jwks)
+    }
+
+    async fn fetch_json<T: serde::de::DeserializeOwned>(&self, url: &str) -> Result<T> {
+        self.http
+            .get(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| AppError::UpstreamError(format!("GET {} failed: {}", url, e)))?
+            .json()
+            .await
+            .map_err(|e| AppError::UpstreamError(format!("Invalid JSON from {}: {}", url, e)))
+    }
+}
+
+/// Some providers send `email_verified` as the string "true"
+fn bool_or_string<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<bool, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum BoolOrString {
+        Bool(bool),
+        String(String),
+    }
+
+    Ok(match BoolOrString::deserialize(deserializer)? {
+        BoolOrString::Bool(value) => value,
+        BoolOrString::String(value) => value.eq_ignore_ascii_case("true"),
+    })
+}
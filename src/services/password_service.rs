@@ -0,0 +1,116 @@
+use crate::config::Config;
+use crate::errors::{AppError, Result};
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Algorithm, Argon2, KeyId, Params, ParamsBuilder, Version,
+};
+use sha2::{Digest, Sha256};
+
+/// Hashes and verifies passwords with Argon2id.
+///
+/// The optional pepper is applied as the Argon2 secret. Peppered hashes carry a fingerprint of
+/// the pepper as their `keyid`, so hashes made before a pepper was configured still verify (and
+/// are upgraded on the next login).
+#[derive(Clone)]
+pub struct PasswordService {
+    params: Params,
+    pepper: Option<Vec<u8>>,
+}
+
+impl PasswordService {
+    pub fn from_config(config: &Config) -> anyhow::Result<Self> {
+        let pepper = config
+            .password_pepper
+            .as_ref()
+            .map(|pepper| pepper.as_bytes().to_vec());
+
+        let mut params = ParamsBuilder::new();
+        params
+            .m_cost(config.argon2_memory_kib)
+            .t_cost(config.argon2_iterations)
+            .p_cost(config.argon2_parallelism);
+        if let Some(pepper) = &pepper {
+            let keyid = KeyId::new(&pepper_id(pepper))
+                .map_err(|e| anyhow::anyhow!("Invalid pepper id: {}", e))?;
+            params.keyid(keyid);
+        }
+
+        let params = params
+            .build()
+            .map_err(|e| anyhow::anyhow!("Invalid Argon2 parameters: {}", e))?;
+
+        if let Some(pepper) = &pepper {
+            Argon2::new_with_secret(pepper, Algorithm::Argon2id, Version::V0x13, params.clone())
+                .map_err(|e| anyhow::anyhow!("Invalid password pepper: {}", e))?;
+        }
+
+        Ok(Self { params, pepper })
+    }
+
+    pub fn hash(&self, password: &str) -> Result<String> {
+        let salt = SaltString::generate(&mut OsRng);
+
+        self.argon2()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+            .map_err(|_| AppError::PasswordHashError)
+    }
+
+    pub fn verify(&self, password: &str, hash: &str) -> Result<()> {
+        let parsed_hash = PasswordHash::new(hash).map_err(|_| AppError::PasswordHashError)?;
+        let keyid = Params::try_from(&parsed_hash)
+            .map(|params| params.keyid().to_vec())
+            .unwrap_or_default();
+
+        let argon2 = if keyid.is_empty() {
+            // Hashed before a pepper was configured
+            Argon2::default()
+        } else if keyid == self.params.keyid() {
+            self.argon2()
+        } else {
+            tracing::warn!("Password hash was made with a different pepper");
+            return Err(AppError::InvalidCredentials);
+        };
+
+        argon2
+            .verify_password(password.as_bytes(), &parsed_hash)
+            .map_err(|_| AppError::InvalidCredentials)
+    }
+
+    /// Whether `hash` was made with other parameters (or pepper) than are configured now
+    pub fn needs_rehash(&self, hash: &str) -> bool {
+        let Ok(parsed_hash) = PasswordHash::new(hash) else {
+            return false;
+        };
+
+        parsed_hash.algorithm != Algorithm::Argon2id.ident()
+            || parsed_hash.version != Some(Version::V0x13.into())
+            || Params::try_from(&parsed_hash).map_or(true, |params| {
+                params.m_cost() != self.params.m_cost()
+                    || params.t_cost() != self.params.t_cost()
+                    || params.p_cost() != self.params.p_cost()
+                    || params.keyid() != self.params.keyid()
+            })
+    }
+
+    fn argon2(&self) -> Argon2<'_> {
+        match &self.pepper {
+            Some(pepper) => Argon2::new_with_secret(
+                pepper,
+                Algorithm::Argon2id,
+                Version::V0x13,
+                self.params.clone(),
+            )
+            .expect("pepper was checked in from_config"),
+            None => Argon2::new(Algorithm::Argon2id, Version::V0x13, self.params.clone()),
+        }
+    }
+}
+
+/// Short fingerprint identifying the pepper a hash was made with
+fn pepper_id(pepper: &[u8]) -> [u8; Params::MAX_KEYID_LEN] {
+    let digest = Sha256::digest(pepper);
+    let mut id = [0u8; Params::MAX_KEYID_LEN];
+    id.copy_from_slice(&digest[..Params::MAX_KEYID_LEN]);
+    id
+}
@@ -0,0 +1,61 @@
+use crate::services::redis_service::RedisService;
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Short-lived, single-use state for multi-step flows (OIDC logins, WebAuthn ceremonies).
+///
+/// Entries are stored in Redis when available so any instance can finish the flow,
+/// and in process memory otherwise.
+pub struct PendingStore<T> {
+    redis: Option<Arc<RedisService>>,
+    /// Builds the Redis key for an entry id
+    key: fn(&str) -> String,
+    ttl: Duration,
+    memory: Mutex<HashMap<String, (T, Instant)>>,
+}
+
+impl<T: Serialize + DeserializeOwned> PendingStore<T> {
+    pub fn new(redis: Option<Arc<RedisService>>, key: fn(&str) -> String, ttl: Duration) -> Self {
+        Self {
+            redis,
+            key,
+            ttl,
+            memory: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn put(&self, id: &str, value: T) {
+        if let Some(redis) = &self.redis {
+            match redis
+                .set_with_ttl(&(self.key)(id), &value, self.ttl.as_secs() as usize)
+                .await
+            {
+                Ok(()) => return,
+                Err(e) => tracing::warn!("Redis error: {}. Keeping pending state in memory.", e),
+            }
+        }
+
+        let mut memory = self.memory.lock().unwrap_or_else(|p| p.into_inner());
+        memory.retain(|_, (_, created)| created.elapsed() < self.ttl);
+        memory.insert(id.to_string(), (value, Instant::now()));
+    }
+
+    /// Look up and remove an entry so each id can be used once
+    pub async fn take(&self, id: &str) -> Option<T> {
+        if let Some(redis) = &self.redis {
+            match redis.take::<T>(&(self.key)(id)).await {
+                Ok(Some(value)) => return Some(value),
+                Ok(None) => {}
+                Err(e) => tracing::warn!("Redis error: {}. Checking pending state in memory.", e),
+            }
+        }
+
+        let mut memory = self.memory.lock().unwrap_or_else(|p| p.into_inner());
+        memory
+            .remove(id)
+            .filter(|(_, created)| created.elapsed() < self.ttl)
+            .map(|(value, _)| value)
+    }
+}
@@ -0,0 +1,281 @@
+use crate::errors::{AppError, Result};
+use crate::models::user::{self, Entity as User, UpdateProfileRequest};
+use crate::models::user_avatar::{self, Entity as UserAvatar};
+use crate::validation::is_tombstone_username;
+use chrono::Utc;
+use image::{
+    codecs::jpeg::JpegEncoder, imageops::FilterType, DynamicImage, ImageFormat, ImageReader, Limits,
+};
+use sea_orm::{
+    sea_query::{Expr, OnConflict},
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait, Order,
+    PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, Set, TransactionTrait,
+};
+use std::io::Cursor;
+
+/// Largest image upload (avatar or room icon) accepted, in bytes
+pub const MAX_IMAGE_UPLOAD_BYTES: usize = 5 * 1024 * 1024;
+/// Avatars are stored as squares of this many pixels
+const AVATAR_SIZE: u32 = 256;
+/// Uploads larger than this in either dimension are refused before decoding
+const MAX_IMAGE_SOURCE_DIMENSION: u32 = 8192;
+const IMAGE_JPEG_QUALITY: u8 = 85;
+const DEFAULT_SEARCH_LIMIT: u64 = 20;
+const MAX_SEARCH_LIMIT: u64 = 50;
+
+/// Profiles of users: the editable fields and avatars
+#[derive(Clone)]
+pub struct ProfileService {
+    db: DatabaseConnection,
+}
+
+impl ProfileService {
+    pub fn new(db: DatabaseConnection) -> Self {
+        Self { db }
+    }
+
+    pub async fn get(&self, user_id: i32) -> Result<user::Model> {
+        User::find_by_id(user_id)
+            .one(&self.db)
+            .await?
+            .ok_or(AppError::UserNotFound)
+    }
+
+    /// Search the directory: users whose username or a word of their display name starts with
+    /// `query` come first, then those with similar names (trigram similarity). Users hidden from
+    /// the directory are never returned.
+    pub async fn search(
+        &self,
+        query: &str,
+        limit: Option<u64>,
+        offset: Option<u64>,
+    ) -> Result<Vec<user::Model>> {
+        let query = query.trim().to_lowercase();
+        let prefix = format!("{}%", escape_like(&query));
+        // Also match later words of display names, e.g. "pac" finds "Al Pacino"
+        let word_prefix = format!("% {}", prefix);
+        let limit = limit
+            .unwrap_or(DEFAULT_SEARCH_LIMIT)
+            .clamp(1, MAX_SEARCH_LIMIT);
+
+        let users = User::find()
+            .filter(user::Column::HiddenFromDirectory.eq(false))
+            .filter(Expr::cust_with_values(
+                "(lower(username) LIKE $1 OR lower(display_name) LIKE $1 \
+                 OR lower(display_name) LIKE $2 \
+                 OR lower(username) % $3 OR lower(display_name) % $3)",
+                [prefix.clone(), word_prefix.clone(), query.clone()],
+            ))
+            .order_by(
+                Expr::cust_with_values(
+                    "lower(username) LIKE $1 OR lower(display_name) LIKE $1 \
+                     OR lower(display_name) LIKE $2",
+                    [prefix, word_prefix],
+                ),
+                Order::Desc,
+            )
+            .order_by(
+                Expr::cust_with_values(
+                    "GREATEST(similarity(lower(username), $1), \
+                     similarity(lower(COALESCE(display_name, '')), $1))",
+                    [query],
+                ),
+                Order::Desc,
+            )
+            .order_by_asc(user::Column::Username)
+            .order_by_asc(user::Column::Id)
+            .limit(limit)
+            .offset(offset.unwrap_or(0))
+            .all(&self.db)
+            .await?;
+
+        Ok(users)
+    }
+
+    pub async fn update(&self, user_id: i32, req: UpdateProfileRequest) -> Result<user::Model> {
+        let mut user: user::ActiveModel = self.get(user_id).await?.into();
+
+        if let Some(username) = req.username {
+            if username_taken(&self.db, &username, Some(user_id)).await? {
+                return Err(AppError::UsernameTaken);
+            }
+            user.username = Set(username);
+        }
+        if let Some(display_name) = req.display_name {
+            user.display_name = Set(non_empty(display_name));
+        }
+        if let Some(bio) = req.bio {
+            user.bio = Set(non_empty(bio));
+        }
+        if let Some(status_text) = req.status_text {
+            user.status_text = Set(non_empty(status_text));
+        }
+        if let Some(timezone) = req.timezone {
+            user.timezone = Set(non_empty(timezone));
+        }
+        if let Some(hidden) = req.hidden_from_directory {
+            user.hidden_from_directory = Set(hidden);
+        }
+        if let Some(visible) = req.email_visible {
+            user.email_visible = Set(visible);
+        }
+
+        Ok(user.update(&self.db).await?)
+    }
+
+    /// Resize an uploaded image into the user's avatar
+    pub async fn set_avatar(&self, user_id: i32, upload: Vec<u8>) -> Result<user::Model> {
+        let (content_type, data) = tokio::task::spawn_blocking(move || {
+            process_square_image(&upload, AVATAR_SIZE, "Avatar")
+        })
+            .await
+            .map_err(|_| AppError::InternalServerError)??;
+
+        let now = Utc::now().naive_utc();
+        let txn = self.db.begin().await?;
+
+        UserAvatar::insert(user_avatar::ActiveModel {
+            user_id: Set(user_id),
+            content_type: Set(content_type.to_string()),
+            data: Set(data),
+            updated_at: Set(now),
+        })
+        .on_conflict(
+            OnConflict::column(user_avatar::Column::UserId)
+                .update_columns([
+                    user_avatar::Column::ContentType,
+                    user_avatar::Column::Data,
+                    user_avatar::Column::UpdatedAt,
+                ])
+                .to_owned(),
+        )
+        .exec(&txn)
+        .await?;
+
+        let mut user: user::ActiveModel = User::find_by_id(user_id)
+            .one(&txn)
+            .await?
+            .ok_or(AppError::UserNotFound)?
+            .into();
+        user.avatar_updated_at = Set(Some(now));
+        let user = user.update(&txn).await?;
+
+        txn.commit().await?;
+        Ok(user)
+    }
+
+    pub async fn delete_avatar(&self, user_id: i32) -> Result<()> {
+        let txn = self.db.begin().await?;
+
+        UserAvatar::delete_by_id(user_id).exec(&txn).await?;
+
+        let mut user: user::ActiveModel = User::find_by_id(user_id)
+            .one(&txn)
+            .await?
+            .ok_or(AppError::UserNotFound)?
+            .into();
+        user.avatar_updated_at = Set(None);
+        user.update(&txn).await?;
+
+        txn.commit().await?;
+        Ok(())
+    }
+
+    pub async fn avatar(&self, user_id: i32) -> Result<Option<user_avatar::Model>> {
+        Ok(UserAvatar::find_by_id(user_id).one(&self.db).await?)
+    }
+}
+
+/// Make `value` match literally inside a LIKE pattern
+pub fn escape_like(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// Whether someone other than `except` goes by `username`. Usernames are unique regardless
+/// of case, so clients can tell senders apart by name.
+pub async fn username_taken<C: ConnectionTrait>(
+    db: &C,
+    username: &str,
+    except: Option<i32>,
+) -> Result<bool> {
+    let mut query = User::find().filter(Expr::cust_with_values(
+        "lower(username) = $1",
+        [username.trim().to_lowercase()],
+    ));
+    if let Some(user_id) = except {
+        query = query.filter(user::Column::Id.ne(user_id));
+    }
+
+    Ok(query.count(db).await? > 0)
+}
+
+/// `base` if it is free, otherwise `base` with the first free suffix (`base-2`, `base-3`, ...)
+pub async fn available_username<C: ConnectionTrait>(db: &C, base: &str) -> Result<String> {
+    let base = if base.trim().is_empty() || is_tombstone_username(base) {
+        "user"
+    } else {
+        base.trim()
+    };
+    if !username_taken(db, base, None).await? {
+        return Ok(base.to_string());
+    }
+
+    for suffix in 2.. {
+        let candidate = format!("{}-{}", base, suffix);
+        if !username_taken(db, &candidate, None).await? {
+            return Ok(candidate);
+        }
+    }
+    unreachable!("ran out of username suffixes")
+}
+
+/// Treat blank optional fields as cleared
+pub fn non_empty(value: String) -> Option<String> {
+    let value = value.trim();
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+/// Decode an upload, crop it to a centred square and scale it to `size` pixels. Images with
+/// transparency are stored as PNG, everything else as JPEG. `label` names the image in errors.
+pub fn process_square_image(
+    upload: &[u8],
+    size: u32,
+    label: &str,
+) -> Result<(&'static str, Vec<u8>)> {
+    let unsupported =
+        || AppError::BadRequest(format!("{} must be a PNG, JPEG, GIF or WebP image", label));
+
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_IMAGE_SOURCE_DIMENSION);
+    limits.max_image_height = Some(MAX_IMAGE_SOURCE_DIMENSION);
+
+    let mut reader = ImageReader::new(Cursor::new(upload))
+        .with_guessed_format()
+        .map_err(|_| unsupported())?;
+    if !matches!(
+        reader.format(),
+        Some(ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::Gif | ImageFormat::WebP)
+    ) {
+        return Err(unsupported());
+    }
+    reader.limits(limits);
+
+    let image = reader.decode().map_err(|_| unsupported())?;
+    let square = image.resize_to_fill(size, size, FilterType::Lanczos3);
+
+    let mut out = Vec::new();
+    if square.color().has_alpha() {
+        square
+            .write_to(&mut Cursor::new(&mut out), ImageFormat::Png)
+            .map_err(|_| AppError::InternalServerError)?;
+        Ok(("image/png", out))
+    } else {
+        let rgb = DynamicImage::ImageRgb8(square.to_rgb8());
+        rgb.write_with_encoder(JpegEncoder::new_with_quality(&mut out, IMAGE_JPEG_QUALITY))
+            .map_err(|_| AppError::InternalServerError)?;
+        Ok(("image/jpeg", out))
+    }
+}
@@ -0,0 +1,70 @@
+use crate::errors::{AppError, Result};
+use crate::services::redis_service::{CacheKey, RedisService};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+struct Hits {
+    count: u64,
+    expires: Instant,
+}
+
+/// Caps how often a user may perform an action. Every attempt counts, and the window restarts
+/// with each one, so clients that keep retrying while limited stay limited.
+///
+/// Counters live in Redis when available so every instance sees them, and in process memory
+/// otherwise.
+pub struct RateLimiter {
+    redis: Option<Arc<RedisService>>,
+    memory: Mutex<HashMap<String, Hits>>,
+}
+
+impl RateLimiter {
+    pub fn new(redis: Option<Arc<RedisService>>) -> Self {
+        Self {
+            redis,
+            memory: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Count an attempt at `action` by `subject`, refusing it once more than `limit` were made
+    /// within `window_seconds` of each other
+    pub async fn hit(
+        &self,
+        action: &str,
+        subject: &str,
+        limit: u64,
+        window_seconds: u64,
+    ) -> Result<()> {
+        if self.increment(action, subject, window_seconds).await > limit {
+            return Err(AppError::RateLimited {
+                retry_after: window_seconds,
+            });
+        }
+
+        Ok(())
+    }
+
+    async fn increment(&self, action: &str, subject: &str, window_seconds: u64) -> u64 {
+        let key = CacheKey::rate_limit(action, subject);
+
+        if let Some(redis) = &self.redis {
+            match redis.incr_with_ttl(&key, window_seconds as usize).await {
+                Ok(count) => return count.max(0) as u64,
+                Err(e) => tracing::warn!("Redis error: {}. Counting {} in memory.", e, action),
+            }
+        }
+
+        let now = Instant::now();
+        let mut memory = self.memory.lock().unwrap_or_else(|p| p.into_inner());
+        memory.retain(|_, hits| hits.expires > now);
+
+        let hits = memory.entry(key).or_insert(Hits {
+            count: 0,
+            expires: now,
+        });
+        hits.count += 1;
+        hits.expires = now + Duration::from_secs(window_seconds);
+        hits.count
+    }
+}
@@ -1,5 +1,6 @@
 use redis::{aio::ConnectionManager, AsyncCommands, RedisError};
 use serde::{de::DeserializeOwned, Serialize};
+use uuid::Uuid;
 
 /// Redis cache service for managing cached data
 #[derive(Clone)]
@@ -67,6 +68,33 @@ impl RedisService {
         Ok(())
     }
 
+    /// Get a value and delete it in one step (GETDEL), so it can be consumed only once
+    pub async fn take<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>, RedisError> {
+        let mut conn = self.client.clone();
+        let value: Option<String> = redis::cmd("GETDEL").arg(key).query_async(&mut conn).await?;
+
+        Ok(value.and_then(|json| match serde_json::from_str(&json) {
+            Ok(data) => Some(data),
+            Err(e) => {
+                tracing::warn!("Failed to deserialize cached value for key {}: {}", key, e);
+                None
+            }
+        }))
+    }
+
+    /// Increment a counter and (re)set its TTL in one step, returning the new value
+    pub async fn incr_with_ttl(&self, key: &str, ttl_seconds: usize) -> Result<i64, RedisError> {
+        let mut conn = self.client.clone();
+        let (value,): (i64,) = redis::pipe()
+            .atomic()
+            .incr(key, 1)
+            .expire(key, ttl_seconds as i64)
+            .ignore()
+            .query_async(&mut conn)
+            .await?;
+        Ok(value)
+    }
+
     /// Delete a key from cache
     pub async fn delete(&self, key: &str) -> Result<(), RedisError> {
         let mut conn = self.client.clone();
@@ -114,10 +142,11 @@ impl RedisService {
 /// Cache key builder for consistent key naming
 pub struct CacheKey;
 
+#[allow(dead_code)]
 impl CacheKey {
-    /// Generate cache key for rooms list
-    pub fn rooms_list() -> String {
-        "rooms:list".to_string()
+    /// Generate cache key for the list of public rooms
+    pub fn public_rooms() -> String {
+        "rooms:list:public".to_string()
     }
 
     /// Generate cache key for a specific room
@@ -135,6 +164,51 @@ impl CacheKey {
         format!("user:{}", user_id)
     }
 
+    /// Generate key marking an access token (by `jti`) as revoked
+    pub fn revoked_token(jti: &str) -> String {
+        format!("revoked:token:{}", jti)
+    }
+
+    /// Generate key marking a session (by id) as revoked
+    pub fn revoked_session(session_id: Uuid) -> String {
+        format!("revoked:session:{}", session_id)
+    }
+
+    /// Generate key holding the cutoff before which a user's tokens are revoked
+    pub fn user_tokens_valid_after(user_id: i32) -> String {
+        format!("revoked:user:{}", user_id)
+    }
+
+    /// Generate key marking a user as disabled by an admin
+    pub fn user_disabled(user_id: i32) -> String {
+        format!("disabled:user:{}", user_id)
+    }
+
+    /// Generate key counting recent failed logins for an account or IP (`kind`)
+    pub fn login_failures(kind: &str, subject: &str) -> String {
+        format!("login:failures:{}:{}", kind, subject)
+    }
+
+    /// Generate key marking an account or IP (`kind`) as temporarily locked out of login
+    pub fn login_lock(kind: &str, subject: &str) -> String {
+        format!("login:lock:{}:{}", kind, subject)
+    }
+
+    /// Generate key counting recent attempts at a rate limited `action` by `subject`
+    pub fn rate_limit(action: &str, subject: &str) -> String {
+        format!("ratelimit:{}:{}", action, subject)
+    }
+
+    /// Generate key for a pending OpenID Connect login, by `state` parameter
+    pub fn oidc_state(state: &str) -> String {
+        format!("oidc:state:{}", state)
+    }
+
+    /// Generate key for a pending WebAuthn ceremony, by challenge
+    pub fn webauthn_challenge(challenge: &str) -> String {
+        format!("webauthn:challenge:{}", challenge)
+    }
+
     /// Pattern to match all room keys
     pub fn rooms_pattern() -> String {
         "room*".to_string()
@@ -0,0 +1,154 @@
+use crate::errors::{AppError, Result};
+use crate::models::refresh_token::{self, Entity as RefreshToken};
+use crate::utils::{generate_opaque_token, hash_token};
+use chrono::{Duration, Utc};
+use sea_orm::{
+    sea_query::Expr, ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection,
+    EntityTrait, QueryFilter, Set, TransactionTrait,
+};
+use uuid::Uuid;
+
+/// Number of random bytes in a refresh token
+const REFRESH_TOKEN_BYTES: usize = 32;
+
+/// Issues and rotates opaque refresh tokens.
+///
+/// Only a SHA-256 hash of each token is stored. Every rotation revokes the presented token and
+/// mints a new one in the same family; presenting an already-rotated token means it leaked, so
+/// the whole family is revoked and the legitimate holder has to log in again.
+#[derive(Clone)]
+pub struct RefreshTokenService {
+    db: DatabaseConnection,
+    ttl_days: i64,
+}
+
+impl RefreshTokenService {
+    pub fn new(db: DatabaseConnection, ttl_days: i64) -> Self {
+        Self { db, ttl_days }
+    }
+
+    /// Issue a token for a fresh login, starting the family `family_id` (the session id)
+    pub async fn issue(&self, user_id: i32, family_id: Uuid) -> Result<String> {
+        let (raw, _) = self.insert(&self.db, user_id, family_id).await?;
+        Ok(raw)
+    }
+
+    /// Exchange a refresh token for a new one.
+    /// Returns the owning user id, the new raw token and the token family.
+    pub async fn rotate(&self, raw: &str) -> Result<(i32, String, Uuid)> {
+        let current = RefreshToken::find()
+            .filter(refresh_token::Column::TokenHash.eq(hash_token(raw)))
+            .one(&self.db)
+            .await?
+            .ok_or(AppError::InvalidToken)?;
+
+        if current.revoked_at.is_some() {
+            return Err(self.reuse_detected(&current).await);
+        }
+
+        if current.expires_at <= Utc::now().naive_utc() {
+            return Err(AppError::TokenExpired);
+        }
+
+        let txn = self.db.begin().await?;
+
+        // Guard against two concurrent rotations of the same token: only one may win
+        let claimed = RefreshToken::update_many()
+            .col_expr(refresh_token::Column::RevokedAt, Expr::value(Utc::now().naive_utc()))
+            .filter(refresh_token::Column::Id.eq(current.id))
+            .filter(refresh_token::Column::RevokedAt.is_null())
+            .exec(&txn)
+            .await?;
+
+        if claimed.rows_affected == 0 {
+            txn.rollback().await?;
+            return Err(self.reuse_detected(&current).await);
+        }
+
+        let (raw, next_id) = self.insert(&txn, current.user_id, current.family_id).await?;
+
+        RefreshToken::update_many()
+            .col_expr(refresh_token::Column::ReplacedById, Expr::value(next_id))
+            .filter(refresh_token::Column::Id.eq(current.id))
+            .exec(&txn)
+            .await?;
+
+        txn.commit().await?;
+
+        Ok((current.user_id, raw, current.family_id))
+    }
+
+    /// Revoke every live token in a family
+    pub async fn revoke_family(&self, family_id: Uuid) -> Result<()> {
+        RefreshToken::update_many()
+            .col_expr(refresh_token::Column::RevokedAt, Expr::value(Utc::now().naive_utc()))
+            .filter(refresh_token::Column::FamilyId.eq(family_id))
+            .filter(refresh_token::Column::RevokedAt.is_null())
+            .exec(&self.db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Revoke the family of a presented token, provided it belongs to `user_id`
+    pub async fn revoke_family_of(&self, raw: &str, user_id: i32) -> Result<()> {
+        let token = RefreshToken::find()
+            .filter(refresh_token::Column::TokenHash.eq(hash_token(raw)))
+            .filter(refresh_token::Column::UserId.eq(user_id))
+            .one(&self.db)
+            .await?;
+
+        match token {
+            Some(token) => self.revoke_family(token.family_id).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Revoke every live refresh token belonging to `user_id`
+    pub async fn revoke_all_for_user(&self, user_id: i32) -> Result<()> {
+        RefreshToken::update_many()
+            .col_expr(refresh_token::Column::RevokedAt, Expr::value(Utc::now().naive_utc()))
+            .filter(refresh_token::Column::UserId.eq(user_id))
+            .filter(refresh_token::Column::RevokedAt.is_null())
+            .exec(&self.db)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn reuse_detected(&self, token: &refresh_token::Model) -> AppError {
+        tracing::warn!(
+            "Refresh token reuse detected for user {} (family {}); revoking family",
+            token.user_id,
+            token.family_id
+        );
+
+        match self.revoke_family(token.family_id).await {
+            Ok(()) => AppError::InvalidToken,
+            Err(e) => e,
+        }
+    }
+
+    async fn insert<C: ConnectionTrait>(
+        &self,
+        conn: &C,
+        user_id: i32,
+        family_id: Uuid,
+    ) -> Result<(String, i32)> {
+        let raw = generate_opaque_token(REFRESH_TOKEN_BYTES);
+        let now = Utc::now().naive_utc();
+
+        let token = refresh_token::ActiveModel {
+            user_id: Set(user_id),
+            family_id: Set(family_id),
+            token_hash: Set(hash_token(&raw)),
+            expires_at: Set(now + Duration::days(self.ttl_days)),
+            created_at: Set(now),
+            ..Default::default()
+        }
+        .insert(conn)
+        .await?;
+
+        Ok((raw, token.id))
+    }
+}
@@ -0,0 +1,253 @@
+use crate::errors::{AppError, Result};
+use crate::models::revoked_token::{self, Entity as RevokedToken};
+use crate::models::session::Entity as Session;
+use crate::models::user::{self, Entity as User};
+use crate::services::jwt_service::Claims;
+use crate::services::redis_service::{CacheKey, RedisService};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use sea_orm::{
+    sea_query::{Expr, OnConflict},
+    ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QuerySelect, Set,
+};
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Server-side denylist for access tokens.
+///
+/// Postgres is the source of truth. When Redis is available, every revocation is mirrored there
+/// with a TTL matching the token lifetime and used as a positive cache: a hit answers "revoked"
+/// straight away, while a miss, an error or a disabled Redis falls back to Postgres. A lost
+/// mirror write therefore only costs a database lookup. Tokens bound to a session are revoked
+/// together with it: in Postgres that means the session row is gone.
+#[derive(Clone)]
+pub struct RevocationService {
+    db: DatabaseConnection,
+    redis: Option<Arc<RedisService>>,
+    access_token_ttl_secs: i64,
+}
+
+impl RevocationService {
+    pub fn new(
+        db: DatabaseConnection,
+        redis: Option<Arc<RedisService>>,
+        access_token_ttl_secs: i64,
+    ) -> Self {
+        Self {
+            db,
+            redis,
+            access_token_ttl_secs,
+        }
+    }
+
+    /// Revoke a single access token until it expires
+    pub async fn revoke_token(&self, claims: &Claims) -> Result<()> {
+        let jti = Uuid::parse_str(&claims.jti).map_err(|_| AppError::InvalidToken)?;
+        let user_id = claims.user_id()?;
+        let now = Utc::now().naive_utc();
+        let expires_at = timestamp_to_naive(claims.exp as i64);
+
+        let entry = revoked_token::ActiveModel {
+            jti: Set(jti),
+            user_id: Set(user_id),
+            expires_at: Set(expires_at),
+            revoked_at: Set(now),
+        };
+
+        RevokedToken::insert(entry)
+            .on_conflict(
+                OnConflict::column(revoked_token::Column::Jti)
+                    .do_nothing()
+                    .to_owned(),
+            )
+            .exec_without_returning(&self.db)
+            .await?;
+
+        // Entries for tokens that have expired on their own are no longer needed
+        RevokedToken::delete_many()
+            .filter(revoked_token::Column::ExpiresAt.lt(now))
+            .exec(&self.db)
+            .await?;
+
+        if let Some(redis) = &self.redis {
+            let ttl = (claims.exp as i64 - now.and_utc().timestamp()).max(1) as usize;
+            if let Err(e) = redis
+                .set_with_ttl(&CacheKey::revoked_token(&claims.jti), &true, ttl)
+                .await
+            {
+                tracing::warn!("Failed to mirror token revocation to Redis: {}", e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Revoke every access token issued to `user_id` up to now
+    pub async fn revoke_all_for_user(&self, user_id: i32) -> Result<()> {
+        let now = Utc::now();
+
+        User::update_many()
+            .col_expr(user::Column::TokensValidAfter, Expr::value(now.naive_utc()))
+            .filter(user::Column::Id.eq(user_id))
+            .exec(&self.db)
+            .await?;
+
+        if let Some(redis) = &self.redis {
+            if let Err(e) = redis
+                .set_with_ttl(
+                    &CacheKey::user_tokens_valid_after(user_id),
+                    &now.timestamp(),
+                    self.access_token_ttl_secs.max(1) as usize,
+                )
+                .await
+            {
+                tracing::warn!("Failed to mirror user token revocation to Redis: {}", e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Disable `user_id`, refusing all of their tokens, or enable them again. Tokens issued
+    /// before the user was disabled stay revoked; callers revoke them with `revoke_all_for_user`.
+    pub async fn set_user_disabled(&self, user_id: i32, disabled: bool) -> Result<()> {
+        let disabled_at = disabled.then(|| Utc::now().naive_utc());
+
+        User::update_many()
+            .col_expr(user::Column::DisabledAt, Expr::value(disabled_at))
+            .filter(user::Column::Id.eq(user_id))
+            .exec(&self.db)
+            .await?;
+
+        if let Some(redis) = &self.redis {
+            let key = CacheKey::user_disabled(user_id);
+            // Disabled users cannot get new tokens, so once their last token has expired the
+            // mirror is no longer needed
+            let mirrored = if disabled {
+                redis
+                    .set_with_ttl(&key, &true, self.access_token_ttl_secs.max(1) as usize)
+                    .await
+            } else {
+                redis.delete(&key).await
+            };
+            if let Err(e) = mirrored {
+                tracing::warn!("Failed to mirror disabled user to Redis: {}", e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether `user_id` has been disabled by an admin. Like revocations, the Redis mirror is
+    /// only trusted when it says the user is disabled.
+    pub async fn is_disabled(&self, user_id: i32) -> Result<bool> {
+        if let Some(redis) = &self.redis {
+            match redis.exists(&CacheKey::user_disabled(user_id)).await {
+                Ok(true) => return Ok(true),
+                Ok(false) => {}
+                Err(e) => {
+                    tracing::warn!("Redis error: {}. Checking disabled user in database.", e);
+                }
+            }
+        }
+
+        let disabled_at: Option<Option<NaiveDateTime>> = User::find_by_id(user_id)
+            .select_only()
+            .column(user::Column::DisabledAt)
+            .into_tuple()
+            .one(&self.db)
+            .await?;
+
+        Ok(disabled_at.flatten().is_some())
+    }
+
+    /// Revoke every access token issued to a session. The caller deletes the session row,
+    /// which is what the Postgres check looks at; a failed Redis mirror is reported so the
+    /// caller does not treat the revocation as complete.
+    pub async fn revoke_session(&self, session_id: Uuid) -> Result<()> {
+        if let Some(redis) = &self.redis {
+            redis
+                .set_with_ttl(
+                    &CacheKey::revoked_session(session_id),
+                    &true,
+                    self.access_token_ttl_secs.max(1) as usize,
+                )
+                .await
+                .map_err(|e| {
+                    tracing::error!("Failed to mirror session revocation to Redis: {}", e);
+                    AppError::InternalServerError
+                })?;
+        }
+
+        Ok(())
+    }
+
+    /// Whether the token described by `claims` has been revoked
+    pub async fn is_revoked(&self, claims: &Claims) -> Result<bool> {
+        if let Some(redis) = &self.redis {
+            match self.is_revoked_in_redis(redis, claims).await {
+                Ok(true) => return Ok(true),
+                Ok(false) => {}
+                Err(e) => {
+                    tracing::warn!("Redis error: {}. Checking revocation in database.", e);
+                }
+            }
+        }
+
+        self.is_revoked_in_db(claims).await
+    }
+
+    /// Whether Redis holds a revocation covering `claims`. Only `true` is conclusive.
+    async fn is_revoked_in_redis(
+        &self,
+        redis: &RedisService,
+        claims: &Claims,
+    ) -> std::result::Result<bool, redis::RedisError> {
+        if redis.exists(&CacheKey::revoked_token(&claims.jti)).await? {
+            return Ok(true);
+        }
+
+        if let Some(session_id) = claims.session_id() {
+            if redis.exists(&CacheKey::revoked_session(session_id)).await? {
+                return Ok(true);
+            }
+        }
+
+        let user_id = claims.sub.parse::<i32>().unwrap_or_default();
+        let valid_after = redis
+            .get::<i64>(&CacheKey::user_tokens_valid_after(user_id))
+            .await?;
+
+        Ok(valid_after.is_some_and(|cutoff| (claims.iat as i64) < cutoff))
+    }
+
+    async fn is_revoked_in_db(&self, claims: &Claims) -> Result<bool> {
+        let jti = Uuid::parse_str(&claims.jti).map_err(|_| AppError::InvalidToken)?;
+
+        if RevokedToken::find_by_id(jti).one(&self.db).await?.is_some() {
+            return Ok(true);
+        }
+
+        if let Some(session_id) = claims.session_id() {
+            if Session::find_by_id(session_id).one(&self.db).await?.is_none() {
+                return Ok(true);
+            }
+        }
+
+        let valid_after: Option<Option<NaiveDateTime>> = User::find_by_id(claims.user_id()?)
+            .select_only()
+            .column(user::Column::TokensValidAfter)
+            .into_tuple()
+            .one(&self.db)
+            .await?;
+
+        Ok(valid_after
+            .flatten()
+            .is_some_and(|cutoff| (claims.iat as i64) < cutoff.and_utc().timestamp()))
+    }
+}
+
+fn timestamp_to_naive(timestamp: i64) -> NaiveDateTime {
+    DateTime::from_timestamp(timestamp, 0)
+        .unwrap_or_else(Utc::now)
+        .naive_utc()
+}
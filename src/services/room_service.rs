@@ -0,0 +1,1032 @@
+use crate::db::{advisory_xact_lock, LockSpace};
+use crate::errors::{AppError, Result};
+use crate::models::message::{self, Entity as Message, MessageKind};
+use crate::models::room::{
+    self, CreateDmRequest, CreateRoomRequest, Entity as Room, PostingPolicy, RoomKind,
+    RoomVisibility, UpdateRoomRequest,
+};
+use crate::models::room_ban::{self, Entity as RoomBan};
+use crate::models::room_icon::{self, Entity as RoomIcon};
+use crate::models::room_invitation::{self, Entity as RoomInvitation};
+use crate::models::room_join_request::{self, Entity as RoomJoinRequest};
+use crate::models::room_member::{self, Entity as RoomMember, RoomRole};
+use crate::models::room_permission::{
+    self, Entity as RoomPermission, Permission, PermissionOverride, RoomPermissionsResponse,
+};
+use crate::models::user::{self, Entity as User, UserRole};
+use crate::models::user_block::{self, BlockKind, Entity as UserBlock};
+use crate::services::jwt_service::Claims;
+use crate::services::message_service::not_expired;
+use crate::services::profile_service::{non_empty, process_square_image};
+use chrono::Utc;
+use sea_orm::{
+    sea_query::{Condition, Expr, OnConflict},
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait, Iterable,
+    PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, Set, TransactionTrait,
+};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Room icons are stored as squares of this many pixels
+const ROOM_ICON_SIZE: u32 = 256;
+
+/// What the caller of a room-scoped request may do there, as worked out by
+/// `RoomService::access` and `RoomService::authorize`
+#[derive(Clone, Debug)]
+pub struct RoomAccess {
+    pub room: room::Model,
+    pub user_id: i32,
+    /// The caller's membership; `None` if they have not joined
+    pub member_role: Option<RoomRole>,
+    /// Server admins act as the owner of every channel; DMs stay between their participants
+    pub server_admin: bool,
+    pub permissions: BTreeSet<Permission>,
+}
+
+impl RoomAccess {
+    /// The role the caller acts with. Anyone may take part in a public room without joining it,
+    /// with the rights of a member.
+    pub fn role(&self) -> RoomRole {
+        if self.server_admin {
+            return RoomRole::Owner;
+        }
+        self.member_role.unwrap_or(RoomRole::Member)
+    }
+
+    pub fn can(&self, permission: Permission) -> bool {
+        self.permissions.contains(&permission)
+    }
+
+    pub fn require(&self, permission: Permission) -> Result<()> {
+        if self.can(permission) {
+            return Ok(());
+        }
+        Err(AppError::Forbidden(format!(
+            "You need the {} permission in this room",
+            permission
+        )))
+    }
+
+    /// Refuse to post in an announcement room unless the caller is an admin or the owner
+    pub fn require_posting(&self) -> Result<()> {
+        if self.room.settings.posting == PostingPolicy::Admins
+            && !self.role().outranks(RoomRole::Moderator)
+        {
+            return Err(AppError::Forbidden(
+                "Only admins can post in this room".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Seconds the caller has to leave between messages; moderators and above are exempt
+    pub fn slow_mode_seconds(&self) -> u32 {
+        if self.role().outranks(RoomRole::Member) {
+            return 0;
+        }
+        self.room.settings.slow_mode_seconds
+    }
+
+    /// Refuse to act on a member of `target` role unless the caller outranks them
+    pub fn require_outranks(&self, target: RoomRole) -> Result<()> {
+        if self.role().outranks(target) {
+            return Ok(());
+        }
+        Err(AppError::Forbidden(
+            "You can only manage members below your own role".to_string(),
+        ))
+    }
+}
+
+/// Rooms, their members and what each member may do
+#[derive(Clone)]
+pub struct RoomService {
+    db: DatabaseConnection,
+    max_group_dm_participants: usize,
+}
+
+impl RoomService {
+    pub fn new(db: DatabaseConnection, max_group_dm_participants: usize) -> Self {
+        Self {
+            db,
+            max_group_dm_participants,
+        }
+    }
+
+    /// Create a room owned by `owner_id`
+    pub async fn create(&self, owner_id: i32, req: CreateRoomRequest) -> Result<room::Model> {
+        let now = Utc::now().naive_utc();
+        let txn = self.db.begin().await?;
+
+        let room = room::ActiveModel {
+            name: Set(req.name.trim().to_string()),
+            visibility: Set(req.visibility),
+            kind: Set(RoomKind::Channel),
+            created_at: Set(now),
+            ..Default::default()
+        }
+        .insert(&txn)
+        .await?;
+
+        room_member::ActiveModel {
+            room_id: Set(room.id),
+            user_id: Set(owner_id),
+            role: Set(RoomRole::Owner),
+            joined_at: Set(now),
+            ..Default::default()
+        }
+        .insert(&txn)
+        .await?;
+
+        txn.commit().await?;
+
+        tracing::info!("User {} created room {}", owner_id, room.id);
+        Ok(room)
+    }
+
+    /// Find the DM between `user_id` and the users in `req`, or start one. A DM with a single
+    /// other user is reused if it exists; group DMs are always new, owned by `user_id`.
+    ///
+    /// Returns the room and whether it was created.
+    pub async fn start_dm(
+        &self,
+        user_id: i32,
+        req: CreateDmRequest,
+    ) -> Result<(room::Model, bool)> {
+        let mut others: Vec<i32> = req
+            .user_ids
+            .into_iter()
+            .filter(|id| *id != user_id)
+            .collect();
+        others.sort_unstable();
+        others.dedup();
+
+        if others.is_empty() {
+            return Err(AppError::BadRequest(
+                "A DM needs someone other than yourself".to_string(),
+            ));
+        }
+
+        for &other in &others {
+            self.require_user(other).await?;
+            if self.blocked_between(user_id, other).await? {
+                return Err(AppError::Forbidden(
+                    "You cannot message this user".to_string(),
+                ));
+            }
+        }
+
+        let kind = if others.len() == 1 {
+            RoomKind::Dm
+        } else {
+            RoomKind::GroupDm
+        };
+        let name = match kind {
+            RoomKind::GroupDm => req
+                .name
+                .map(|name| name.trim().to_string())
+                .unwrap_or_default(),
+            _ => String::new(),
+        };
+
+        let now = Utc::now().naive_utc();
+        let txn = self.db.begin().await?;
+
+        if let [other] = others[..] {
+            // Serializes DMs between the same pair, so two concurrent requests cannot
+            // both miss the existing room and create one each. See `LockSpace` for how lock
+            // keys are namespaced.
+            advisory_xact_lock(
+                &txn,
+                LockSpace::DirectMessage,
+                user_id.min(other),
+                user_id.max(other),
+            )
+            .await?;
+
+            if let Some(room) = find_dm(&txn, user_id, other).await? {
+                return Ok((room, false));
+            }
+        }
+
+        let room = room::ActiveModel {
+            name: Set(name),
+            visibility: Set(RoomVisibility::Private),
+            kind: Set(kind),
+            created_at: Set(now),
+            ..Default::default()
+        }
+        .insert(&txn)
+        .await?;
+
+        // Neither side of a 1:1 DM can manage the other, so it has no owner
+        let creator_role = match kind {
+            RoomKind::GroupDm => RoomRole::Owner,
+            _ => RoomRole::Member,
+        };
+        let participants = std::iter::once((user_id, creator_role))
+            .chain(others.iter().map(|&id| (id, RoomRole::Member)));
+        RoomMember::insert_many(participants.map(|(id, role)| room_member::ActiveModel {
+            room_id: Set(room.id),
+            user_id: Set(id),
+            role: Set(role),
+            joined_at: Set(now),
+            ..Default::default()
+        }))
+        .exec_without_returning(&txn)
+        .await?;
+
+        txn.commit().await?;
+
+        tracing::info!("User {} started {:?} {}", user_id, kind, room.id);
+        Ok((room, true))
+    }
+
+    /// Rooms listed for everyone
+    pub async fn public_rooms(&self) -> Result<Vec<room::Model>> {
+        Ok(Room::find()
+            .filter(room::Column::Visibility.eq(RoomVisibility::Public))
+            .order_by_asc(room::Column::Id)
+            .all(&self.db)
+            .await?)
+    }
+
+    /// Private rooms and DMs `user_id` belongs to
+    pub async fn private_rooms(&self, user_id: i32) -> Result<Vec<room::Model>> {
+        Ok(Room::find()
+            .inner_join(RoomMember)
+            .filter(room_member::Column::UserId.eq(user_id))
+            .filter(room::Column::Visibility.eq(RoomVisibility::Private))
+            .order_by_asc(room::Column::Id)
+            .all(&self.db)
+            .await?)
+    }
+
+    /// Work out what the caller may do in `room_id`. Fails if the room does not exist, is
+    /// private and the caller is not in it, or the caller is banned from it.
+    pub async fn access(&self, room_id: i32, claims: &Claims) -> Result<RoomAccess> {
+        let user_id = claims.user_id()?;
+
+        let room = Room::find_by_id(room_id)
+            .one(&self.db)
+            .await?
+            .ok_or(AppError::RoomNotFound)?;
+
+        // The role in the token may be stale, so it is only a hint to check the database
+        let server_admin = room.kind == RoomKind::Channel
+            && claims.is_admin()
+            && User::find_by_id(user_id)
+                .select_only()
+                .column(user::Column::Role)
+                .into_tuple::<UserRole>()
+                .one(&self.db)
+                .await?
+                == Some(UserRole::Admin);
+
+        if !server_admin && self.ban(room_id, user_id).await?.is_some() {
+            return Err(AppError::Forbidden(
+                "You are banned from this room".to_string(),
+            ));
+        }
+
+        let member_role = self.member(room_id, user_id).await?.map(|m| m.role);
+
+        // Private rooms are not acknowledged to outsiders
+        if room.visibility == RoomVisibility::Private && member_role.is_none() && !server_admin {
+            return Err(AppError::RoomNotFound);
+        }
+
+        let mut access = RoomAccess {
+            room,
+            user_id,
+            member_role,
+            server_admin,
+            permissions: BTreeSet::new(),
+        };
+        access.permissions = self.role_permissions(room_id, access.role()).await?;
+
+        Ok(access)
+    }
+
+    /// Like `access`, but also require `permission`. Every room-scoped handler goes through
+    /// one of the two.
+    pub async fn authorize(
+        &self,
+        room_id: i32,
+        claims: &Claims,
+        permission: Permission,
+    ) -> Result<RoomAccess> {
+        let access = self.access(room_id, claims).await?;
+        access.require(permission)?;
+        Ok(access)
+    }
+
+    /// Delete the room with its messages. Only its owner (or a server admin) may.
+    pub async fn delete(&self, access: &RoomAccess) -> Result<()> {
+        if access.role() != RoomRole::Owner {
+            return Err(AppError::Forbidden(
+                "Only the owner can delete a room".to_string(),
+            ));
+        }
+
+        Room::delete_by_id(access.room.id).exec(&self.db).await?;
+        tracing::info!("User {} deleted room {}", access.user_id, access.room.id);
+        Ok(())
+    }
+
+    /// Change the room's name, topic, description or settings; needs `manage-room`
+    pub async fn update(
+        &self,
+        access: &RoomAccess,
+        req: UpdateRoomRequest,
+    ) -> Result<room::Model> {
+        access.require(Permission::ManageRoom)?;
+
+        let mut room: room::ActiveModel = access.room.clone().into();
+        if let Some(name) = req.name {
+            room.name = Set(name.trim().to_string());
+        }
+        if let Some(topic) = req.topic {
+            room.topic = Set(non_empty(topic));
+        }
+        if let Some(description) = req.description {
+            room.description = Set(non_empty(description));
+        }
+        if let Some(update) = req.settings {
+            let mut settings = access.room.settings.clone();
+            if let Some(seconds) = update.slow_mode_seconds {
+                settings.slow_mode_seconds = seconds;
+            }
+            if let Some(seconds) = update.message_expiry_seconds {
+                settings.message_expiry_seconds = seconds;
+            }
+            if let Some(posting) = update.posting {
+                settings.posting = posting;
+            }
+            room.settings = Set(settings);
+        }
+
+        let room = room.update(&self.db).await?;
+        tracing::info!("User {} updated room {}", access.user_id, room.id);
+        Ok(room)
+    }
+
+    /// Resize an uploaded image into the room's icon; needs `manage-room`
+    pub async fn set_icon(&self, access: &RoomAccess, upload: Vec<u8>) -> Result<room::Model> {
+        access.require(Permission::ManageRoom)?;
+
+        let (content_type, data) = tokio::task::spawn_blocking(move || {
+            process_square_image(&upload, ROOM_ICON_SIZE, "Icon")
+        })
+        .await
+        .map_err(|_| AppError::InternalServerError)??;
+
+        let now = Utc::now().naive_utc();
+        let txn = self.db.begin().await?;
+
+        RoomIcon::insert(room_icon::ActiveModel {
+            room_id: Set(access.room.id),
+            content_type: Set(content_type.to_string()),
+            data: Set(data),
+            updated_at: Set(now),
+        })
+        .on_conflict(
+            OnConflict::column(room_icon::Column::RoomId)
+                .update_columns([
+                    room_icon::Column::ContentType,
+                    room_icon::Column::Data,
+                    room_icon::Column::UpdatedAt,
+                ])
+                .to_owned(),
+        )
+        .exec(&txn)
+        .await?;
+
+        let mut room: room::ActiveModel = access.room.clone().into();
+        room.icon_updated_at = Set(Some(now));
+        let room = room.update(&txn).await?;
+
+        txn.commit().await?;
+        Ok(room)
+    }
+
+    /// Remove the room's icon; needs `manage-room`. Returns the room if it had one.
+    pub async fn delete_icon(&self, access: &RoomAccess) -> Result<Option<room::Model>> {
+        access.require(Permission::ManageRoom)?;
+
+        if access.room.icon_updated_at.is_none() {
+            return Ok(None);
+        }
+
+        let txn = self.db.begin().await?;
+
+        RoomIcon::delete_by_id(access.room.id).exec(&txn).await?;
+
+        let mut room: room::ActiveModel = access.room.clone().into();
+        room.icon_updated_at = Set(None);
+        let room = room.update(&txn).await?;
+
+        txn.commit().await?;
+        Ok(Some(room))
+    }
+
+    /// The room's icon, with the room it belongs to
+    pub async fn icon(&self, room_id: i32) -> Result<Option<(room_icon::Model, room::Model)>> {
+        Ok(RoomIcon::find_by_id(room_id)
+            .find_also_related(Room)
+            .one(&self.db)
+            .await?
+            .and_then(|(icon, room)| Some((icon, room?))))
+    }
+
+    /// Members of the room with their profiles, highest roles first
+    pub async fn members(&self, room_id: i32) -> Result<Vec<(room_member::Model, user::Model)>> {
+        let mut members: Vec<_> = RoomMember::find()
+            .filter(room_member::Column::RoomId.eq(room_id))
+            .find_also_related(User)
+            .order_by_asc(room_member::Column::JoinedAt)
+            .all(&self.db)
+            .await?
+            .into_iter()
+            .filter_map(|(member, user)| Some((member, user?)))
+            .collect();
+
+        members.sort_by_key(|(member, _)| std::cmp::Reverse(member.role.rank()));
+        Ok(members)
+    }
+
+    /// Become a member of the room; joining twice keeps the existing membership
+    pub async fn join(&self, access: &RoomAccess) -> Result<room_member::Model> {
+        self.add(access.room.id, access.user_id).await
+    }
+
+    pub async fn leave(&self, access: &RoomAccess) -> Result<()> {
+        if access.room.kind == RoomKind::Dm {
+            return Err(AppError::BadRequest(
+                "Direct messages cannot be left".to_string(),
+            ));
+        }
+        if access.member_role == Some(RoomRole::Owner) {
+            return Err(AppError::BadRequest(
+                "Transfer ownership of the room before leaving it".to_string(),
+            ));
+        }
+
+        RoomMember::delete_many()
+            .filter(room_member::Column::RoomId.eq(access.room.id))
+            .filter(room_member::Column::UserId.eq(access.user_id))
+            .exec(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    /// Add `user_id` to the room as a member; needs `invite`
+    pub async fn add_member(
+        &self,
+        access: &RoomAccess,
+        user_id: i32,
+    ) -> Result<room_member::Model> {
+        access.require(Permission::Invite)?;
+
+        match self.member(access.room.id, user_id).await? {
+            Some(member) => Ok(member),
+            None => self.admit(access, user_id).await,
+        }
+    }
+
+    /// Give `user_id` a new role, adding them to the room if needed. Needs `manage-room`, and
+    /// the caller must outrank both the member's current and new role.
+    pub async fn set_role(
+        &self,
+        access: &RoomAccess,
+        user_id: i32,
+        role: RoomRole,
+    ) -> Result<room_member::Model> {
+        access.require(Permission::ManageRoom)?;
+
+        if role == RoomRole::Owner {
+            return Err(AppError::BadRequest(
+                "Use ownership transfer to change the owner".to_string(),
+            ));
+        }
+
+        let current = self.member(access.room.id, user_id).await?;
+        access.require_outranks(current.as_ref().map_or(RoomRole::Member, |m| m.role))?;
+        access.require_outranks(role)?;
+
+        let member = match current {
+            Some(member) => member,
+            None => self.admit(access, user_id).await?,
+        };
+
+        let mut member: room_member::ActiveModel = member.into();
+        member.role = Set(role);
+        Ok(member.update(&self.db).await?)
+    }
+
+    /// Remove `user_id` from the room; needs `ban` and a higher role than theirs
+    pub async fn remove_member(&self, access: &RoomAccess, user_id: i32) -> Result<()> {
+        access.require(Permission::Ban)?;
+
+        let Some(member) = self.member(access.room.id, user_id).await? else {
+            return Ok(());
+        };
+        access.require_outranks(member.role)?;
+
+        RoomMember::delete_by_id(member.id).exec(&self.db).await?;
+        Ok(())
+    }
+
+    /// Remove `user_id` from the room and keep them out; needs `ban` and a higher role
+    pub async fn ban_member(
+        &self,
+        access: &RoomAccess,
+        user_id: i32,
+        reason: Option<String>,
+    ) -> Result<room_ban::Model> {
+        access.require(Permission::Ban)?;
+        self.require_user(user_id).await?;
+
+        let member = self.member(access.room.id, user_id).await?;
+        access.require_outranks(member.as_ref().map_or(RoomRole::Member, |m| m.role))?;
+
+        let txn = self.db.begin().await?;
+
+        if let Some(member) = member {
+            RoomMember::delete_by_id(member.id).exec(&txn).await?;
+        }
+        RoomInvitation::delete_many()
+            .filter(room_invitation::Column::RoomId.eq(access.room.id))
+            .filter(room_invitation::Column::UserId.eq(user_id))
+            .exec(&txn)
+            .await?;
+        RoomJoinRequest::delete_many()
+            .filter(room_join_request::Column::RoomId.eq(access.room.id))
+            .filter(room_join_request::Column::UserId.eq(user_id))
+            .exec(&txn)
+            .await?;
+
+        let reason = reason
+            .map(|reason| reason.trim().to_string())
+            .filter(|reason| !reason.is_empty());
+        RoomBan::insert(room_ban::ActiveModel {
+            room_id: Set(access.room.id),
+            user_id: Set(user_id),
+            banned_by: Set(Some(access.user_id)),
+            reason: Set(reason),
+            created_at: Set(Utc::now().naive_utc()),
+            ..Default::default()
+        })
+        .on_conflict(
+            OnConflict::columns([room_ban::Column::RoomId, room_ban::Column::UserId])
+                .update_columns([
+                    room_ban::Column::BannedBy,
+                    room_ban::Column::Reason,
+                    room_ban::Column::CreatedAt,
+                ])
+                .to_owned(),
+        )
+        .exec_without_returning(&txn)
+        .await?;
+
+        txn.commit().await?;
+
+        tracing::info!(
+            "User {} banned user {} from room {}",
+            access.user_id,
+            user_id,
+            access.room.id
+        );
+
+        self.ban(access.room.id, user_id)
+            .await?
+            .ok_or(AppError::InternalServerError)
+    }
+
+    pub async fn unban_member(&self, access: &RoomAccess, user_id: i32) -> Result<()> {
+        access.require(Permission::Ban)?;
+
+        RoomBan::delete_many()
+            .filter(room_ban::Column::RoomId.eq(access.room.id))
+            .filter(room_ban::Column::UserId.eq(user_id))
+            .exec(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn bans(&self, access: &RoomAccess) -> Result<Vec<room_ban::Model>> {
+        access.require(Permission::Ban)?;
+
+        Ok(RoomBan::find()
+            .filter(room_ban::Column::RoomId.eq(access.room.id))
+            .order_by_desc(room_ban::Column::CreatedAt)
+            .all(&self.db)
+            .await?)
+    }
+
+    /// Make `user_id` the owner. The previous owner, if any, becomes an admin. Only the owner
+    /// (or a server admin) may.
+    pub async fn transfer_ownership(
+        &self,
+        access: &RoomAccess,
+        user_id: i32,
+    ) -> Result<room_member::Model> {
+        if access.role() != RoomRole::Owner {
+            return Err(AppError::Forbidden(
+                "Only the owner can transfer a room".to_string(),
+            ));
+        }
+
+        let user = self.require_user(user_id).await?;
+        if user.is_bot {
+            return Err(AppError::BadRequest("Bots cannot own rooms".to_string()));
+        }
+        if self.ban(access.room.id, user_id).await?.is_some() {
+            return Err(AppError::BadRequest(
+                "That user is banned from this room".to_string(),
+            ));
+        }
+
+        let txn = self.db.begin().await?;
+
+        // Demote first: at most one owner per room is enforced by a unique index
+        RoomMember::update_many()
+            .col_expr(room_member::Column::Role, Expr::value(RoomRole::Admin))
+            .filter(room_member::Column::RoomId.eq(access.room.id))
+            .filter(room_member::Column::Role.eq(RoomRole::Owner))
+            .filter(room_member::Column::UserId.ne(user_id))
+            .exec(&txn)
+            .await?;
+
+        let owner = set_member_role(&txn, access.room.id, user_id, RoomRole::Owner).await?;
+
+        txn.commit().await?;
+
+        tracing::info!(
+            "User {} transferred room {} to user {}",
+            access.user_id,
+            access.room.id,
+            user_id
+        );
+        Ok(owner)
+    }
+
+    /// Delete a message; authors may always delete their own, others (and system messages) need
+    /// `delete-others`
+    pub async fn delete_message(&self, access: &RoomAccess, message_id: i32) -> Result<()> {
+        let Some(message) = Message::find_by_id(message_id)
+            .filter(message::Column::RoomId.eq(access.room.id))
+            .filter(not_expired())
+            .one(&self.db)
+            .await?
+        else {
+            return Ok(());
+        };
+
+        if message.sender_id != access.user_id || message.kind == MessageKind::System {
+            access.require(Permission::DeleteOthers)?;
+        }
+
+        Message::delete_by_id(message.id).exec(&self.db).await?;
+        Ok(())
+    }
+
+    /// Members whose role has `permission` in the room
+    pub async fn members_with(&self, room_id: i32, permission: Permission) -> Result<Vec<i32>> {
+        let overrides = self.overrides(room_id).await?;
+        let roles: Vec<RoomRole> = RoomRole::iter()
+            .filter(|role| apply_overrides(*role, &overrides).contains(&permission))
+            .collect();
+
+        Ok(RoomMember::find()
+            .select_only()
+            .column(room_member::Column::UserId)
+            .filter(room_member::Column::RoomId.eq(room_id))
+            .filter(room_member::Column::Role.is_in(roles))
+            .into_tuple()
+            .all(&self.db)
+            .await?)
+    }
+
+    /// Effective permissions of every role in the room, with the overrides behind them
+    pub async fn permissions(&self, room_id: i32) -> Result<RoomPermissionsResponse> {
+        let overrides = self.overrides(room_id).await?;
+
+        let roles = RoomRole::iter()
+            .map(|role| (role, apply_overrides(role, &overrides)))
+            .collect::<BTreeMap<_, _>>();
+
+        Ok(RoomPermissionsResponse {
+            roles,
+            overrides: overrides.into_iter().map(Into::into).collect(),
+        })
+    }
+
+    /// Grant or deny `permission` to `role` in this room; needs `manage-room` and a higher role
+    pub async fn set_override(
+        &self,
+        access: &RoomAccess,
+        override_: PermissionOverride,
+    ) -> Result<RoomPermissionsResponse> {
+        access.require(Permission::ManageRoom)?;
+        access.require_outranks(override_.role)?;
+
+        RoomPermission::insert(room_permission::ActiveModel {
+            room_id: Set(access.room.id),
+            role: Set(override_.role),
+            permission: Set(override_.permission),
+            allowed: Set(override_.allowed),
+            ..Default::default()
+        })
+        .on_conflict(
+            OnConflict::columns([
+                room_permission::Column::RoomId,
+                room_permission::Column::Role,
+                room_permission::Column::Permission,
+            ])
+            .update_column(room_permission::Column::Allowed)
+            .to_owned(),
+        )
+        .exec_without_returning(&self.db)
+        .await?;
+
+        self.permissions(access.room.id).await
+    }
+
+    /// Return `role` to the default for `permission`
+    pub async fn clear_override(
+        &self,
+        access: &RoomAccess,
+        role: RoomRole,
+        permission: Permission,
+    ) -> Result<RoomPermissionsResponse> {
+        access.require(Permission::ManageRoom)?;
+        access.require_outranks(role)?;
+
+        RoomPermission::delete_many()
+            .filter(room_permission::Column::RoomId.eq(access.room.id))
+            .filter(room_permission::Column::Role.eq(role))
+            .filter(room_permission::Column::Permission.eq(permission))
+            .exec(&self.db)
+            .await?;
+
+        self.permissions(access.room.id).await
+    }
+
+    async fn role_permissions(&self, room_id: i32, role: RoomRole) -> Result<BTreeSet<Permission>> {
+        if role == RoomRole::Owner {
+            return Ok(Permission::defaults(role));
+        }
+
+        let overrides = RoomPermission::find()
+            .filter(room_permission::Column::RoomId.eq(room_id))
+            .filter(room_permission::Column::Role.eq(role))
+            .all(&self.db)
+            .await?;
+        Ok(apply_overrides(role, &overrides))
+    }
+
+    async fn overrides(&self, room_id: i32) -> Result<Vec<room_permission::Model>> {
+        Ok(RoomPermission::find()
+            .filter(room_permission::Column::RoomId.eq(room_id))
+            .order_by_asc(room_permission::Column::Id)
+            .all(&self.db)
+            .await?)
+    }
+
+    pub async fn member(&self, room_id: i32, user_id: i32) -> Result<Option<room_member::Model>> {
+        Ok(RoomMember::find()
+            .filter(room_member::Column::RoomId.eq(room_id))
+            .filter(room_member::Column::UserId.eq(user_id))
+            .one(&self.db)
+            .await?)
+    }
+
+    /// The ban keeping `user_id` out of the room, if any
+    pub async fn ban(&self, room_id: i32, user_id: i32) -> Result<Option<room_ban::Model>> {
+        Ok(RoomBan::find()
+            .filter(room_ban::Column::RoomId.eq(room_id))
+            .filter(room_ban::Column::UserId.eq(user_id))
+            .one(&self.db)
+            .await?)
+    }
+
+    /// Add someone who is not in the room yet, as a member
+    async fn admit(&self, access: &RoomAccess, user_id: i32) -> Result<room_member::Model> {
+        self.require_user(user_id).await?;
+        self.check_admission(&access.room, user_id).await?;
+
+        if access.room.kind == RoomKind::GroupDm
+            && self.blocked_between(access.user_id, user_id).await?
+        {
+            return Err(AppError::Forbidden(
+                "You cannot message this user".to_string(),
+            ));
+        }
+
+        self.add(access.room.id, user_id).await
+    }
+
+    /// Check that `user_id` may become a member of `room`: they are not banned from it, and it
+    /// has room for another participant
+    pub async fn check_admission(&self, room: &room::Model, user_id: i32) -> Result<()> {
+        if self.ban(room.id, user_id).await?.is_some() {
+            return Err(AppError::BadRequest(
+                "That user is banned from this room".to_string(),
+            ));
+        }
+
+        match room.kind {
+            RoomKind::Channel => Ok(()),
+            RoomKind::Dm => Err(AppError::BadRequest(
+                "Start a group DM to talk with more people".to_string(),
+            )),
+            RoomKind::GroupDm => {
+                let participants = RoomMember::find()
+                    .filter(room_member::Column::RoomId.eq(room.id))
+                    .count(&self.db)
+                    .await?;
+                if participants >= self.max_group_dm_participants as u64 {
+                    return Err(AppError::BadRequest(format!(
+                        "Group DMs are limited to {} people",
+                        self.max_group_dm_participants
+                    )));
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Whether either user has blocked the other
+    pub async fn blocked_between(&self, user_id: i32, other_id: i32) -> Result<bool> {
+        let blocks = UserBlock::find()
+            .filter(user_block::Column::Kind.eq(BlockKind::Block))
+            .filter(
+                Condition::any()
+                    .add(
+                        Condition::all()
+                            .add(user_block::Column::UserId.eq(user_id))
+                            .add(user_block::Column::TargetId.eq(other_id)),
+                    )
+                    .add(
+                        Condition::all()
+                            .add(user_block::Column::UserId.eq(other_id))
+                            .add(user_block::Column::TargetId.eq(user_id)),
+                    ),
+            )
+            .count(&self.db)
+            .await?;
+        Ok(blocks > 0)
+    }
+
+    /// An account that can be added to rooms
+    pub async fn require_user(&self, user_id: i32) -> Result<user::Model> {
+        User::find_by_id(user_id)
+            .filter(user::Column::DeletedAt.is_null())
+            .one(&self.db)
+            .await?
+            .ok_or(AppError::UserNotFound)
+    }
+
+    /// Add `user_id` as a member; an existing membership is kept as it is
+    pub async fn add(&self, room_id: i32, user_id: i32) -> Result<room_member::Model> {
+        RoomMember::insert(room_member::ActiveModel {
+            room_id: Set(room_id),
+            user_id: Set(user_id),
+            role: Set(RoomRole::Member),
+            joined_at: Set(Utc::now().naive_utc()),
+            ..Default::default()
+        })
+        .on_conflict(
+            OnConflict::columns([room_member::Column::RoomId, room_member::Column::UserId])
+                .do_nothing()
+                .to_owned(),
+        )
+        .exec_without_returning(&self.db)
+        .await?;
+
+        self.member(room_id, user_id)
+            .await?
+            .ok_or(AppError::InternalServerError)
+    }
+}
+
+/// The 1:1 DM between two users, found through their memberships
+async fn find_dm<C: ConnectionTrait>(
+    db: &C,
+    user_id: i32,
+    other_id: i32,
+) -> Result<Option<room::Model>> {
+    let shared: Vec<i32> = RoomMember::find()
+        .select_only()
+        .column(room_member::Column::RoomId)
+        .inner_join(Room)
+        .filter(room::Column::Kind.eq(RoomKind::Dm))
+        .filter(room_member::Column::UserId.is_in([user_id, other_id]))
+        .group_by(room_member::Column::RoomId)
+        .having(Expr::expr(Expr::col(room_member::Column::UserId).count()).eq(2))
+        .into_tuple()
+        .all(db)
+        .await?;
+
+    let Some(room_id) = shared.into_iter().min() else {
+        return Ok(None);
+    };
+    Ok(Room::find_by_id(room_id).one(db).await?)
+}
+
+fn apply_overrides(role: RoomRole, overrides: &[room_permission::Model]) -> BTreeSet<Permission> {
+    let mut permissions = Permission::defaults(role);
+    if role == RoomRole::Owner {
+        return permissions;
+    }
+
+    for entry in overrides.iter().filter(|entry| entry.role == role) {
+        if entry.allowed {
+            permissions.insert(entry.permission);
+        } else {
+            permissions.remove(&entry.permission);
+        }
+    }
+    permissions
+}
+
+/// Set the role of `user_id`, adding them to the room if needed
+pub async fn set_member_role<C: ConnectionTrait>(
+    db: &C,
+    room_id: i32,
+    user_id: i32,
+    role: RoomRole,
+) -> Result<room_member::Model> {
+    RoomMember::insert(room_member::ActiveModel {
+        room_id: Set(room_id),
+        user_id: Set(user_id),
+        role: Set(role),
+        joined_at: Set(Utc::now().naive_utc()),
+        ..Default::default()
+    })
+    .on_conflict(
+        OnConflict::columns([room_member::Column::RoomId, room_member::Column::UserId])
+            .update_column(room_member::Column::Role)
+            .to_owned(),
+    )
+    .exec_without_returning(db)
+    .await?;
+
+    RoomMember::find()
+        .filter(room_member::Column::RoomId.eq(room_id))
+        .filter(room_member::Column::UserId.eq(user_id))
+        .one(db)
+        .await?
+        .ok_or(AppError::InternalServerError)
+}
+
+/// Hand the rooms owned by `user_id` to their highest-ranking, longest-standing other member
+/// before the account goes away. Rooms with nobody to take over stay with the (deleted) owner
+/// until a server admin transfers them.
+pub async fn hand_over_rooms<C: ConnectionTrait>(db: &C, user_id: i32) -> Result<()> {
+    let owned = RoomMember::find()
+        .filter(room_member::Column::UserId.eq(user_id))
+        .filter(room_member::Column::Role.eq(RoomRole::Owner))
+        .all(db)
+        .await?;
+
+    for ownership in owned {
+        let mut candidates = RoomMember::find()
+            .filter(room_member::Column::RoomId.eq(ownership.room_id))
+            .filter(room_member::Column::UserId.ne(user_id))
+            .filter(room_member::Column::Role.ne(RoomRole::ReadOnly))
+            .find_also_related(User)
+            .order_by_asc(room_member::Column::JoinedAt)
+            .all(db)
+            .await?
+            .into_iter()
+            .filter(|(_, user)| {
+                user.as_ref()
+                    .is_some_and(|user| !user.is_bot && user.deleted_at.is_none())
+            })
+            .map(|(member, _)| member)
+            .collect::<Vec<_>>();
+        candidates.sort_by_key(|member| std::cmp::Reverse(member.role.rank()));
+
+        let Some(successor) = candidates.into_iter().next() else {
+            tracing::warn!(
+                "Room {} has nobody to take over from deleted owner {}",
+                ownership.room_id,
+                user_id
+            );
+            continue;
+        };
+
+        RoomMember::delete_by_id(ownership.id).exec(db).await?;
+        set_member_role(db, ownership.room_id, successor.user_id, RoomRole::Owner).await?;
+
+        tracing::info!(
+            "Room {} passed from deleted user {} to user {}",
+            ownership.room_id,
+            user_id,
+            successor.user_id
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,164 @@
+use crate::errors::Result;
+use crate::models::session::{self, Entity as Session};
+use crate::utils::ClientInfo;
+use chrono::{Duration, NaiveDateTime, Utc};
+use sea_orm::{
+    sea_query::Expr, ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter,
+    QueryOrder, Set,
+};
+use uuid::Uuid;
+
+/// Longest user agent kept; anything longer is truncated
+const MAX_USER_AGENT_LEN: usize = 512;
+
+/// Records where users are signed in. A session lives as long as its refresh token family.
+#[derive(Clone)]
+pub struct SessionService {
+    db: DatabaseConnection,
+    ttl_days: i64,
+}
+
+impl SessionService {
+    /// `ttl_days` is the refresh token lifetime; sessions idle for longer can no longer refresh
+    pub fn new(db: DatabaseConnection, ttl_days: i64) -> Self {
+        Self { db, ttl_days }
+    }
+
+    /// Record a new login from `client`, returning the session id
+    pub async fn start(&self, user_id: i32, client: &ClientInfo) -> Result<Uuid> {
+        let now = Utc::now().naive_utc();
+        let user_agent = client
+            .user_agent
+            .as_deref()
+            .map(|ua| ua.chars().take(MAX_USER_AGENT_LEN).collect::<String>());
+
+        let session = session::ActiveModel {
+            id: Set(Uuid::new_v4()),
+            user_id: Set(user_id),
+            device_label: Set(device_label(user_agent.as_deref())),
+            user_agent: Set(user_agent),
+            ip_address: Set(client.ip_address.map(|ip| ip.to_string())),
+            created_at: Set(now),
+            last_used_at: Set(now),
+        }
+        .insert(&self.db)
+        .await?;
+
+        // Sessions that can no longer refresh are only clutter
+        Session::delete_many()
+            .filter(session::Column::UserId.eq(user_id))
+            .filter(session::Column::LastUsedAt.lt(self.cutoff()))
+            .exec(&self.db)
+            .await?;
+
+        Ok(session.id)
+    }
+
+    /// Whether the session `id` of `user_id` began after `since`
+    pub async fn started_after(&self, user_id: i32, id: Uuid, since: NaiveDateTime) -> Result<bool> {
+        let session = Session::find_by_id(id)
+            .filter(session::Column::UserId.eq(user_id))
+            .one(&self.db)
+            .await?;
+
+        Ok(session.is_some_and(|session| session.created_at > since))
+    }
+
+    /// Mark a session as used from `client`. Returns false if it no longer exists.
+    pub async fn touch(&self, id: Uuid, client: &ClientInfo) -> Result<bool> {
+        let mut update = Session::update_many()
+            .col_expr(
+                session::Column::LastUsedAt,
+                Expr::value(Utc::now().naive_utc()),
+            )
+            .filter(session::Column::Id.eq(id));
+
+        if let Some(ip) = client.ip_address {
+            update = update.col_expr(session::Column::IpAddress, Expr::value(ip.to_string()));
+        }
+
+        Ok(update.exec(&self.db).await?.rows_affected > 0)
+    }
+
+    /// List the live sessions of `user_id`, most recently used first
+    pub async fn list(&self, user_id: i32) -> Result<Vec<session::Model>> {
+        let sessions = Session::find()
+            .filter(session::Column::UserId.eq(user_id))
+            .filter(session::Column::LastUsedAt.gte(self.cutoff()))
+            .order_by_desc(session::Column::LastUsedAt)
+            .all(&self.db)
+            .await?;
+
+        Ok(sessions)
+    }
+
+    /// Delete a session of `user_id`, returning whether it existed
+    pub async fn delete(&self, user_id: i32, id: Uuid) -> Result<bool> {
+        let deleted = Session::delete_many()
+            .filter(session::Column::Id.eq(id))
+            .filter(session::Column::UserId.eq(user_id))
+            .exec(&self.db)
+            .await?;
+
+        Ok(deleted.rows_affected > 0)
+    }
+
+    /// Delete every session of `user_id`
+    pub async fn delete_all(&self, user_id: i32) -> Result<()> {
+        Session::delete_many()
+            .filter(session::Column::UserId.eq(user_id))
+            .exec(&self.db)
+            .await?;
+
+        Ok(())
+    }
+
+    fn cutoff(&self) -> chrono::NaiveDateTime {
+        Utc::now().naive_utc() - Duration::days(self.ttl_days)
+    }
+}
+
+/// Describe a device from its user agent, e.g. "Firefox on Linux"
+fn device_label(user_agent: Option<&str>) -> String {
+    let Some(ua) = user_agent.filter(|ua| !ua.trim().is_empty()) else {
+        return "Unknown device".to_string();
+    };
+
+    // Order matters: most browsers also claim to be the ones listed after them
+    let browser = [
+        ("Edg/", "Edge"),
+        ("OPR/", "Opera"),
+        ("Firefox/", "Firefox"),
+        ("Chrome/", "Chrome"),
+        ("Safari/", "Safari"),
+    ]
+    .into_iter()
+    .find(|(token, _)| ua.contains(token))
+    .map(|(_, name)| name);
+
+    let os = [
+        ("Android", "Android"),
+        ("iPhone", "iOS"),
+        ("iPad", "iPadOS"),
+        ("Windows", "Windows"),
+        ("Mac OS X", "macOS"),
+        ("CrOS", "ChromeOS"),
+        ("Linux", "Linux"),
+    ]
+    .into_iter()
+    .find(|(token, _)| ua.contains(token))
+    .map(|(_, name)| name);
+
+    match (browser, os) {
+        (Some(browser), Some(os)) => format!("{} on {}", browser, os),
+        (Some(name), None) | (None, Some(name)) => name.to_string(),
+        // Not a browser: use the product name, e.g. "curl" from "curl/8.5.0"
+        (None, None) => ua
+            .split(['/', ' '])
+            .next()
+            .unwrap_or(ua)
+            .chars()
+            .take(100)
+            .collect(),
+    }
+}
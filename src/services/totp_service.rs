@@ -0,0 +1,280 @@
+use crate::errors::{AppError, Result};
+use crate::models::recovery_code::{self, Entity as RecoveryCode};
+use crate::models::user_totp::{self, Entity as UserTotp, TotpEnrollmentResponse};
+use crate::utils::hash_token;
+use chrono::Utc;
+use qrcode::{render::svg, QrCode};
+use rand::{rngs::OsRng, Rng, RngCore};
+use sea_orm::{
+    sea_query::{Expr, OnConflict},
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait, QueryFilter,
+    Set, TransactionTrait,
+};
+use totp_rs::{Algorithm, Secret, TOTP};
+
+/// Length of the shared secret in bytes (160 bits, as recommended by RFC 4226)
+const SECRET_BYTES: usize = 20;
+const CODE_DIGITS: usize = 6;
+const STEP_SECONDS: u64 = 30;
+/// Accept codes from one step either side of now to tolerate clock drift
+const ALLOWED_SKEW_STEPS: i64 = 1;
+
+const RECOVERY_CODE_COUNT: usize = 10;
+/// Unambiguous lowercase alphabet for recovery codes (no 0/o, 1/l/i)
+const RECOVERY_CODE_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+/// Time-based one-time password second factor (RFC 6238) with recovery codes
+#[derive(Clone)]
+pub struct TotpService {
+    db: DatabaseConnection,
+    issuer: String,
+}
+
+impl TotpService {
+    pub fn new(db: DatabaseConnection, issuer: &str) -> Self {
+        Self {
+            db,
+            issuer: issuer.replace(':', ""),
+        }
+    }
+
+    /// Whether `user_id` has confirmed two-factor authentication
+    pub async fn is_enabled(&self, user_id: i32) -> Result<bool> {
+        let totp = UserTotp::find_by_id(user_id).one(&self.db).await?;
+        Ok(totp.is_some_and(|totp| totp.enabled_at.is_some()))
+    }
+
+    /// Start (or restart) enrollment with a fresh secret. Not allowed once 2FA is enabled.
+    pub async fn begin_enrollment(
+        &self,
+        user_id: i32,
+        account_name: &str,
+    ) -> Result<TotpEnrollmentResponse> {
+        if self.is_enabled(user_id).await? {
+            return Err(AppError::BadRequest(
+                "Two-factor authentication is already enabled".to_string(),
+            ));
+        }
+
+        let mut secret = vec![0u8; SECRET_BYTES];
+        OsRng.fill_bytes(&mut secret);
+        let encoded = Secret::Raw(secret).to_encoded().to_string();
+
+        let pending = user_totp::ActiveModel {
+            user_id: Set(user_id),
+            secret: Set(encoded.clone()),
+            enabled_at: Set(None),
+            last_used_step: Set(None),
+            created_at: Set(Utc::now().naive_utc()),
+        };
+
+        UserTotp::insert(pending)
+            .on_conflict(
+                OnConflict::column(user_totp::Column::UserId)
+                    .update_columns([
+                        user_totp::Column::Secret,
+                        user_totp::Column::EnabledAt,
+                        user_totp::Column::LastUsedStep,
+                        user_totp::Column::CreatedAt,
+                    ])
+                    .to_owned(),
+            )
+            .exec_without_returning(&self.db)
+            .await?;
+
+        let otpauth_uri = self.totp(&encoded, account_name)?.get_url();
+        let qr_svg = QrCode::new(otpauth_uri.as_bytes())
+            .map_err(|e| {
+                tracing::error!("Failed to render TOTP QR code: {}", e);
+                AppError::InternalServerError
+            })?
+            .render::<svg::Color>()
+            .min_dimensions(200, 200)
+            .build();
+
+        Ok(TotpEnrollmentResponse {
+            secret: encoded,
+            otpauth_uri,
+            qr_svg,
+        })
+    }
+
+    /// Finish enrollment by checking a code from the authenticator.
+    /// Returns freshly generated recovery codes, which are shown to the user only once.
+    pub async fn confirm_enrollment(&self, user_id: i32, code: &str) -> Result<Vec<String>> {
+        let totp = UserTotp::find_by_id(user_id)
+            .one(&self.db)
+            .await?
+            .ok_or_else(|| {
+                AppError::BadRequest("Two-factor enrollment has not been started".to_string())
+            })?;
+
+        if totp.enabled_at.is_some() {
+            return Err(AppError::BadRequest(
+                "Two-factor authentication is already enabled".to_string(),
+            ));
+        }
+
+        let step = self.matching_step(&totp, code)?;
+
+        let txn = self.db.begin().await?;
+
+        let mut active: user_totp::ActiveModel = totp.into();
+        active.enabled_at = Set(Some(Utc::now().naive_utc()));
+        active.last_used_step = Set(Some(step));
+        active.update(&txn).await?;
+
+        let codes = self.replace_recovery_codes(&txn, user_id).await?;
+
+        txn.commit().await?;
+
+        Ok(codes)
+    }
+
+    /// Check a second-factor code: a current TOTP code or an unused recovery code
+    pub async fn verify(&self, user_id: i32, code: &str) -> Result<()> {
+        let totp = UserTotp::find_by_id(user_id)
+            .one(&self.db)
+            .await?
+            .filter(|totp| totp.enabled_at.is_some())
+            .ok_or(AppError::InvalidTwoFactorCode)?;
+
+        if let Ok(step) = self.matching_step(&totp, code) {
+            // Claim the step atomically so the same code cannot be used twice
+            let claimed = UserTotp::update_many()
+                .col_expr(user_totp::Column::LastUsedStep, Expr::value(step))
+                .filter(user_totp::Column::UserId.eq(user_id))
+                .filter(
+                    user_totp::Column::LastUsedStep
+                        .is_null()
+                        .or(user_totp::Column::LastUsedStep.lt(step)),
+                )
+                .exec(&self.db)
+                .await?;
+
+            return if claimed.rows_affected == 1 {
+                Ok(())
+            } else {
+                Err(AppError::InvalidTwoFactorCode)
+            };
+        }
+
+        self.consume_recovery_code(user_id, code).await
+    }
+
+    /// Remove the second factor and all recovery codes
+    pub async fn disable(&self, user_id: i32) -> Result<()> {
+        let txn = self.db.begin().await?;
+
+        UserTotp::delete_by_id(user_id).exec(&txn).await?;
+        RecoveryCode::delete_many()
+            .filter(recovery_code::Column::UserId.eq(user_id))
+            .exec(&txn)
+            .await?;
+
+        txn.commit().await?;
+        Ok(())
+    }
+
+    fn totp(&self, encoded_secret: &str, account_name: &str) -> Result<TOTP> {
+        let secret = Secret::Encoded(encoded_secret.to_string())
+            .to_bytes()
+            .map_err(|_| AppError::InternalServerError)?;
+
+        Ok(TOTP::new_unchecked(
+            Algorithm::SHA1,
+            CODE_DIGITS,
+            0,
+            STEP_SECONDS,
+            secret,
+            Some(self.issuer.clone()),
+            account_name.to_string(),
+        ))
+    }
+
+    /// Find the time step within the allowed skew that produces `code`
+    fn matching_step(&self, totp: &user_totp::Model, code: &str) -> Result<i64> {
+        let code = code.trim();
+        if code.len() != CODE_DIGITS || !code.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(AppError::InvalidTwoFactorCode);
+        }
+
+        let generator = self.totp(&totp.secret, "")?;
+        let current_step = Utc::now().timestamp() / STEP_SECONDS as i64;
+
+        (-ALLOWED_SKEW_STEPS..=ALLOWED_SKEW_STEPS)
+            .map(|offset| current_step + offset)
+            .filter(|step| totp.last_used_step.is_none_or(|last| *step > last))
+            .find(|step| {
+                let expected = generator.generate(*step as u64 * STEP_SECONDS);
+                constant_time_eq(expected.as_bytes(), code.as_bytes())
+            })
+            .ok_or(AppError::InvalidTwoFactorCode)
+    }
+
+    async fn consume_recovery_code(&self, user_id: i32, code: &str) -> Result<()> {
+        let used = RecoveryCode::update_many()
+            .col_expr(recovery_code::Column::UsedAt, Expr::value(Utc::now().naive_utc()))
+            .filter(recovery_code::Column::UserId.eq(user_id))
+            .filter(recovery_code::Column::CodeHash.eq(hash_token(&normalize_recovery_code(code))))
+            .filter(recovery_code::Column::UsedAt.is_null())
+            .exec(&self.db)
+            .await?;
+
+        if used.rows_affected == 1 {
+            tracing::info!("Recovery code used by user {}", user_id);
+            Ok(())
+        } else {
+            Err(AppError::InvalidTwoFactorCode)
+        }
+    }
+
+    async fn replace_recovery_codes<C: ConnectionTrait>(
+        &self,
+        conn: &C,
+        user_id: i32,
+    ) -> Result<Vec<String>> {
+        RecoveryCode::delete_many()
+            .filter(recovery_code::Column::UserId.eq(user_id))
+            .exec(conn)
+            .await?;
+
+        let now = Utc::now().naive_utc();
+        let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+            .map(|_| generate_recovery_code())
+            .collect();
+
+        let rows = codes.iter().map(|code| recovery_code::ActiveModel {
+            user_id: Set(user_id),
+            code_hash: Set(hash_token(&normalize_recovery_code(code))),
+            created_at: Set(now),
+            ..Default::default()
+        });
+
+        RecoveryCode::insert_many(rows)
+            .exec_without_returning(conn)
+            .await?;
+
+        Ok(codes)
+    }
+}
+
+/// A code like `k7m2p-x9qrt` (about 49 bits of entropy)
+fn generate_recovery_code() -> String {
+    let mut rng = OsRng;
+    let mut pick = || RECOVERY_CODE_ALPHABET[rng.gen_range(0..RECOVERY_CODE_ALPHABET.len())] as char;
+    let first: String = (0..5).map(|_| pick()).collect();
+    let second: String = (0..5).map(|_| pick()).collect();
+    format!("{}-{}", first, second)
+}
+
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
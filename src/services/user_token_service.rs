@@ -0,0 +1,127 @@
+use crate::errors::{AppError, Result};
+use crate::models::user_token::{self, Entity as UserToken, TokenPurpose};
+use crate::utils::{generate_opaque_token, hash_token};
+use chrono::{Duration, Utc};
+use sea_orm::{
+    sea_query::Expr, ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter,
+    Set,
+};
+
+/// Number of random bytes in a single-use token
+const USER_TOKEN_BYTES: usize = 32;
+
+/// Issues and redeems single-use tokens sent to users out of band
+#[derive(Clone)]
+pub struct UserTokenService {
+    db: DatabaseConnection,
+}
+
+impl UserTokenService {
+    pub fn new(db: DatabaseConnection) -> Self {
+        Self { db }
+    }
+
+    /// Issue a token for `purpose`, invalidating any earlier unused ones for the same purpose
+    pub async fn issue(
+        &self,
+        user_id: i32,
+        purpose: TokenPurpose,
+        ttl: Duration,
+    ) -> Result<String> {
+        self.insert(user_id, purpose, ttl, None).await
+    }
+
+    /// Like [`issue`](Self::issue), but the token can only be redeemed together with
+    /// `device_nonce`, which stays on the device that asked for it
+    pub async fn issue_for_device(
+        &self,
+        user_id: i32,
+        purpose: TokenPurpose,
+        ttl: Duration,
+        device_nonce: &str,
+    ) -> Result<String> {
+        self.insert(user_id, purpose, ttl, Some(hash_token(device_nonce)))
+            .await
+    }
+
+    /// Redeem a token, returning the user it was issued to.
+    ///
+    /// The token is marked used in the same statement that checks it, so it can be redeemed
+    /// at most once even under concurrent requests.
+    pub async fn consume(&self, raw: &str, purpose: TokenPurpose) -> Result<i32> {
+        self.redeem(raw, purpose, None).await
+    }
+
+    /// Redeem a token issued with [`issue_for_device`](Self::issue_for_device). A wrong nonce
+    /// leaves the token unused.
+    pub async fn consume_from_device(
+        &self,
+        raw: &str,
+        purpose: TokenPurpose,
+        device_nonce: &str,
+    ) -> Result<i32> {
+        self.redeem(raw, purpose, Some(device_nonce)).await
+    }
+
+    async fn insert(
+        &self,
+        user_id: i32,
+        purpose: TokenPurpose,
+        ttl: Duration,
+        nonce_hash: Option<String>,
+    ) -> Result<String> {
+        let now = Utc::now().naive_utc();
+
+        UserToken::update_many()
+            .col_expr(user_token::Column::UsedAt, Expr::value(now))
+            .filter(user_token::Column::UserId.eq(user_id))
+            .filter(user_token::Column::Purpose.eq(purpose))
+            .filter(user_token::Column::UsedAt.is_null())
+            .exec(&self.db)
+            .await?;
+
+        let raw = generate_opaque_token(USER_TOKEN_BYTES);
+
+        user_token::ActiveModel {
+            user_id: Set(user_id),
+            purpose: Set(purpose),
+            token_hash: Set(hash_token(&raw)),
+            nonce_hash: Set(nonce_hash),
+            expires_at: Set(now + ttl),
+            created_at: Set(now),
+            ..Default::default()
+        }
+        .insert(&self.db)
+        .await?;
+
+        Ok(raw)
+    }
+
+    async fn redeem(
+        &self,
+        raw: &str,
+        purpose: TokenPurpose,
+        device_nonce: Option<&str>,
+    ) -> Result<i32> {
+        let now = Utc::now().naive_utc();
+
+        let mut redeem = UserToken::update_many()
+            .col_expr(user_token::Column::UsedAt, Expr::value(now))
+            .filter(user_token::Column::TokenHash.eq(hash_token(raw)))
+            .filter(user_token::Column::Purpose.eq(purpose))
+            .filter(user_token::Column::UsedAt.is_null())
+            .filter(user_token::Column::ExpiresAt.gt(now));
+
+        if let Some(nonce) = device_nonce {
+            redeem = redeem.filter(user_token::Column::NonceHash.eq(hash_token(nonce)));
+        }
+
+        let redeemed = redeem.exec_with_returning(&self.db).await?;
+
+        redeemed
+            .into_iter()
+            .next()
+            .map(|token| token.user_id)
+            .ok_or(AppError::InvalidToken)
+    }
+}
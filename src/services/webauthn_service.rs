@@ -0,0 +1,563 @@
+use crate::config::Config;
+use crate::errors::{AppError, Result};
+use crate::models::user::{self, Entity as User};
+use crate::models::webauthn_credential::{
+    self, AuthenticationCredential, AuthenticatorSelection, CredentialCreationOptions,
+    CredentialRequestOptions, Entity as WebauthnCredential, PasskeyResponse,
+    PublicKeyCredentialCreationOptions, PublicKeyCredentialDescriptor,
+    PublicKeyCredentialParameters, PublicKeyCredentialRequestOptions, PublicKeyCredentialUser,
+    RegisterPasskeyRequest, RelyingParty,
+};
+use crate::services::pending_store::PendingStore;
+use crate::services::redis_service::{CacheKey, RedisService};
+use crate::utils::generate_opaque_token;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Utc;
+use ciborium::Value;
+use p256::ecdsa::signature::Verifier;
+use sea_orm::{
+    sea_query::Expr, ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter,
+    QueryOrder, Set,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// How long the browser has to complete a ceremony
+const CEREMONY_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+const CHALLENGE_BYTES: usize = 32;
+
+/// COSE algorithm identifiers we can verify, in order of preference
+const COSE_ALG_ES256: i64 = -7;
+const COSE_ALG_RS256: i64 = -257;
+
+/// Authenticator data flags (WebAuthn §6.1)
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL: u8 = 0x40;
+
+/// What a challenge was issued for
+#[derive(Debug, Serialize, Deserialize)]
+enum Ceremony {
+    Registration {
+        user_id: i32,
+    },
+    /// `user_id` is set when the login was started for a specific account
+    Authentication {
+        user_id: Option<i32>,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    kind: String,
+    challenge: String,
+    origin: String,
+    #[serde(rename = "crossOrigin", default)]
+    cross_origin: bool,
+}
+
+/// Passkey registration and login (WebAuthn Level 2, "none" attestation).
+///
+/// Passkeys are required to verify the user (PIN or biometric), so a successful assertion
+/// counts as both factors. Supports ES256 and RS256 credentials.
+pub struct WebAuthnService {
+    db: DatabaseConnection,
+    rp_id: String,
+    rp_name: String,
+    origins: Vec<String>,
+    /// Pending ceremonies by challenge
+    pending: PendingStore<Ceremony>,
+}
+
+impl WebAuthnService {
+    pub fn new(db: DatabaseConnection, redis: Option<Arc<RedisService>>, config: &Config) -> Self {
+        Self {
+            db,
+            rp_id: config.webauthn_rp_id.clone(),
+            rp_name: config.webauthn_rp_name.clone(),
+            origins: config.webauthn_origins.clone(),
+            pending: PendingStore::new(redis, CacheKey::webauthn_challenge, CEREMONY_TIMEOUT),
+        }
+    }
+
+    /// Options for `navigator.credentials.create()` to add a passkey to `user_id`
+    pub async fn begin_registration(&self, user_id: i32) -> Result<CredentialCreationOptions> {
+        let user = User::find_by_id(user_id)
+            .one(&self.db)
+            .await?
+            .ok_or(AppError::UserNotFound)?;
+
+        let challenge = generate_opaque_token(CHALLENGE_BYTES);
+        self.pending
+            .put(&challenge, Ceremony::Registration { user_id })
+            .await;
+
+        Ok(CredentialCreationOptions {
+            public_key: PublicKeyCredentialCreationOptions {
+                rp: RelyingParty {
+                    id: self.rp_id.clone(),
+                    name: self.rp_name.clone(),
+                },
+                user: PublicKeyCredentialUser {
+                    id: user_handle(user.id),
+                    name: user.email.clone(),
+                    display_name: user.username.clone(),
+                },
+                challenge,
+                pub_key_cred_params: [COSE_ALG_ES256, COSE_ALG_RS256]
+                    .into_iter()
+                    .map(|alg| PublicKeyCredentialParameters {
+                        kind: "public-key".to_string(),
+                        alg,
+                    })
+                    .collect(),
+                timeout: CEREMONY_TIMEOUT.as_millis() as u64,
+                exclude_credentials: self.descriptors(user.id).await?,
+                authenticator_selection: AuthenticatorSelection {
+                    resident_key: "required".to_string(),
+                    user_verification: "required".to_string(),
+                },
+                attestation: "none".to_string(),
+            },
+        })
+    }
+
+    /// Verify the authenticator's response and store the new passkey
+    pub async fn finish_registration(
+        &self,
+        user_id: i32,
+        req: RegisterPasskeyRequest,
+    ) -> Result<PasskeyResponse> {
+        let credential = req.credential;
+        if credential.kind != "public-key" {
+            return Err(registration_error("Unsupported credential type"));
+        }
+
+        let ceremony = self
+            .verify_client_data(&credential.response.client_data_json, "webauthn.create")
+            .await
+            .map_err(registration_error)?;
+        if !matches!(ceremony, Ceremony::Registration { user_id: id } if id == user_id) {
+            return Err(registration_error(
+                "Challenge was not issued for this registration",
+            ));
+        }
+
+        let attestation: Value = decode_b64(&credential.response.attestation_object)
+            .and_then(|bytes| ciborium::from_reader(bytes.as_slice()).ok())
+            .ok_or_else(|| registration_error("Malformed attestation object"))?;
+        let auth_data = cbor_field(&attestation, "authData")
+            .and_then(Value::as_bytes)
+            .and_then(|bytes| AuthenticatorData::parse(bytes))
+            .ok_or_else(|| registration_error("Malformed authenticator data"))?;
+
+        // The attestation statement is not checked: we do not restrict authenticator models
+        self.check_authenticator_data(&auth_data)
+            .map_err(registration_error)?;
+        let attested = auth_data
+            .attested_credential
+            .ok_or_else(|| registration_error("Missing attested credential data"))?;
+
+        if decode_b64(&credential.raw_id).as_ref() != Some(&attested.credential_id) {
+            return Err(registration_error("Credential ID mismatch"));
+        }
+        if CoseKey::parse(&attested.public_key).is_none() {
+            return Err(registration_error("Unsupported public key algorithm"));
+        }
+
+        let credential_id = URL_SAFE_NO_PAD.encode(&attested.credential_id);
+        let existing = WebauthnCredential::find()
+            .filter(webauthn_credential::Column::CredentialId.eq(&credential_id))
+            .one(&self.db)
+            .await?;
+        if existing.is_some() {
+            return Err(registration_error("Passkey is already registered"));
+        }
+
+        let name = req
+            .name
+            .map(|name| name.trim().chars().take(100).collect::<String>())
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| "Passkey".to_string());
+
+        let stored = webauthn_credential::ActiveModel {
+            user_id: Set(user_id),
+            credential_id: Set(credential_id),
+            public_key: Set(attested.public_key),
+            sign_count: Set(auth_data.sign_count as i64),
+            aaguid: Set(attested.aaguid),
+            name: Set(name),
+            created_at: Set(Utc::now().naive_utc()),
+            ..Default::default()
+        }
+        .insert(&self.db)
+        .await?;
+
+        tracing::info!("User {} registered passkey {}", user_id, stored.id);
+
+        Ok(stored.into())
+    }
+
+    /// Options for `navigator.credentials.get()`.
+    ///
+    /// With an email, the login is limited to that account's passkeys. Credentials are never
+    /// listed in the options, so the response is the same for every address and accounts
+    /// cannot be enumerated; the authenticator offers its discoverable credentials instead.
+    pub async fn begin_authentication(
+        &self,
+        email: Option<&str>,
+    ) -> Result<CredentialRequestOptions> {
+        let user = match email {
+            Some(email) => {
+                User::find()
+                    .filter(user::email_is(email))
+                    .one(&self.db)
+                    .await?
+            }
+            None => None,
+        };
+
+        let challenge = generate_opaque_token(CHALLENGE_BYTES);
+        self.pending
+            .put(
+                &challenge,
+                Ceremony::Authentication {
+                    user_id: user.map(|user| user.id),
+                },
+            )
+            .await;
+
+        Ok(CredentialRequestOptions {
+            public_key: PublicKeyCredentialRequestOptions {
+                challenge,
+                rp_id: self.rp_id.clone(),
+                timeout: CEREMONY_TIMEOUT.as_millis() as u64,
+                allow_credentials: Vec::new(),
+                user_verification: "required".to_string(),
+            },
+        })
+    }
+
+    /// Verify an assertion and return the user it authenticates
+    pub async fn finish_authentication(&self, credential: AuthenticationCredential) -> Result<i32> {
+        self.verify_assertion(credential).await.map_err(|reason| {
+            tracing::warn!("Passkey login rejected: {}", reason);
+            AppError::AuthError("Passkey authentication failed".to_string())
+        })
+    }
+
+    /// List the passkeys of `user_id`, newest first
+    pub async fn list(&self, user_id: i32) -> Result<Vec<PasskeyResponse>> {
+        let credentials = WebauthnCredential::find()
+            .filter(webauthn_credential::Column::UserId.eq(user_id))
+            .order_by_desc(webauthn_credential::Column::CreatedAt)
+            .all(&self.db)
+            .await?;
+
+        Ok(credentials.into_iter().map(Into::into).collect())
+    }
+
+    pub async fn remove(&self, user_id: i32, id: i32) -> Result<()> {
+        WebauthnCredential::delete_many()
+            .filter(webauthn_credential::Column::Id.eq(id))
+            .filter(webauthn_credential::Column::UserId.eq(user_id))
+            .exec(&self.db)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn verify_assertion(
+        &self,
+        credential: AuthenticationCredential,
+    ) -> std::result::Result<i32, String> {
+        if credential.kind != "public-key" {
+            return Err("unsupported credential type".to_string());
+        }
+
+        let response = credential.response;
+        let Ceremony::Authentication {
+            user_id: expected_user,
+        } = self
+            .verify_client_data(&response.client_data_json, "webauthn.get")
+            .await?
+        else {
+            return Err("challenge was not issued for a login".to_string());
+        };
+
+        let credential_id = decode_b64(&credential.raw_id)
+            .map(|raw| URL_SAFE_NO_PAD.encode(raw))
+            .ok_or("malformed credential ID")?;
+        let stored = WebauthnCredential::find()
+            .filter(webauthn_credential::Column::CredentialId.eq(&credential_id))
+            .one(&self.db)
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or("unknown credential")?;
+
+        if expected_user.is_some_and(|user_id| user_id != stored.user_id) {
+            return Err("credential belongs to a different account".to_string());
+        }
+        if let Some(handle) = &response.user_handle {
+            if *handle != user_handle(stored.user_id) {
+                return Err("user handle mismatch".to_string());
+            }
+        }
+
+        let auth_data_bytes =
+            decode_b64(&response.authenticator_data).ok_or("malformed authenticator data")?;
+        let auth_data =
+            AuthenticatorData::parse(&auth_data_bytes).ok_or("malformed authenticator data")?;
+        self.check_authenticator_data(&auth_data)?;
+
+        // The signature covers authenticatorData || SHA-256(clientDataJSON)
+        let client_data = decode_b64(&response.client_data_json).ok_or("malformed client data")?;
+        let mut signed = auth_data_bytes;
+        signed.extend_from_slice(&Sha256::digest(&client_data));
+
+        let signature = decode_b64(&response.signature).ok_or("malformed signature")?;
+        let key = CoseKey::parse(&stored.public_key).ok_or("unusable stored public key")?;
+        if !key.verify(&signed, &signature) {
+            return Err("invalid signature".to_string());
+        }
+
+        self.record_use(&stored, auth_data.sign_count).await?;
+
+        Ok(stored.user_id)
+    }
+
+    /// Advance the signature counter. A counter that does not increase means the
+    /// credential may have been cloned, so the login is refused.
+    async fn record_use(
+        &self,
+        credential: &webauthn_credential::Model,
+        sign_count: u32,
+    ) -> std::result::Result<(), String> {
+        let sign_count = sign_count as i64;
+
+        let mut update = WebauthnCredential::update_many()
+            .col_expr(
+                webauthn_credential::Column::SignCount,
+                Expr::value(sign_count),
+            )
+            .col_expr(
+                webauthn_credential::Column::LastUsedAt,
+                Expr::value(Utc::now().naive_utc()),
+            )
+            .filter(webauthn_credential::Column::Id.eq(credential.id));
+
+        // Authenticators without a counter always report 0
+        update = if sign_count == 0 {
+            update.filter(webauthn_credential::Column::SignCount.eq(0))
+        } else {
+            update.filter(webauthn_credential::Column::SignCount.lt(sign_count))
+        };
+
+        let result = update.exec(&self.db).await.map_err(|e| e.to_string())?;
+        if result.rows_affected != 1 {
+            return Err(format!(
+                "signature counter did not increase for passkey {} (stored {}, got {}); \
+                 the authenticator may be cloned",
+                credential.id, credential.sign_count, sign_count
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Check `clientDataJSON` and consume the challenge it answers
+    async fn verify_client_data(
+        &self,
+        client_data_json: &str,
+        expected_type: &str,
+    ) -> std::result::Result<Ceremony, String> {
+        let client_data: ClientData = decode_b64(client_data_json)
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .ok_or("malformed client data")?;
+
+        let ceremony = self
+            .pending
+            .take(&client_data.challenge)
+            .await
+            .ok_or("unknown or expired challenge")?;
+
+        if client_data.kind != expected_type {
+            return Err(format!(
+                "unexpected client data type '{}'",
+                client_data.kind
+            ));
+        }
+        if client_data.cross_origin {
+            return Err("cross-origin ceremonies are not allowed".to_string());
+        }
+        if !self.origins.contains(&client_data.origin) {
+            return Err(format!("origin '{}' is not allowed", client_data.origin));
+        }
+
+        Ok(ceremony)
+    }
+
+    fn check_authenticator_data(
+        &self,
+        auth_data: &AuthenticatorData,
+    ) -> std::result::Result<(), String> {
+        if auth_data.rp_id_hash[..] != Sha256::digest(self.rp_id.as_bytes())[..] {
+            return Err("relying party ID mismatch".to_string());
+        }
+        if auth_data.flags & FLAG_USER_PRESENT == 0 {
+            return Err("user presence flag not set".to_string());
+        }
+        if auth_data.flags & FLAG_USER_VERIFIED == 0 {
+            return Err("user verification flag not set".to_string());
+        }
+        Ok(())
+    }
+
+    async fn descriptors(&self, user_id: i32) -> Result<Vec<PublicKeyCredentialDescriptor>> {
+        let credentials = WebauthnCredential::find()
+            .filter(webauthn_credential::Column::UserId.eq(user_id))
+            .all(&self.db)
+            .await?;
+
+        Ok(credentials
+            .into_iter()
+            .map(|credential| PublicKeyCredentialDescriptor {
+                kind: "public-key".to_string(),
+                id: credential.credential_id,
+            })
+            .collect())
+    }
+}
+
+/// Parsed authenticator data (WebAuthn §6.1)
+struct AuthenticatorData {
+    rp_id_hash: [u8; 32],
+    flags: u8,
+    sign_count: u32,
+    attested_credential: Option<AttestedCredential>,
+}
+
+struct AttestedCredential {
+    aaguid: Uuid,
+    credential_id: Vec<u8>,
+    /// COSE_Key bytes exactly as sent by the authenticator
+    public_key: Vec<u8>,
+}
+
+impl AuthenticatorData {
+    fn parse(bytes: &[u8]) -> Option<Self> {
+        let rp_id_hash = bytes.get(..32)?.try_into().ok()?;
+        let flags = *bytes.get(32)?;
+        let sign_count = u32::from_be_bytes(bytes.get(33..37)?.try_into().ok()?);
+
+        let attested_credential = if flags & FLAG_ATTESTED_CREDENTIAL != 0 {
+            let aaguid = Uuid::from_slice(bytes.get(37..53)?).ok()?;
+            let id_len = u16::from_be_bytes(bytes.get(53..55)?.try_into().ok()?) as usize;
+            let credential_id = bytes.get(55..55 + id_len)?.to_vec();
+
+            // The key is followed by optional extensions, so find where its CBOR item ends
+            let key_start = &bytes[55 + id_len..];
+            let mut rest = key_start;
+            let _: Value = ciborium::from_reader(&mut rest).ok()?;
+            let public_key = key_start[..key_start.len() - rest.len()].to_vec();
+
+            Some(AttestedCredential {
+                aaguid,
+                credential_id,
+                public_key,
+            })
+        } else {
+            None
+        };
+
+        Some(Self {
+            rp_id_hash,
+            flags,
+            sign_count,
+            attested_credential,
+        })
+    }
+}
+
+/// A credential public key decoded from its COSE_Key form (RFC 9053)
+enum CoseKey {
+    Es256(p256::ecdsa::VerifyingKey),
+    Rs256(rsa::pkcs1v15::VerifyingKey<Sha256>),
+}
+
+impl CoseKey {
+    fn parse(bytes: &[u8]) -> Option<Self> {
+        let key: Value = ciborium::from_reader(bytes).ok()?;
+        let key = key.as_map()?;
+        let param = |label: i64| {
+            key.iter()
+                .find(|(k, _)| k.as_integer().map(i128::from) == Some(label.into()))
+                .map(|(_, v)| v)
+        };
+        let int_param = |label: i64| param(label)?.as_integer().map(i128::from);
+        let bytes_param = |label: i64| param(label)?.as_bytes();
+
+        match int_param(3)?.try_into().ok()? {
+            // kty EC2, crv P-256, x and y coordinates
+            COSE_ALG_ES256 if int_param(1)? == 2 && int_param(-1)? == 1 => {
+                let (x, y) = (bytes_param(-2)?, bytes_param(-3)?);
+                if x.len() != 32 || y.len() != 32 {
+                    return None;
+                }
+                let point = p256::EncodedPoint::from_affine_coordinates(
+                    x.as_slice().into(),
+                    y.as_slice().into(),
+                    false,
+                );
+                p256::ecdsa::VerifyingKey::from_encoded_point(&point)
+                    .ok()
+                    .map(CoseKey::Es256)
+            }
+            // kty RSA, modulus and exponent
+            COSE_ALG_RS256 if int_param(1)? == 3 => {
+                let public_key = rsa::RsaPublicKey::new(
+                    rsa::BigUint::from_bytes_be(bytes_param(-1)?),
+                    rsa::BigUint::from_bytes_be(bytes_param(-2)?),
+                )
+                .ok()?;
+                Some(CoseKey::Rs256(rsa::pkcs1v15::VerifyingKey::new(public_key)))
+            }
+            _ => None,
+        }
+    }
+
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        match self {
+            // WebAuthn ECDSA signatures are ASN.1 DER encoded
+            CoseKey::Es256(key) => p256::ecdsa::Signature::from_der(signature)
+                .is_ok_and(|signature| key.verify(message, &signature).is_ok()),
+            CoseKey::Rs256(key) => rsa::pkcs1v15::Signature::try_from(signature)
+                .is_ok_and(|signature| key.verify(message, &signature).is_ok()),
+        }
+    }
+}
+
+/// Opaque WebAuthn user handle for a user
+fn user_handle(user_id: i32) -> String {
+    URL_SAFE_NO_PAD.encode(user_id.to_string())
+}
+
+/// Decode base64url, tolerating padding some clients add
+fn decode_b64(value: &str) -> Option<Vec<u8>> {
+    URL_SAFE_NO_PAD.decode(value.trim_end_matches('=')).ok()
+}
+
+fn cbor_field<'a>(map: &'a Value, name: &str) -> Option<&'a Value> {
+    map.as_map()?
+        .iter()
+        .find(|(k, _)| k.as_text() == Some(name))
+        .map(|(_, v)| v)
+}
+
+fn registration_error(reason: impl std::fmt::Display) -> AppError {
+    AppError::BadRequest(format!("Passkey registration failed: {}", reason))
+}
@@ -1,26 +1,96 @@
+use crate::config::ClientIpSource;
 use crate::errors::AppError;
+use crate::models::api_key::Scope;
+use crate::models::user::{self, Entity as User, UserRole};
+use crate::services::api_key_service::{ApiKeyService, API_KEY_PREFIX};
 use crate::services::jwt_service::{Claims, JwtService};
+use crate::services::revocation_service::RevocationService;
 use axum::{
     async_trait,
-    extract::{FromRef, FromRequestParts},
-    http::request::Parts,
+    extract::{ConnectInfo, FromRef, FromRequestParts},
+    http::{header, request::Parts},
     response::{IntoResponse, Response},
 };
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rand::{rngs::OsRng, RngCore};
+use sea_orm::{DatabaseConnection, EntityTrait, QuerySelect};
+use sha2::{Digest, Sha256};
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 
-// Implement FromRequestParts for Claims to extract JWT from Authorization header
+/// Generate an unguessable, URL-safe opaque token from `bytes` bytes of OS randomness
+pub fn generate_opaque_token(bytes: usize) -> String {
+    let mut buf = vec![0u8; bytes];
+    OsRng.fill_bytes(&mut buf);
+    URL_SAFE_NO_PAD.encode(buf)
+}
+
+/// Hash an opaque token for storage; tokens are high-entropy so a plain SHA-256 is enough
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Authenticate a bearer credential: a session JWT or an API key.
+///
+/// API keys are only accepted where the endpoint declares the scope it needs
+/// (`required_scope`) and the key grants it; everywhere else they are refused.
+pub async fn authenticate<S>(
+    token: &str,
+    required_scope: Option<Scope>,
+    state: &S,
+) -> Result<Claims, AppError>
+where
+    Arc<JwtService>: FromRef<S>,
+    Arc<RevocationService>: FromRef<S>,
+    Arc<ApiKeyService>: FromRef<S>,
+{
+    if token.starts_with(API_KEY_PREFIX) {
+        let api_key_service: Arc<ApiKeyService> = Arc::from_ref(state);
+        let claims = api_key_service.authenticate(token).await?;
+
+        return match required_scope {
+            None => Err(AppError::Forbidden(
+                "API keys cannot be used for this endpoint".to_string(),
+            )),
+            Some(scope) if !claims.allows(scope) => Err(AppError::Forbidden(format!(
+                "API key is missing the {} scope",
+                scope
+            ))),
+            Some(_) => Ok(claims),
+        };
+    }
+
+    // Verify token
+    let jwt_service: Arc<JwtService> = Arc::from_ref(state);
+    let claims = jwt_service.verify_token(token)?;
+
+    let revocation_service: Arc<RevocationService> = Arc::from_ref(state);
+    if revocation_service.is_disabled(claims.user_id()?).await? {
+        return Err(AppError::AccountDisabled);
+    }
+
+    // Reject tokens revoked by logout before they expire
+    if revocation_service.is_revoked(&claims).await? {
+        return Err(AppError::TokenRevoked);
+    }
+
+    Ok(claims)
+}
+
+// Implement FromRequestParts for Claims to extract a JWT or API key from the Authorization header.
+// Routes that accept API keys attach the scope they need as a request extension.
 #[async_trait]
 impl<S> FromRequestParts<S> for Claims
 where
     S: Send + Sync,
     Arc<JwtService>: FromRef<S>,
+    Arc<RevocationService>: FromRef<S>,
+    Arc<ApiKeyService>: FromRef<S>,
 {
     type Rejection = Response;
 
     async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
-        // Extract JWT service from state
-        let jwt_service: Arc<JwtService> = Arc::from_ref(state);
-
         // Get authorization header
         let auth_header = parts
             .headers
@@ -33,9 +103,97 @@ where
             .strip_prefix("Bearer ")
             .ok_or_else(|| AppError::AuthError("Invalid authorization format".to_string()).into_response())?;
 
-        // Verify token
-        jwt_service
-            .verify_token(token)
-            .map_err(|e| e.into_response())
+        let required_scope = parts.extensions.get::<Scope>().copied();
+
+        authenticate(token, required_scope, state)
+            .await
+            .map_err(IntoResponse::into_response)
+    }
+}
+
+/// A signed-in admin. Extracting it refuses everyone else, so admin handlers take it as their
+/// guard; the role in the token is confirmed with the database in case it was revoked since.
+#[derive(Debug, Clone)]
+pub struct Admin(pub Claims);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Admin
+where
+    S: Send + Sync,
+    Arc<JwtService>: FromRef<S>,
+    Arc<RevocationService>: FromRef<S>,
+    Arc<ApiKeyService>: FromRef<S>,
+    Arc<DatabaseConnection>: FromRef<S>,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let claims = Claims::from_request_parts(parts, state).await?;
+        let forbidden = || AppError::Forbidden("Admin access required".to_string()).into_response();
+
+        if !claims.is_admin() {
+            return Err(forbidden());
+        }
+
+        let user_id = claims.user_id().map_err(IntoResponse::into_response)?;
+        let db = Arc::<DatabaseConnection>::from_ref(state);
+        let role: Option<UserRole> = User::find_by_id(user_id)
+            .select_only()
+            .column(user::Column::Role)
+            .into_tuple()
+            .one(db.as_ref())
+            .await
+            .map_err(|e| AppError::from(e).into_response())?;
+
+        if role != Some(UserRole::Admin) {
+            return Err(forbidden());
+        }
+
+        Ok(Self(claims))
+    }
+}
+
+/// Who is making a request, as far as the server can tell
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+    pub user_agent: Option<String>,
+    pub ip_address: Option<IpAddr>,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ClientInfo
+where
+    S: Send + Sync,
+    ClientIpSource: FromRef<S>,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let user_agent = parts
+            .headers
+            .get(header::USER_AGENT)
+            .and_then(|h| h.to_str().ok())
+            .map(str::to_string);
+
+        let ip_address = match ClientIpSource::from_ref(state) {
+            ClientIpSource::Peer => parts
+                .extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip()),
+            // Earlier entries are supplied by the client and cannot be trusted
+            ClientIpSource::XForwardedFor => parts
+                .headers
+                .get_all("X-Forwarded-For")
+                .iter()
+                .filter_map(|h| h.to_str().ok())
+                .flat_map(|h| h.split(','))
+                .last()
+                .and_then(|ip| ip.trim().parse().ok()),
+        };
+
+        Ok(Self {
+            user_agent,
+            ip_address,
+        })
     }
 }
@@ -0,0 +1,262 @@
+use crate::config::{Config, PasswordPolicy};
+use crate::errors::AppError;
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequest, Request},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// Longest email address accepted (RFC 5321 path limit)
+const MAX_EMAIL_LENGTH: usize = 254;
+const USERNAME_MIN_LENGTH: usize = 3;
+const USERNAME_MAX_LENGTH: usize = 32;
+/// Upper bound on any password, so hashing cannot be used to burn CPU
+pub const MAX_PASSWORD_LENGTH: usize = 128;
+
+/// Limits that depend on configuration
+#[derive(Clone, Debug)]
+pub struct ValidationRules {
+    pub password: PasswordPolicy,
+    /// Longest message content, in characters
+    pub max_message_length: usize,
+    /// Most people in a group DM, its creator included
+    pub max_group_dm_participants: usize,
+}
+
+impl ValidationRules {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            password: config.password_policy.clone(),
+            max_message_length: config.max_message_length,
+            max_group_dm_participants: config.max_group_dm_participants,
+        }
+    }
+}
+
+/// Problems found in a request, keyed by field name
+#[derive(Debug, Default, Serialize)]
+#[serde(transparent)]
+pub struct ValidationErrors(BTreeMap<String, Vec<String>>);
+
+impl ValidationErrors {
+    pub fn add(&mut self, field: &str, message: impl Into<String>) {
+        self.0
+            .entry(field.to_string())
+            .or_default()
+            .push(message.into());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fields: Vec<String> = self
+            .0
+            .iter()
+            .map(|(field, messages)| format!("{} {}", field, messages.join(", ")))
+            .collect();
+        write!(f, "{}", fields.join("; "))
+    }
+}
+
+impl From<ValidationErrors> for AppError {
+    fn from(errors: ValidationErrors) -> Self {
+        AppError::ValidationError(errors)
+    }
+}
+
+/// A request body that can check itself
+pub trait Validate {
+    fn validate(&self, rules: &ValidationRules) -> Result<(), ValidationErrors>;
+}
+
+/// Collects the problems of a request, one field at a time, e.g.
+/// `Validator::new().email("email", &req.email).finish()`
+#[derive(Default)]
+pub struct Validator {
+    errors: ValidationErrors,
+}
+
+impl Validator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record `message` for `field` unless `ok`
+    pub fn check(mut self, field: &str, ok: bool, message: &str) -> Self {
+        if !ok {
+            self.errors.add(field, message);
+        }
+        self
+    }
+
+    /// Non-blank and at most `max` characters. Surrounding whitespace counts towards the limit,
+    /// since not every field is stored trimmed.
+    pub fn length(self, field: &str, value: &str, max: usize) -> Self {
+        self.check(field, !value.trim().is_empty(), "must not be empty")
+            .check(
+                field,
+                value.chars().count() <= max,
+                &format!("must be at most {} characters", max),
+            )
+    }
+
+    /// At most `max` characters, if present
+    pub fn max_length(self, field: &str, value: Option<&str>, max: usize) -> Self {
+        self.check(
+            field,
+            value.is_none_or(|value| value.chars().count() <= max),
+            &format!("must be at most {} characters", max),
+        )
+    }
+
+    /// A deliverable email address
+    pub fn email(self, field: &str, value: &str) -> Self {
+        let value = value.trim();
+        let ok = value.len() <= MAX_EMAIL_LENGTH
+            && value
+                .split_once('@')
+                .is_some_and(|(_, domain)| domain.contains('.') && !domain.starts_with('.'))
+            // The same parser the mailer uses, so every accepted address can be mailed
+            && value.parse::<lettre::Address>().is_ok();
+        self.check(field, ok, "must be a valid email address")
+    }
+
+    /// An existing email address: only bounded, since it may predate the checks in `email`
+    pub fn email_input(self, field: &str, value: &str) -> Self {
+        self.check(
+            field,
+            value.trim().len() <= MAX_EMAIL_LENGTH,
+            &format!("must be at most {} characters", MAX_EMAIL_LENGTH),
+        )
+    }
+
+    /// Not a name given to deleted accounts
+    pub fn unreserved_username(self, field: &str, value: &str) -> Self {
+        self.check(field, !is_tombstone_username(value), "is reserved")
+    }
+
+    /// Letters, digits, `_`, `-` and `.`, starting with a letter or digit
+    pub fn username(self, field: &str, value: &str) -> Self {
+        let length = value.chars().count();
+        self.unreserved_username(field, value).check(
+            field,
+            (USERNAME_MIN_LENGTH..=USERNAME_MAX_LENGTH).contains(&length),
+            &format!(
+                "must be {}-{} characters",
+                USERNAME_MIN_LENGTH, USERNAME_MAX_LENGTH
+            ),
+        )
+        .check(
+            field,
+            value
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.')),
+            "may only contain letters, digits, '_', '-' and '.'",
+        )
+        .check(
+            field,
+            value
+                .chars()
+                .next()
+                .is_some_and(|c| c.is_ascii_alphanumeric()),
+            "must start with a letter or digit",
+        )
+    }
+
+    /// A new password, checked against the configured policy
+    pub fn password(self, field: &str, value: &str, policy: &PasswordPolicy) -> Self {
+        let length = value.chars().count();
+        self.check(
+            field,
+            length >= policy.min_length,
+            &format!("must be at least {} characters", policy.min_length),
+        )
+        .check(
+            field,
+            length <= MAX_PASSWORD_LENGTH,
+            &format!("must be at most {} characters", MAX_PASSWORD_LENGTH),
+        )
+        .check(
+            field,
+            !policy.require_lowercase || value.chars().any(char::is_lowercase),
+            "must contain a lowercase letter",
+        )
+        .check(
+            field,
+            !policy.require_uppercase || value.chars().any(char::is_uppercase),
+            "must contain an uppercase letter",
+        )
+        .check(
+            field,
+            !policy.require_digit || value.chars().any(|c| c.is_ascii_digit()),
+            "must contain a digit",
+        )
+        .check(
+            field,
+            !policy.require_symbol || value.chars().any(|c| !c.is_alphanumeric()),
+            "must contain a symbol",
+        )
+    }
+
+    /// An existing password: only bounded, since it may predate the current policy
+    pub fn password_input(self, field: &str, value: &str) -> Self {
+        self.check(field, !value.is_empty(), "must not be empty")
+            .check(
+                field,
+                value.chars().count() <= MAX_PASSWORD_LENGTH,
+                &format!("must be at most {} characters", MAX_PASSWORD_LENGTH),
+            )
+    }
+
+    pub fn finish(self) -> Result<(), ValidationErrors> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(self.errors)
+        }
+    }
+}
+
+/// Like `Json<T>`, but rejects bodies that fail `T::validate` with a `ValidationError`
+pub struct ValidatedJson<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for ValidatedJson<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+    Arc<ValidationRules>: FromRef<S>,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<T>::from_request(req, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+
+        let rules = Arc::<ValidationRules>::from_ref(state);
+        value
+            .validate(&rules)
+            .map_err(|errors| AppError::from(errors).into_response())?;
+
+        Ok(Self(value))
+    }
+}
+
+/// Deleted accounts are renamed `deleted-<id>`, so nobody may pick such a name
+pub fn is_tombstone_username(value: &str) -> bool {
+    value
+        .trim()
+        .to_ascii_lowercase()
+        .strip_prefix("deleted-")
+        .is_some_and(|id| !id.is_empty() && id.chars().all(|c| c.is_ascii_digit()))
+}
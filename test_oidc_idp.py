@@ -0,0 +1,119 @@
+#!/usr/bin/env python3
+"""
+Mock OpenID Connect identity provider for testing SSO locally
+Requires: pip install pyjwt cryptography
+
+Run it, then start the backend with:
+  OIDC_PROVIDERS=mock
+  OIDC_MOCK_ISSUER=http://localhost:9000
+  OIDC_MOCK_CLIENT_ID=chat-backend
+  OIDC_MOCK_CLIENT_SECRET=secret
+
+Open http://localhost:3000/auth/oidc/mock/start in a browser (or follow the
+redirects with curl -L). The mock IdP logs everybody in as the configured user
+without asking for credentials.
+"""
+
+import base64
+import hashlib
+import json
+import secrets
+import sys
+import time
+from http.server import BaseHTTPRequestHandler, HTTPServer
+from urllib.parse import parse_qs, urlencode, urlparse
+
+import jwt
+from cryptography.hazmat.primitives.asymmetric import rsa
+
+PORT = 9000
+ISSUER = f"http://localhost:{PORT}"
+CLIENT_ID = "chat-backend"
+KEY_ID = "mock-key-1"
+
+USER = {
+    "sub": "mock-user-1",
+    "email": sys.argv[1] if len(sys.argv) > 1 else "sso@example.com",
+    "email_verified": True,
+    "name": "SSO User",
+}
+
+private_key = rsa.generate_private_key(public_exponent=65537, key_size=2048)
+public_numbers = private_key.public_key().public_numbers()
+
+# Authorization codes waiting to be exchanged: code -> (nonce, code_challenge)
+pending_codes = {}
+
+
+def b64url_uint(value):
+    raw = value.to_bytes((value.bit_length() + 7) // 8, "big")
+    return base64.urlsafe_b64encode(raw).rstrip(b"=").decode()
+
+
+class Handler(BaseHTTPRequestHandler):
+    def send_json(self, status, body):
+        payload = json.dumps(body).encode()
+        self.send_response(status)
+        self.send_header("Content-Type", "application/json")
+        self.send_header("Content-Length", str(len(payload)))
+        self.end_headers()
+        self.wfile.write(payload)
+
+    def do_GET(self):
+        url = urlparse(self.path)
+        query = {k: v[0] for k, v in parse_qs(url.query).items()}
+
+        if url.path == "/.well-known/openid-configuration":
+            self.send_json(200, {
+                "issuer": ISSUER,
+                "authorization_endpoint": f"{ISSUER}/authorize",
+                "token_endpoint": f"{ISSUER}/token",
+                "jwks_uri": f"{ISSUER}/jwks",
+            })
+        elif url.path == "/jwks":
+            self.send_json(200, {"keys": [{
+                "kty": "RSA",
+                "use": "sig",
+                "alg": "RS256",
+                "kid": KEY_ID,
+                "n": b64url_uint(public_numbers.n),
+                "e": b64url_uint(public_numbers.e),
+            }]})
+        elif url.path == "/authorize":
+            code = secrets.token_urlsafe(16)
+            pending_codes[code] = (query.get("nonce"), query.get("code_challenge"))
+            redirect = f"{query['redirect_uri']}?{urlencode({'code': code, 'state': query['state']})}"
+            self.send_response(302)
+            self.send_header("Location", redirect)
+            self.end_headers()
+        else:
+            self.send_json(404, {"error": "not_found"})
+
+    def do_POST(self):
+        if urlparse(self.path).path != "/token":
+            self.send_json(404, {"error": "not_found"})
+            return
+
+        length = int(self.headers.get("Content-Length", 0))
+        form = {k: v[0] for k, v in parse_qs(self.rfile.read(length).decode()).items()}
+
+        nonce, challenge = pending_codes.pop(form.get("code"), (None, None))
+        verifier = form.get("code_verifier", "")
+        expected = base64.urlsafe_b64encode(hashlib.sha256(verifier.encode()).digest()).rstrip(b"=").decode()
+        if challenge is None or expected != challenge:
+            self.send_json(400, {"error": "invalid_grant"})
+            return
+
+        now = int(time.time())
+        id_token = jwt.encode(
+            {**USER, "iss": ISSUER, "aud": CLIENT_ID, "iat": now, "exp": now + 300, "nonce": nonce},
+            private_key,
+            algorithm="RS256",
+            headers={"kid": KEY_ID},
+        )
+        self.send_json(200, {"access_token": secrets.token_urlsafe(16), "token_type": "Bearer", "id_token": id_token})
+
+
+if __name__ == "__main__":
+    print(f"Mock OIDC provider listening on {ISSUER} (user: {USER['email']})")
+    HTTPServer(("0.0.0.0", PORT), Handler).serve_forever()
@@ -0,0 +1,169 @@
+#!/usr/bin/env python3
+"""
+Software passkey authenticator for testing WebAuthn login
+Requires: pip install cryptography
+
+Usage: python test_passkey.py EMAIL PASSWORD [ORIGIN]
+
+Signs in with the password, registers a new ES256 passkey, then signs in again with
+the passkey only. ORIGIN must be one of WEBAUTHN_ORIGINS (default: http://localhost:5173,
+the APP_BASE_URL in the example env files).
+"""
+
+import base64
+import hashlib
+import json
+import os
+import struct
+import sys
+import urllib.error
+import urllib.request
+
+from cryptography.hazmat.primitives import hashes
+from cryptography.hazmat.primitives.asymmetric import ec
+
+BASE_URL = "http://localhost:3000"
+ORIGIN = sys.argv[3] if len(sys.argv) > 3 else "http://localhost:5173"
+
+
+def b64url(data):
+    return base64.urlsafe_b64encode(data).rstrip(b"=").decode()
+
+
+def cbor(value):
+    """Minimal CBOR encoder for the types WebAuthn needs"""
+    def head(major, length):
+        if length < 24:
+            return bytes([major << 5 | length])
+        if length < 0x100:
+            return bytes([major << 5 | 24, length])
+        if length < 0x10000:
+            return bytes([major << 5 | 25]) + struct.pack(">H", length)
+        return bytes([major << 5 | 26]) + struct.pack(">I", length)
+
+    if isinstance(value, int):
+        return head(0, value) if value >= 0 else head(1, -1 - value)
+    if isinstance(value, bytes):
+        return head(2, len(value)) + value
+    if isinstance(value, str):
+        encoded = value.encode()
+        return head(3, len(encoded)) + encoded
+    if isinstance(value, dict):
+        return head(5, len(value)) + b"".join(cbor(k) + cbor(v) for k, v in value.items())
+    raise TypeError(f"cannot encode {type(value)}")
+
+
+def request(method, path, body=None, token=None):
+    req = urllib.request.Request(f"{BASE_URL}{path}", method=method)
+    req.add_header("Content-Type", "application/json")
+    if token:
+        req.add_header("Authorization", f"Bearer {token}")
+    data = json.dumps(body).encode() if body is not None else None
+    try:
+        with urllib.request.urlopen(req, data) as response:
+            return json.loads(response.read() or "null")
+    except urllib.error.HTTPError as e:
+        sys.exit(f"{method} {path} failed with {e.code}: {e.read().decode()}")
+
+
+class SoftwareAuthenticator:
+    # Flags: user present, user verified (and attested credential data on registration)
+    FLAGS_UP_UV = 0x01 | 0x04
+    FLAG_AT = 0x40
+
+    def __init__(self):
+        self.credential_id = os.urandom(16)
+        self.private_key = ec.generate_private_key(ec.SECP256R1())
+        self.user_handle = None
+        self.sign_count = 0
+
+    def cose_public_key(self):
+        numbers = self.private_key.public_key().public_numbers()
+        return cbor({
+            1: 2,    # kty: EC2
+            3: -7,   # alg: ES256
+            -1: 1,   # crv: P-256
+            -2: numbers.x.to_bytes(32, "big"),
+            -3: numbers.y.to_bytes(32, "big"),
+        })
+
+    def client_data(self, kind, challenge):
+        return json.dumps({"type": kind, "challenge": challenge, "origin": ORIGIN}).encode()
+
+    def create(self, options):
+        rp_id_hash = hashlib.sha256(options["rp"]["id"].encode()).digest()
+        self.user_handle = options["user"]["id"]
+        self.sign_count += 1
+
+        attested = (
+            bytes(16)  # AAGUID
+            + struct.pack(">H", len(self.credential_id))
+            + self.credential_id
+            + self.cose_public_key()
+        )
+        auth_data = rp_id_hash + bytes([self.FLAGS_UP_UV | self.FLAG_AT]) + struct.pack(">I", self.sign_count) + attested
+
+        return {
+            "id": b64url(self.credential_id),
+            "rawId": b64url(self.credential_id),
+            "type": "public-key",
+            "response": {
+                "clientDataJSON": b64url(self.client_data("webauthn.create", options["challenge"])),
+                "attestationObject": b64url(cbor({"fmt": "none", "attStmt": {}, "authData": auth_data})),
+            },
+        }
+
+    def get(self, options):
+        allowed = [c["id"] for c in options["allowCredentials"]]
+        if allowed and b64url(self.credential_id) not in allowed:
+            sys.exit("No matching credential")
+
+        rp_id_hash = hashlib.sha256(options["rpId"].encode()).digest()
+        self.sign_count += 1
+        auth_data = rp_id_hash + bytes([self.FLAGS_UP_UV]) + struct.pack(">I", self.sign_count)
+        client_data = self.client_data("webauthn.get", options["challenge"])
+
+        signature = self.private_key.sign(
+            auth_data + hashlib.sha256(client_data).digest(),
+            ec.ECDSA(hashes.SHA256()),
+        )
+
+        return {
+            "id": b64url(self.credential_id),
+            "rawId": b64url(self.credential_id),
+            "type": "public-key",
+            "response": {
+                "clientDataJSON": b64url(client_data),
+                "authenticatorData": b64url(auth_data),
+                "signature": b64url(signature),
+                "userHandle": self.user_handle,
+            },
+        }
+
+
+def main():
+    if len(sys.argv) < 3:
+        sys.exit(__doc__)
+    email, password = sys.argv[1], sys.argv[2]
+
+    login = request("POST", "/auth/login", {"email": email, "password": password})
+    if "token" not in login:
+        sys.exit(f"Password login did not return a token: {login}")
+    token = login["token"]
+    print(f"Signed in with password as user {login['user']['id']}")
+
+    authenticator = SoftwareAuthenticator()
+
+    options = request("POST", "/me/passkeys/register/start", token=token)["publicKey"]
+    credential = authenticator.create(options)
+    passkey = request("POST", "/me/passkeys/register/finish", {"name": "Test authenticator", "credential": credential}, token)
+    print(f"Registered passkey: {passkey}")
+
+    options = request("POST", "/auth/passkey/start", {})["publicKey"]
+    assertion = authenticator.get(options)
+    response = request("POST", "/auth/passkey/finish", {"credential": assertion})
+    print(f"Signed in with passkey as user {response['user']['id']}")
+
+
+if __name__ == "__main__":
+    main()